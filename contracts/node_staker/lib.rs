@@ -13,6 +13,9 @@ mod node_staker {
     pub const STAKE_DURATION_MILIS: Timestamp = 1000 * 60 * 60 * 24 * 30;
     pub const BASE_SLASH: Balance = 2;
     pub const SLASH_FACTOR: u32 = 1;
+    pub const MAX_ENDPOINTS: usize = 8;
+    /// Placement weight of an unslashed stake, slashes lower it proportionally.
+    pub const FULL_STAKE_WEIGHT: u32 = 16;
 
     #[derive(scale::Decode, scale::Encode, Clone, Copy)]
     #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
@@ -21,16 +24,34 @@ mod node_staker {
         Ip6([u8; 16 + 2]),
     }
 
+    #[derive(scale::Decode, scale::Encode, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+    pub enum Transport {
+        Tcp,
+        Ws,
+        Quic,
+    }
+
+    /// Address the node accepts connections on, clients pick the ones their transport supports.
+    #[derive(scale::Decode, scale::Encode, Clone, Copy)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+    pub struct Endpoint {
+        pub transport: Transport,
+        pub addr: NodeAddress,
+    }
+
     #[ink(event)]
     pub struct Joined {
         pub identity: Ed,
-        pub addr: NodeAddress,
+        pub sign: CryptoHash,
+        pub endpoints: Vec<Endpoint>,
+        pub weight: u32,
     }
 
     #[ink(event)]
     pub struct AddrChanged {
         pub identity: Ed,
-        pub addr: NodeAddress,
+        pub endpoints: Vec<Endpoint>,
     }
 
     #[ink(event)]
@@ -66,6 +87,31 @@ mod node_staker {
         pub id: Ed,
     }
 
+    /// Parameters every node in the network has to agree on.
+    #[derive(scale::Decode, scale::Encode, Clone, Copy)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
+    pub struct NetworkParams {
+        pub replication_factor: u8,
+        pub block_size: u32,
+        pub block_history: u32,
+        pub max_message_size: u32,
+        /// Virtual points per node seeded by [`NodeStaker::beacon_block`], zero places topics
+        /// by node key distance.
+        pub virtual_nodes: u32,
+    }
+
+    impl Default for NetworkParams {
+        fn default() -> Self {
+            Self {
+                replication_factor: 4,
+                block_size: 1024 * 32,
+                block_history: 32,
+                max_message_size: 1024,
+                virtual_nodes: 0,
+            }
+        }
+    }
+
     #[derive(scale::Decode, scale::Encode)]
     #[cfg_attr(feature = "std", derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout))]
     struct Stake {
@@ -74,7 +120,7 @@ mod node_staker {
         created_at: Timestamp,
         votes: Votes,
         id: Ed,
-        addr: NodeAddress,
+        endpoints: Vec<Endpoint>,
     }
 
     impl Stake {
@@ -86,6 +132,11 @@ mod node_staker {
                 self.amount
             }
         }
+
+        fn weight(&self) -> u32 {
+            let weight = self.apply_slashes() * Balance::from(FULL_STAKE_WEIGHT) / STAKE_AMOUNT;
+            u32::try_from(weight).unwrap_or(u32::MAX)
+        }
     }
 
     /// Defines the storage of your contract.
@@ -95,6 +146,8 @@ mod node_staker {
     pub struct NodeStaker {
         stakes: ink::storage::Mapping<NodeIdentity, Stake>,
         stake_list: Vec<NodeIdentity>,
+        params: NetworkParams,
+        created_at: BlockNumber,
     }
 
     impl Default for NodeStaker {
@@ -107,27 +160,52 @@ mod node_staker {
         /// Constructor that initializes the `bool` value to the given `init_value`.
         #[ink(constructor)]
         pub fn new() -> Self {
-            Self { stakes: ink::storage::Mapping::new(), stake_list: Vec::new() }
+            Self::with_params(NetworkParams::default())
+        }
+
+        #[ink(constructor)]
+        pub fn with_params(params: NetworkParams) -> Self {
+            assert!(params.replication_factor > 0, "replication factor must not be zero");
+            Self {
+                stakes: ink::storage::Mapping::new(),
+                stake_list: Vec::new(),
+                params,
+                created_at: Self::env().block_number(),
+            }
+        }
+
+        #[ink(message)]
+        pub fn params(&self) -> NetworkParams {
+            self.params
+        }
+
+        /// Hash of this block is the randomness beacon for virtual node placement, nobody
+        /// knows it before the network exists.
+        #[ink(message)]
+        pub fn beacon_block(&self) -> BlockNumber {
+            self.created_at
         }
 
         #[ink(message, payable)]
         #[allow(clippy::needless_pass_by_value)]
-        pub fn join(&mut self, data: NodeData, addr: NodeAddress) {
+        pub fn join(&mut self, data: NodeData, endpoints: Vec<Endpoint>) {
             let amount = self.env().transferred_value();
             assert!(amount == STAKE_AMOUNT, "wrong amount");
+            Self::check_endpoints(&endpoints);
             let stake = Stake {
                 amount,
                 owner: Self::env().caller(),
                 created_at: Self::env().block_timestamp(),
                 votes: Votes::default(),
                 id: data.id,
-                addr,
+                endpoints: endpoints.clone(),
             };
             let id = NodeIdentity { sign: data.sign, enc: data.enc };
             assert!(self.stakes.insert(id, &stake).is_none(), "already joined");
             self.stake_list.push(id);
 
-            self.env().emit_event(Joined { identity: data.id, addr });
+            let weight = stake.weight();
+            self.env().emit_event(Joined { identity: data.id, sign: data.sign, endpoints, weight });
         }
 
         #[ink(message)]
@@ -150,23 +228,44 @@ mod node_staker {
             self.stakes.insert(target, &target_stake);
         }
 
+        /// Nodes with their endpoints and placement weight derived from the stake.
         #[ink(message)]
-        pub fn list(&self) -> Vec<(NodeData, NodeAddress)> {
+        pub fn list(&self) -> Vec<(NodeData, Vec<Endpoint>, u32)> {
             self.stake_list
                 .iter()
                 .map(|id| {
                     let stake = self.stakes.get(id).unwrap();
-                    (NodeData { sign: id.sign, enc: id.enc, id: stake.id }, stake.addr)
+                    let weight = stake.weight();
+                    let data = NodeData { sign: id.sign, enc: id.enc, id: stake.id };
+                    (data, stake.endpoints, weight)
                 })
                 .collect()
         }
 
-        pub fn change_addr(&mut self, identity: NodeIdentity, addr: NodeAddress) {
+        /// Registration of the node with the `id` key, lets nodes that learn the network
+        /// through lookups check what they found without fetching the whole [`Self::list`].
+        #[ink(message)]
+        pub fn node(&self, id: Ed) -> Option<(NodeData, Vec<Endpoint>, u32)> {
+            self.stake_list.iter().find_map(|identity| {
+                let stake = self.stakes.get(identity).filter(|s| s.id == id)?;
+                let weight = stake.weight();
+                let data = NodeData { sign: identity.sign, enc: identity.enc, id: stake.id };
+                Some((data, stake.endpoints, weight))
+            })
+        }
+
+        pub fn change_addr(&mut self, identity: NodeIdentity, endpoints: Vec<Endpoint>) {
             let mut stake = self.stakes.get(identity).expect("not joined");
             assert!(stake.owner == self.env().caller(), "not owner");
-            stake.addr = addr;
+            Self::check_endpoints(&endpoints);
+            stake.endpoints = endpoints.clone();
             self.stakes.insert(identity, &stake);
-            self.env().emit_event(AddrChanged { identity: stake.id, addr });
+            self.env().emit_event(AddrChanged { identity: stake.id, endpoints });
+        }
+
+        fn check_endpoints(endpoints: &[Endpoint]) {
+            assert!(!endpoints.is_empty(), "no endpoints");
+            assert!(endpoints.len() <= MAX_ENDPOINTS, "too many endpoints");
         }
 
         #[ink(message)]
@@ -225,7 +324,7 @@ mod node_staker {
             ink_env::set_block_timestamp::<Env>(0);
             staker.join(
                 NodeData { sign: identity.sign, enc: identity.enc, id: Ed::default() },
-                NodeAddress::Ip4([0; 6]),
+                vec![Endpoint { transport: Transport::Tcp, addr: NodeAddress::Ip4([0; 6]) }],
             );
             ink_env::set_account_balance::<Env>(
                 ink_env::callee::<Env>(),
@@ -283,6 +382,28 @@ mod node_staker {
             join(&mut node_staker, STAKE_AMOUNT + 1, identity, alice);
         }
 
+        #[ink::test]
+        #[should_panic(expected = "no endpoints")]
+        fn join_no_endpoints() {
+            let mut node_staker = init_contract();
+            let [identity, ..] = identities();
+            let [alice, ..] = accounts();
+            ink_env::set_caller::<Env>(alice);
+            ink_env::set_value_transferred::<Env>(STAKE_AMOUNT);
+            node_staker.join(
+                NodeData { sign: identity.sign, enc: identity.enc, id: Ed::default() },
+                Vec::new(),
+            );
+        }
+
+        #[ink::test]
+        fn custom_params() {
+            ink_env::set_callee::<Env>(ink_env::default_accounts::<Env>().charlie);
+            let params = NetworkParams { replication_factor: 7, ..Default::default() };
+            let node_staker = NodeStaker::with_params(params);
+            assert_eq!(node_staker.params().replication_factor, 7);
+        }
+
         #[ink::test]
         fn tvote() {
             let mut node_staker = init_contract();
@@ -323,6 +444,18 @@ mod node_staker {
             vote(&mut node_staker, identity, target, 1, alice);
         }
 
+        #[ink::test]
+        fn slashes_lower_weight() {
+            let mut node_staker = init_contract();
+            let [identity, target] = identities();
+            let [alice, bob] = accounts();
+            join(&mut node_staker, STAKE_AMOUNT, identity, alice);
+            join(&mut node_staker, STAKE_AMOUNT, target, bob);
+            vote(&mut node_staker, identity, target, -1, alice);
+            let weights = node_staker.list().into_iter().map(|(.., w)| w).collect::<Vec<_>>();
+            assert_eq!(weights, [FULL_STAKE_WEIGHT, FULL_STAKE_WEIGHT - 1]);
+        }
+
         #[ink::test]
         fn treclaim() {
             let mut node_staker = init_contract();
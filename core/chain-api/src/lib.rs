@@ -11,7 +11,7 @@ use {
         runtime_types::pallet_contracts::primitives::{ContractResult, ExecReturnValue},
         subxt, subxt_signer, user_manager, Hash, InkMessage,
     },
-    futures::{StreamExt, TryFutureExt, TryStreamExt},
+    futures::{StreamExt, TryStreamExt},
     parity_scale_codec::{Decode, Encode as _},
     std::str::FromStr,
     subxt::{
@@ -34,13 +34,17 @@ pub type Keypair = subxt_signer::sr25519::Keypair;
 pub type Signature = subxt_signer::sr25519::Signature;
 pub type CallPayload = Payload<Call>;
 pub type NodeAddress = node_staker::NodeAddress;
+pub type NodeTransport = node_staker::Transport;
+pub type NodeEndpoint = node_staker::Endpoint;
 pub type StakeEvent = node_staker::Event;
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub type Nonce = u64;
+pub type BlockNumber = u64;
 pub type RawUserName = [u8; USER_NAME_CAP];
 pub type UserIdentity = user_manager::Profile;
 pub type NodeIdentity = node_staker::NodeIdentity;
 pub type NodeData = node_staker::NodeData;
+pub type NetworkParams = node_staker::NetworkParams;
 
 #[must_use]
 pub fn immortal_era() -> String {
@@ -173,37 +177,57 @@ pub struct Client<S: TransactionHandler> {
 }
 
 impl<S: TransactionHandler> Client<S> {
+    /// Yields contract events of each finalized block. When `after` is specified, blocks
+    /// finalized since then are fetched first so that no event is missed.
     pub async fn node_contract_event_stream(
         &self,
         contract: ContractId,
-    ) -> Result<impl futures::Stream<Item = Result<StakeEvent>>, Error> {
-        Ok(self
-            .inner
-            .client
-            .blocks()
-            .subscribe_finalized()
-            .await?
-            .then(move |block| {
-                let contract = contract.clone();
-                async move {
-                    Ok::<_, Error>(
-                        block?
-                            .events()
-                            .await?
-                            .find::<polkadot::contracts::events::ContractEmitted>()
-                            .filter_map(Result::ok)
-                            .filter(move |event| event.contract == contract)
-                            .map(|event| {
-                                StakeEvent::decode(&mut event.data.as_slice()).map_err(|e| {
-                                    Error::Decode(subxt::error::DecodeError::custom(e))
-                                })
-                            })
-                            .collect::<Vec<_>>(),
-                    )
-                }
-                .map_ok(futures::stream::iter)
-            })
-            .try_flatten())
+        after: Option<BlockNumber>,
+    ) -> Result<impl futures::Stream<Item = Result<(BlockNumber, Vec<Result<StakeEvent>>)>>> {
+        let live = self.inner.client.blocks().subscribe_finalized().await?;
+
+        let head = match after {
+            Some(_) => {
+                let head = self.inner.legacy.chain_get_finalized_head().await?;
+                self.inner.client.blocks().at(head).await?.number().into()
+            }
+            None => 0,
+        };
+        let missed = after.map_or(1..=0, |after| after + 1..=head);
+
+        let inner = self.inner.clone();
+        let missed = futures::stream::iter(missed).then(move |number| {
+            let inner = inner.clone();
+            async move {
+                let hash = inner
+                    .legacy
+                    .chain_get_block_hash(Some(number.into()))
+                    .await?
+                    .ok_or_else(|| Error::Other(format!("block {number} not found")))?;
+                inner.client.blocks().at(hash).await
+            }
+        });
+        let live = live.try_filter(move |block| {
+            futures::future::ready(BlockNumber::from(block.number()) > head)
+        });
+
+        Ok(missed.chain(live).and_then(move |block| {
+            let contract = contract.clone();
+            async move {
+                let events = block
+                    .events()
+                    .await?
+                    .find::<polkadot::contracts::events::ContractEmitted>()
+                    .filter_map(Result::ok)
+                    .filter(move |event| event.contract == contract)
+                    .map(|event| {
+                        StakeEvent::decode(&mut event.data.as_slice())
+                            .map_err(|e| Error::Decode(subxt::error::DecodeError::custom(e)))
+                    })
+                    .collect();
+                Ok((block.number().into(), events))
+            }
+        }))
     }
 
     pub async fn with_signer(url: &str, account: S) -> Result<Self> {
@@ -223,16 +247,43 @@ impl<S: TransactionHandler> Client<S> {
         &self,
         dest: ContractId,
         data: NodeData,
-        addr: NodeAddress,
+        endpoints: Vec<NodeEndpoint>,
         nonce: Nonce,
     ) -> Result<()> {
-        self.call_auto_weight(1_000_000, dest, node_staker::messages::join(data, addr), nonce).await
+        let call = node_staker::messages::join(data, endpoints);
+        self.call_auto_weight(1_000_000, dest, call, nonce).await
     }
 
-    pub async fn list(&self, addr: ContractId) -> Result<Vec<(NodeData, NodeAddress)>> {
+    /// Nodes with their endpoints and placement weight.
+    pub async fn list(&self, addr: ContractId) -> Result<Vec<(NodeData, Vec<NodeEndpoint>, u32)>> {
         self.call_dry(0, addr, node_staker::messages::list()).await
     }
 
+    /// Registration of the node with the ed25519 `id`, `None` if it is not staked.
+    pub async fn node(
+        &self,
+        addr: ContractId,
+        id: [u8; 32],
+    ) -> Result<Option<(NodeData, Vec<NodeEndpoint>, u32)>> {
+        self.call_dry(0, addr, node_staker::messages::node(id)).await
+    }
+
+    pub async fn network_params(&self, addr: ContractId) -> Result<NetworkParams> {
+        self.call_dry(0, addr, node_staker::messages::params()).await
+    }
+
+    /// Hash of the block the node contract uses as the randomness beacon for placement.
+    pub async fn placement_beacon(&self, addr: ContractId) -> Result<[u8; 32]> {
+        let number: u32 = self.call_dry(0, addr, node_staker::messages::beacon_block()).await?;
+        let hash = self
+            .inner
+            .legacy
+            .chain_get_block_hash(Some(u64::from(number).into()))
+            .await?
+            .ok_or_else(|| Error::Other(format!("beacon block {number} not found")))?;
+        Ok(hash.0)
+    }
+
     pub async fn vote(
         &self,
         dest: ContractId,
@@ -1,4 +1,7 @@
-use {self::node_staker::NodeData, subxt::PolkadotConfig};
+use {
+    self::node_staker::{NetworkParams, NodeData},
+    subxt::PolkadotConfig,
+};
 
 /// Trait implemented by [`smart_bench_macro::contract`] for all contract constructors.
 pub trait InkConstructor: codec::Encode {
@@ -28,7 +31,10 @@ mod polkadot {}
 contract_macro::contract!("../../target/ink/node_staker/node_staker.contract");
 contract_macro::contract!("../../target/ink/user_manager/user_manager.contract");
 
-use {node_staker::NodeAddress, std::net::IpAddr};
+use {
+    node_staker::{Endpoint, NodeAddress, Transport},
+    std::net::IpAddr,
+};
 pub use {
     polkadot::*,
     subxt::{self, ext::*},
@@ -53,6 +59,30 @@ impl Clone for NodeAddress {
 
 impl Copy for NodeAddress {}
 
+impl Clone for Transport {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Copy for Transport {}
+
+impl Clone for Endpoint {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Copy for Endpoint {}
+
+impl Clone for NetworkParams {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Copy for NetworkParams {}
+
 impl Clone for NodeData {
     fn clone(&self) -> Self {
         *self
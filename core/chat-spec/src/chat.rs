@@ -35,6 +35,10 @@ impl Topic for ChatName {
 #[derive(Codec)]
 pub enum ChatEvent<'a> {
     Message(Proof<ChatName>, Reminder<'a>),
+    /// Not persisted, only delivered to currently subscribed members (typing, presence...).
+    Ephemeral(Proof<ChatName>, Reminder<'a>),
+    /// Owner deleted the chat, subscriptions to it end with this event.
+    Deleted(Proof<ChatName>),
 }
 
 #[derive(Codec)]
@@ -48,6 +52,9 @@ pub struct ChatChecksums {
 pub enum ChatAction<'a> {
     AddUser(Identity),
     SendMessage(Reminder<'a>),
+    TransferOwnership(Identity),
+    DeleteChat,
+    Ephemeral(Reminder<'a>),
 }
 
 impl From<Identity> for ChatAction<'_> {
@@ -66,6 +73,10 @@ impl<'a> From<Reminder<'a>> for ChatAction<'a> {
 pub enum CreateChatError {
     #[error("chat already exists")]
     AlreadyExists,
+    #[error("insufficient proof of work, expected difficulty {0}")]
+    InsufficientWork(u8),
+    #[error("chat was recently deleted, the name is not free yet")]
+    RecentlyDeleted,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
@@ -84,6 +95,10 @@ pub enum ChatActionError {
     MessageTooLarge,
     #[error("latest message block is still being finalized")]
     MessageBlockNotFinalized,
+    #[error("only the owner can do this")]
+    NotOwner,
+    #[error("user is not a member")]
+    UserNotMember,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
@@ -9,15 +9,97 @@
 
 use {
     component_utils::{arrayvec::ArrayVec, crypto::ToProofContext, Codec, Reminder},
-    crypto::{enc, Serialized},
+    crypto::{enc, sign, Serialized, TransmutationCircle},
+    rand_core::CryptoRngCore,
     std::{convert::Infallible, num::NonZeroUsize},
 };
 
-pub const REPLICATION_FACTOR: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(4) };
+/// Upper bound for [`NetworkParams::replication_factor`], so that replica bookkeeping can
+/// stay on the stack.
+pub const MAX_REPLICATION_FACTOR: usize = 16;
 
 pub type BlockNumber = u64;
 pub type Identity = crypto::Hash;
-pub type ReplVec<T> = ArrayVec<T, { REPLICATION_FACTOR.get() }>;
+pub type PowNonce = u64;
+pub type ReplVec<T> = ArrayVec<T, MAX_REPLICATION_FACTOR>;
+
+/// Parameters every node of the network has to agree on, published by the node contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetworkParams {
+    /// Amount of nodes replicating a topic besides the closest one.
+    pub replication_factor: NonZeroUsize,
+    pub block_size: usize,
+    pub block_history: usize,
+    pub max_message_size: usize,
+    /// Virtual points per node, zero places topics by node key distance.
+    pub virtual_nodes: u32,
+    /// Seed of the virtual points, taken from the chain after the params are fetched.
+    pub placement_beacon: [u8; 32],
+}
+
+impl NetworkParams {
+    #[must_use]
+    pub fn replicator_count(&self) -> usize {
+        self.replication_factor.get() + 1
+    }
+
+    #[must_use]
+    pub fn majority(&self) -> usize {
+        self.replication_factor.get() / 2
+    }
+}
+
+/// Matches the defaults of the node contract.
+impl Default for NetworkParams {
+    fn default() -> Self {
+        Self {
+            replication_factor: NonZeroUsize::new(4).unwrap(),
+            block_size: 1024 * 32,
+            block_history: 32,
+            max_message_size: 1024,
+            virtual_nodes: 0,
+            placement_beacon: [0; 32],
+        }
+    }
+}
+
+impl TryFrom<chain_api::NetworkParams> for NetworkParams {
+    type Error = InvalidNetworkParams;
+
+    fn try_from(params: chain_api::NetworkParams) -> Result<Self, Self::Error> {
+        let replication_factor = NonZeroUsize::new(params.replication_factor as usize)
+            .filter(|rf| rf.get() <= MAX_REPLICATION_FACTOR)
+            .ok_or(InvalidNetworkParams::ReplicationFactor(params.replication_factor))?;
+        let params = Self {
+            replication_factor,
+            block_size: params.block_size as usize,
+            block_history: params.block_history as usize,
+            max_message_size: params.max_message_size as usize,
+            virtual_nodes: params.virtual_nodes,
+            placement_beacon: [0; 32],
+        };
+
+        if params.block_size <= params.max_message_size {
+            return Err(InvalidNetworkParams::BlockSize(params.block_size));
+        }
+
+        if params.block_history == 0 {
+            return Err(InvalidNetworkParams::BlockHistory);
+        }
+
+        Ok(params)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidNetworkParams {
+    #[error("replication factor {0} is not in 1..={MAX_REPLICATION_FACTOR}")]
+    ReplicationFactor(u8),
+    #[error("block size {0} can not fit the largest message")]
+    BlockSize(usize),
+    #[error("block history can not be empty")]
+    BlockHistory,
+}
 
 mod chat;
 mod profile;
@@ -34,18 +116,21 @@ pub use {
 
 component_utils::compose_protocols! {
     fn Subscribe(PossibleTopic) -> Result<(), Infallible>;
+    fn SubscribeProfile(Identity, EventSeq) -> Result<(), Infallible>;
 
-    fn CreateChat(ChatName, Identity) -> Result<(), CreateChatError>;
+    fn CreateChat(ChatName, Identity, PowNonce) -> Result<(), CreateChatError>;
     fn PerformChatAction<'a>(Proof<ChatName>, ChatAction<'a>) -> Result<(), ChatActionError>;
     fn FetchMessages<'a>(ChatName, Cursor) -> Result<(Cursor, Reminder<'a>), FetchMessagesError>;
     fn ProposeMsgBlock(ChatName, BlockNumber, crypto::Hash) -> Result<(), ProposeMsgBlockError>;
     fn SendBlock<'a>(ChatName, BlockNumber, Reminder<'a>) -> Result<(), SendBlockError>;
     fn FetchLatestBlock<'a>(ChatName) -> Result<(BlockNumber, Reminder<'a>), FetchLatestBlockError>;
 
-    fn CreateProfile<'a>(Proof<&'a [u8]>, Serialized<enc::PublicKey>) -> Result<(), CreateAccountError>;
+    fn CreateProfile<'a>(Proof<&'a [u8]>, Serialized<enc::PublicKey>, PowNonce) -> Result<(), CreateAccountError>;
     fn SetVault<'a>(Proof<Reminder<'a>>) -> Result<(), SetVaultError>;
     fn FetchVault<'a>(Identity) -> Result<(Nonce, Nonce, Reminder<'a>), FetchVaultError>;
-    fn ReadMail<'a>(Proof<Mail>) -> Result<Reminder<'a>, ReadMailError>;
+    // mail before the sequence number is dropped, the rest is returned together with the
+    // sequence number of the next mail
+    fn ReadMail<'a>(Proof<Mail>, EventSeq) -> Result<(EventSeq, Reminder<'a>), ReadMailError>;
     fn SendMail<'a>(Identity, Reminder<'a>) -> Result<(), SendMailError>;
     fn FetchProfile(Identity) -> Result<FetchProfileResp, FetchProfileError>;
     fn FetchFullProfile<'a>(Identity) -> Result<BorrowedProfile<'a>, FetchProfileError>;
@@ -73,6 +158,79 @@ pub enum ReplError<T> {
     Inner(T),
 }
 
+/// Sent in place of the `ProtocolResult` when server refuses to dispatch the request at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Rejection {
+    #[error("too many requests on this path")]
+    PathRateLimited,
+    #[error("too many requests from this identity")]
+    IdentityRateLimited,
+    #[error("node is shutting down")]
+    ShuttingDown,
+}
+
+impl Rejection {
+    /// `ProtocolResult` starts with a bool so this can not be mistaken for it.
+    const TAG: u8 = 2;
+}
+
+impl<'a> Codec<'a> for Rejection {
+    fn encode(&self, buffer: &mut impl component_utils::Buffer) -> Option<()> {
+        buffer.push(Self::TAG)?;
+        buffer.push(match self {
+            Self::PathRateLimited => 0,
+            Self::IdentityRateLimited => 1,
+            Self::ShuttingDown => 2,
+        })
+    }
+
+    fn decode(buffer: &mut &'a [u8]) -> Option<Self> {
+        if *buffer.take_first()? != Self::TAG {
+            return None;
+        }
+        match buffer.take_first()? {
+            0 => Some(Self::PathRateLimited),
+            1 => Some(Self::IdentityRateLimited),
+            2 => Some(Self::ShuttingDown),
+            _ => None,
+        }
+    }
+}
+
+/// Prefix of server to server rpc payloads wrapped in [`SignedRpc`], no protocol uses it.
+pub const SIGNED_RPC_PREFIX: u8 = u8::MAX;
+
+/// Rpc payload of a node that is shutting down, receivers drop it from their routes and hand
+/// the topics it replicated to the nodes taking its place. No protocol uses the prefix.
+pub const LEAVING_PREFIX: u8 = u8::MAX - 1;
+
+/// Rpc payload signed by the sending node. Whoever holds it can prove the node sent the
+/// payload, so signed votes double as slashing evidence.
+#[derive(Codec)]
+pub struct SignedRpc<'a> {
+    pub pk: Serialized<sign::PublicKey>,
+    pub signature: Serialized<sign::Signature>,
+    pub payload: Reminder<'a>,
+}
+
+impl<'a> SignedRpc<'a> {
+    pub fn new(keys: &sign::Keypair, payload: &'a [u8], rng: impl CryptoRngCore) -> Self {
+        Self {
+            pk: keys.public_key().into_bytes(),
+            signature: keys.sign(payload, rng).into_bytes(),
+            payload: Reminder(payload),
+        }
+    }
+
+    /// Hash of the signing key, compare it with the `sign` hash the node registered on chain.
+    #[must_use]
+    pub fn verify(&self) -> Option<crypto::Hash> {
+        let pk = sign::PublicKey::from_ref(&self.pk);
+        pk.verify(self.payload.0, sign::Signature::from_ref(&self.signature)).ok()?;
+        Some(crypto::hash::new(pk))
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Codec)]
 pub enum PossibleTopic {
     Profile(Identity),
@@ -167,6 +325,23 @@ pub fn advance_nonce(current: &mut Nonce, new: Nonce) -> bool {
     valid
 }
 
+/// Number of leading zero bits in the hash of `topic` and `nonce`. Servers can demand a
+/// minimal difficulty before admitting new topics, which makes squatting names expensive.
+#[must_use]
+pub fn pow_difficulty(topic: PossibleTopic, nonce: PowNonce) -> u8 {
+    let hash = crypto::hash::with_nonce(topic.as_bytes(), nonce);
+    let zeros = hash
+        .iter()
+        .position(|&b| b != 0)
+        .map_or(hash.len() * 8, |i| i * 8 + hash[i].leading_zeros() as usize);
+    zeros.min(u8::MAX as usize) as u8
+}
+
+#[must_use]
+pub fn solve_pow(topic: PossibleTopic, difficulty: u8) -> PowNonce {
+    (0..).find(|&nonce| pow_difficulty(topic, nonce) >= difficulty).expect("we will find it")
+}
+
 pub type ProtocolResult<'a, P> = Result<<P as Protocol>::Response<'a>, <P as Protocol>::Error>;
 
 pub trait Topic: for<'a> Codec<'a> + std::hash::Hash + Eq + 'static + Into<PossibleTopic> {
@@ -9,6 +9,9 @@ use {
 pub const MAIL_BOX_CAP: usize = 1024 * 1024;
 
 pub type UserName = ArrayString<32>;
+/// Position of a mail in the per-identity event queue, keeps increasing even after the mail
+/// is read.
+pub type EventSeq = u64;
 
 #[derive(Clone, Codec)]
 pub struct Profile {
@@ -17,6 +20,7 @@ pub struct Profile {
     pub last_sig: Serialized<sign::Signature>,
     pub vault_version: Nonce,
     pub mail_action: Nonce,
+    pub mail_seq: EventSeq,
     pub vault: Vec<u8>,
     pub mail: Vec<u8>,
 }
@@ -28,25 +32,35 @@ pub struct BorrowedProfile<'a> {
     pub last_sig: Serialized<sign::Signature>,
     pub vault_version: Nonce,
     pub mail_action: Nonce,
+    pub mail_seq: EventSeq,
     pub vault: &'a [u8],
     pub mail: &'a [u8],
 }
 
 impl Profile {
-    pub fn read_mail(&mut self) -> &[u8] {
-        // SAFETY: thre resulting slice locks mutable access to self, we just need to truncate
-        // while preserving the borrow
-        let slice = unsafe { std::mem::transmute(self.mail.as_slice()) };
-        // SAFETY: while the slice exists we cannot push to `self.mail` thus truncating is safe, we
-        // avoid truncate since it calls destructors witch requires mutable access to slice memory,
-        // we dont want that
-        unsafe { self.mail.set_len(0) };
-        slice
+    /// Drops the mail before `from`, the client asking for it has already seen it.
+    pub fn ack_mail(&mut self, from: EventSeq) {
+        let acked = from.saturating_sub(self.first_mail_seq()) as usize;
+        let len = unpack_mail(&self.mail).take(acked).map(|m| m.len() + 2).sum::<usize>();
+        self.mail.drain(..len);
     }
 
-    pub fn push_mail(&mut self, content: &[u8]) {
+    pub fn push_mail(&mut self, content: &[u8]) -> EventSeq {
         self.mail.extend(encode_len(content.len()));
         self.mail.extend_from_slice(content);
+        self.mail_seq += 1;
+        self.mail_seq - 1
+    }
+
+    /// Unread mail paired with sequence numbers, starting from `from`.
+    pub fn mail_since(&self, from: EventSeq) -> impl Iterator<Item = (EventSeq, &[u8])> {
+        (self.first_mail_seq()..)
+            .zip(unpack_mail(&self.mail))
+            .skip_while(move |&(seq, _)| seq < from)
+    }
+
+    fn first_mail_seq(&self) -> EventSeq {
+        self.mail_seq - unpack_mail(&self.mail).count() as EventSeq
     }
 }
 
@@ -58,6 +72,7 @@ impl<'a> From<&'a Profile> for BorrowedProfile<'a> {
             last_sig: profile.last_sig,
             vault_version: profile.vault_version,
             mail_action: profile.mail_action,
+            mail_seq: profile.mail_seq,
             vault: profile.vault.as_slice(),
             mail: profile.mail.as_slice(),
         }
@@ -85,6 +100,7 @@ impl<'a> From<BorrowedProfile<'a>> for Profile {
             last_sig: profile.last_sig,
             vault_version: profile.vault_version,
             mail_action: profile.mail_action,
+            mail_seq: profile.mail_seq,
             vault: profile.vault.to_vec(),
             mail: profile.mail.to_vec(),
         }
@@ -102,7 +118,7 @@ impl Topic for Identity {
     type Record = Profile;
 }
 
-type ProfileEvent<'a> = Reminder<'a>;
+pub type ProfileEvent<'a> = (EventSeq, Reminder<'a>);
 
 #[derive(Codec)]
 pub struct FetchProfileResp {
@@ -122,6 +138,8 @@ pub enum CreateAccountError {
     InvalidProof,
     #[error("account already exists")]
     AlreadyExists,
+    #[error("insufficient proof of work, expected difficulty {0}")]
+    InsufficientWork(u8),
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
@@ -154,10 +172,6 @@ pub enum ReadMailError {
 pub enum SendMailError {
     #[error("account not found")]
     NotFound,
-    #[error("sent directly")]
-    SentDirectly,
-    #[error("sending to self is not allowed")]
-    SendingToSelf,
     #[error("mailbox full (limit: {MAIL_BOX_CAP})")]
     MailboxFull,
 }
@@ -94,8 +94,8 @@ pub fn encrypt(
     unsafe { core::mem::transmute((tag, nonce)) }
 }
 
-const NONCE_SIZE: usize = <<Aes256Gcm as AeadCore>::NonceSize as Unsigned>::USIZE;
-const TAG_SIZE: usize = <<Aes256Gcm as AeadCore>::TagSize as Unsigned>::USIZE;
+pub const NONCE_SIZE: usize = <<Aes256Gcm as AeadCore>::NonceSize as Unsigned>::USIZE;
+pub const TAG_SIZE: usize = <<Aes256Gcm as AeadCore>::TagSize as Unsigned>::USIZE;
 
 #[derive(Debug, Clone, Copy)]
 pub struct FixedAesPayload<const SIZE: usize> {
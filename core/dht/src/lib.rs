@@ -1,16 +1,29 @@
 #![feature(trait_alias)]
 #![feature(let_chains)]
+
+#[cfg(feature = "lookup")]
+pub mod lookup;
+
 use {
+    instant::{Duration, Instant},
     libp2p::{
+        futures::FutureExt,
         identity::{ed25519, PublicKey},
+        multiaddr::Protocol,
         multihash::Multihash,
-        swarm::NetworkBehaviour,
+        swarm::{
+            dial_opts::{DialOpts, PeerCondition},
+            DialFailure, FromSwarm, NetworkBehaviour,
+        },
         Multiaddr, PeerId,
     },
     primitive_types::U256,
     std::{convert::Infallible, iter},
 };
 
+/// How long a suspect route keeps its place in [`RoutingTable::closest_alive`] by default.
+pub const DEFAULT_SUSPECT_GRACE: Duration = Duration::from_secs(30);
+
 pub type Filter = fn(
     &mut RoutingTable,
     PeerId,
@@ -18,10 +31,31 @@ pub type Filter = fn(
     &Multiaddr,
 ) -> Result<(), libp2p::swarm::ConnectionDenied>;
 
-#[derive(Clone)]
 pub struct Behaviour {
     pub table: RoutingTable,
+    /// Nodes found by lookups, they are dialable just like the ones in the table.
+    #[cfg(feature = "lookup")]
+    pub lookups: Option<lookup::Lookups>,
     filter: Filter,
+    transports: Vec<Transport>,
+    ping_interval: Option<Duration>,
+    ping_timer: Option<futures_timer::Delay>,
+    pings: Vec<PeerId>,
+}
+
+impl Clone for Behaviour {
+    fn clone(&self) -> Self {
+        Self {
+            table: self.table.clone(),
+            #[cfg(feature = "lookup")]
+            lookups: self.lookups.clone(),
+            filter: self.filter,
+            transports: self.transports.clone(),
+            ping_interval: self.ping_interval,
+            ping_timer: None,
+            pings: Vec::new(),
+        }
+    }
 }
 
 impl Default for Behaviour {
@@ -32,7 +66,46 @@ impl Default for Behaviour {
 
 impl Behaviour {
     pub fn new(filter: Filter) -> Self {
-        Self { table: RoutingTable::default(), filter }
+        Self {
+            table: RoutingTable::default(),
+            #[cfg(feature = "lookup")]
+            lookups: None,
+            filter,
+            transports: vec![Transport::Tcp, Transport::Ws, Transport::Quic],
+            ping_interval: None,
+            ping_timer: None,
+            pings: Vec::new(),
+        }
+    }
+
+    /// Periodically dials suspect routes so they can recover without traffic.
+    #[must_use]
+    pub fn with_pings(mut self, interval: Duration) -> Self {
+        self.ping_interval = Some(interval);
+        self
+    }
+
+    /// Enables [`lookup::Lookups`] for networks where the table can not hold every node.
+    #[cfg(feature = "lookup")]
+    #[must_use]
+    pub fn with_lookups(mut self, local: ed25519::PublicKey) -> Self {
+        self.lookups = Some(lookup::Lookups::new(local));
+        self
+    }
+
+    fn route(&self, peer: PeerId) -> Option<&Route> {
+        let route = self.table.route(peer);
+        #[cfg(feature = "lookup")]
+        let route = route.or_else(|| self.lookups.as_ref()?.route(peer));
+        route
+    }
+
+    /// Transports the swarm can dial, most preferred first. Route addresses over other
+    /// transports are never handed to the swarm.
+    #[must_use]
+    pub fn with_transports(mut self, transports: Vec<Transport>) -> Self {
+        self.transports = transports;
+        self
     }
 }
 
@@ -70,15 +143,33 @@ impl NetworkBehaviour for Behaviour {
     ) -> Result<Vec<Multiaddr>, libp2p::swarm::ConnectionDenied> {
         if addresses.is_empty()
             && let Some(peer) = maybe_peer
-            && let Some(addr) = self.table.get(peer)
+            && let Some(route) = self.route(peer)
         {
-            return Ok(vec![addr.clone()]);
+            return Ok(route.preferred_addrs(&self.transports).cloned().collect());
         }
 
         Ok(vec![])
     }
 
-    fn on_swarm_event(&mut self, _: libp2p::swarm::FromSwarm) {}
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(c) => {
+                self.table.report_alive(c.peer_id);
+                #[cfg(feature = "lookup")]
+                if let Some(lookups) = &mut self.lookups {
+                    lookups.buckets.report_alive(c.peer_id);
+                }
+            }
+            FromSwarm::DialFailure(DialFailure { peer_id: Some(peer), .. }) => {
+                self.table.report_failure(peer);
+                #[cfg(feature = "lookup")]
+                if let Some(lookups) = &mut self.lookups {
+                    lookups.buckets.report_failure(peer);
+                }
+            }
+            _ => {}
+        }
+    }
 
     fn on_connection_handler_event(
         &mut self,
@@ -91,17 +182,91 @@ impl NetworkBehaviour for Behaviour {
 
     fn poll(
         &mut self,
-        _cx: &mut std::task::Context<'_>,
+        cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<libp2p::swarm::ToSwarm<Self::ToSwarm, libp2p::swarm::THandlerInEvent<Self>>>
     {
+        if let Some(interval) = self.ping_interval {
+            let timer = self.ping_timer.get_or_insert_with(|| futures_timer::Delay::new(interval));
+            if timer.poll_unpin(cx).is_ready() {
+                timer.reset(interval);
+                _ = timer.poll_unpin(cx);
+                self.pings.extend(self.table.suspects().map(Route::peer_id));
+            }
+        }
+
+        if let Some(peer) = self.pings.pop() {
+            let opts = DialOpts::peer_id(peer).condition(PeerCondition::DisconnectedAndNotDialing);
+            return std::task::Poll::Ready(libp2p::swarm::ToSwarm::Dial { opts: opts.build() });
+        }
+
         std::task::Poll::Pending
     }
 }
 
-#[derive(Default, Clone)]
+/// How [`RoutingTable::closest`] maps data onto routes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// Routes whose key is closest to the data. Whoever grinds keys picks what they store.
+    #[default]
+    Key,
+    /// Each route occupies `points * weight` positions derived from the beacon and its key,
+    /// so the position can not be chosen before the beacon is known and load evens out.
+    /// Routes of zero weight occupy nothing and replicate nothing.
+    Virtual { beacon: [u8; 32], points: u32 },
+}
+
+impl Placement {
+    /// Zero virtual points means [`Placement::Key`].
+    #[must_use]
+    pub fn new(virtual_points: u32, beacon: [u8; 32]) -> Self {
+        match virtual_points {
+            0 => Self::Key,
+            points => Self::Virtual { beacon, points },
+        }
+    }
+
+    /// Ring positions the route occupies.
+    fn positions(self, route: &Route) -> impl Iterator<Item = U256> {
+        let id: [u8; 32] = route.id.into();
+        let (key, beacon, count) = match self {
+            Self::Key => (Some(route.id), [0; 32], 0),
+            Self::Virtual { beacon, points } => (None, beacon, points.saturating_mul(route.weight)),
+        };
+        key.into_iter().chain((0..count).map(move |i| {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(&beacon).update(&id).update(&i.to_le_bytes());
+            U256::from(hasher.finalize().as_bytes())
+        }))
+    }
+
+    /// Distance of the closest position of the route to `key`, routes without positions are
+    /// infinitely far.
+    fn distance(self, key: U256, route: &Route) -> U256 {
+        self.positions(route).map(|p| shortest_distance(key, p)).min().unwrap_or(U256::MAX)
+    }
+}
+
+#[derive(Clone)]
 pub struct RoutingTable {
     // sorted vec is perfect since we almost never insert new entries
     routes: Vec<Route>,
+    placement: Placement,
+    // sorted virtual positions with the index of their route, empty with key placement
+    points: Vec<(U256, usize)>,
+    /// How long a suspect route keeps its place in [`RoutingTable::closest_alive`] before the
+    /// next candidate takes over.
+    pub suspect_grace: Duration,
+}
+
+impl Default for RoutingTable {
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            placement: Placement::Key,
+            points: Vec::new(),
+            suspect_grace: DEFAULT_SUSPECT_GRACE,
+        }
+    }
 }
 
 impl RoutingTable {
@@ -109,31 +274,124 @@ impl RoutingTable {
         self.routes.iter()
     }
 
+    #[must_use]
+    pub fn placement(&self) -> Placement {
+        self.placement
+    }
+
+    pub fn set_placement(&mut self, placement: Placement) {
+        self.placement = placement;
+        self.rebuild_points();
+    }
+
+    fn rebuild_points(&mut self) {
+        self.points.clear();
+        if self.placement == Placement::Key {
+            return;
+        }
+
+        for (index, route) in self.routes.iter().enumerate() {
+            self.points.extend(self.placement.positions(route).map(|p| (p, index)));
+        }
+        self.points.sort_unstable();
+    }
+
     pub fn bulk_insert(&mut self, routes: impl IntoIterator<Item = Route>) {
         assert!(self.routes.is_empty());
         self.routes.extend(routes);
         self.routes.sort_by_key(|r| r.id);
+        self.rebuild_points();
     }
 
     pub fn insert(&mut self, route: Route) {
+        self.insert_route(route);
+        self.rebuild_points();
+    }
+
+    /// Like [`Self::insert`] for many routes, virtual points are recomputed once.
+    pub fn insert_all(&mut self, routes: impl IntoIterator<Item = Route>) {
+        routes.into_iter().for_each(|r| self.insert_route(r));
+        self.rebuild_points();
+    }
+
+    fn insert_route(&mut self, mut route: Route) {
         match self.routes.binary_search_by_key(&route.id, |r| r.id) {
-            Ok(i) => self.routes[i] = route,
+            Ok(i) => {
+                // same address is most likely the same process
+                if self.routes[i].addrs == route.addrs {
+                    route.health = self.routes[i].health;
+                }
+                self.routes[i] = route;
+            }
             Err(i) => self.routes.insert(i, route),
         }
     }
 
+    pub fn retain(&mut self, keep: impl FnMut(&Route) -> bool) {
+        self.routes.retain(keep);
+        self.rebuild_points();
+    }
+
     pub fn remove(&mut self, id: PeerId) -> Option<Route> {
         let id = try_peer_id_to_ed(id)?;
         let index = self.routes.binary_search_by_key(&id.into(), |r| r.id).ok()?;
-        Some(self.routes.remove(index))
+        let route = self.routes.remove(index);
+        self.rebuild_points();
+        Some(route)
     }
 
     #[must_use]
-    pub fn get(&self, id: PeerId) -> Option<&Multiaddr> {
-        let id = try_peer_id_to_ed(id)?;
-        let id: U256 = id.into();
+    pub fn get(&self, id: PeerId) -> Option<&[Address]> {
+        self.route(id).map(Route::addrs)
+    }
+
+    #[must_use]
+    pub fn route(&self, id: PeerId) -> Option<&Route> {
+        let id: U256 = try_peer_id_to_ed(id)?.into();
         let index = self.routes.binary_search_by_key(&id, |r| r.id).ok()?;
-        Some(&self.routes[index].addr)
+        Some(&self.routes[index])
+    }
+
+    fn route_mut(&mut self, id: PeerId) -> Option<&mut Route> {
+        let id: U256 = try_peer_id_to_ed(id)?.into();
+        let index = self.routes.binary_search_by_key(&id, |r| r.id).ok()?;
+        Some(&mut self.routes[index])
+    }
+
+    /// Connection or request to the peer succeeded.
+    pub fn report_alive(&mut self, id: PeerId) {
+        if let Some(route) = self.route_mut(id) {
+            route.mark_alive();
+        }
+    }
+
+    /// Dial or request to the peer failed, the first failure starts the grace period.
+    pub fn report_failure(&mut self, id: PeerId) {
+        if let Some(route) = self.route_mut(id) {
+            route.mark_failed();
+        }
+    }
+
+    /// The peer is known and not suspect for longer than [`Self::suspect_grace`].
+    #[must_use]
+    pub fn is_alive(&self, id: PeerId) -> bool {
+        self.route(id).is_some_and(|r| r.is_alive(self.suspect_grace))
+    }
+
+    pub fn suspects(&self) -> impl Iterator<Item = &Route> + '_ {
+        self.routes.iter().filter(|r| matches!(r.health, Health::Suspect(_)))
+    }
+
+    /// Like [`Self::closest`] but skips routes that are suspect for longer than
+    /// [`Self::suspect_grace`].
+    pub fn closest_alive(&self, data: &[u8]) -> impl Iterator<Item = &Route> + '_ {
+        let grace = self.suspect_grace;
+        self.closest(data).filter(move |r| r.is_alive(grace))
+    }
+
+    /// Like [`Self::closest`] for data that is already hashed.
+    pub fn closest_to_key(&self, key: [u8; 32]) -> impl Iterator<Item = &Route> + '_ {
+        self.closest_low(U256::from(key))
     }
 
     pub fn closest(&self, data: &[u8]) -> impl Iterator<Item = &Route> + '_ {
@@ -143,36 +401,76 @@ impl RoutingTable {
     }
 
     fn closest_low(&self, id: U256) -> impl Iterator<Item = &Route> + '_ {
-        let index = self.routes.binary_search_by_key(&id, |r| r.id).unwrap_or_else(|i| i);
-
-        let mut left = self.routes[..index].iter().rev();
-        let mut right = self.routes[index..].iter();
-        let mut left_peek = left.next().or_else(|| right.next_back());
-        let mut right_peek = right.next().or_else(|| left.next_back());
-
-        iter::from_fn(move || {
-            let (left_route, right_route) = match (left_peek, right_peek) {
-                (Some(left), Some(right)) => (left, right),
-                // we do not peek anymore since this must be the last one
-                (Some(either), None) | (None, Some(either)) => {
-                    left_peek = None;
-                    right_peek = None;
-                    return Some(either);
-                }
-                (None, None) => return None,
-            };
-
-            if shortest_distance(id, left_route.id) < shortest_distance(id, right_route.id) {
-                left_peek = left.next().or_else(|| right.next_back());
-                Some(left_route)
+        let is_virtual = matches!(self.placement, Placement::Virtual { .. });
+        // position on the ring and the route it belongs to
+        let point = move |i: usize| {
+            if is_virtual {
+                self.points[i]
             } else {
-                right_peek = right.next().or_else(|| left.next_back());
-                Some(right_route)
+                (self.routes[i].id, i)
             }
-        })
+        };
+        let (len, index) = if is_virtual {
+            (self.points.len(), self.points.partition_point(|p| p.0 < id))
+        } else {
+            (self.routes.len(), self.routes.partition_point(|r| r.id < id))
+        };
+
+        // virtual points of one route are scattered, it is yielded on the first one, callers
+        // take a few routes so searching the yielded ones is cheaper than a flag per route
+        let mut yielded = Vec::new();
+        walk_ring(id, index, len, move |i| point(i).0)
+            .map(move |i| point(i).1)
+            .filter(move |&i| {
+                if !is_virtual {
+                    return true;
+                }
+                if yielded.contains(&i) {
+                    return false;
+                }
+                yielded.push(i);
+                true
+            })
+            .map(|i| &self.routes[i])
     }
 }
 
+/// Indices of sorted ring positions ordered by distance from `id`, `index` being the first
+/// position not less than `id`.
+fn walk_ring(
+    id: U256,
+    index: usize,
+    len: usize,
+    position: impl Fn(usize) -> U256,
+) -> impl Iterator<Item = usize> {
+    let mut left = (0..index).rev();
+    let mut right = index..len;
+    let mut left_peek = left.next().or_else(|| right.next_back());
+    let mut right_peek = right.next().or_else(|| left.next_back());
+
+    iter::from_fn(move || {
+        let (left_index, right_index) = match (left_peek, right_peek) {
+            (Some(left), Some(right)) => (left, right),
+            // we do not peek anymore since this must be the last one
+            (Some(either), None) | (None, Some(either)) => {
+                left_peek = None;
+                right_peek = None;
+                return Some(either);
+            }
+            (None, None) => return None,
+        };
+
+        let left_distance = shortest_distance(id, position(left_index));
+        if left_distance < shortest_distance(id, position(right_index)) {
+            left_peek = left.next().or_else(|| right.next_back());
+            Some(left_index)
+        } else {
+            right_peek = right.next().or_else(|| left.next_back());
+            Some(right_index)
+        }
+    })
+}
+
 fn shortest_distance(a: U256, b: U256) -> U256 {
     a.overflowing_sub(b).0.min(b.overflowing_sub(a).0)
 }
@@ -184,17 +482,126 @@ pub fn try_peer_id_to_ed(id: PeerId) -> Option<[u8; 32]> {
     bytes[bytes.len() - 32..].try_into().ok()
 }
 
+/// Reachability of a route as observed by this node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Health {
+    #[default]
+    Unknown,
+    Alive,
+    /// Failing since the instant, nothing succeeded after.
+    Suspect(Instant),
+}
+
+/// Transport an address is reached over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Tcp,
+    Ws,
+    Quic,
+}
+
+impl Transport {
+    /// Innermost transport wins, `/tcp/../ws` is a websocket address. Anything that is neither
+    /// websocket nor quic is treated as plain tcp.
+    #[must_use]
+    pub fn of(addr: &Multiaddr) -> Self {
+        addr.iter().fold(Self::Tcp, |transport, protocol| match protocol {
+            Protocol::Ws(_) | Protocol::Wss(_) => Self::Ws,
+            Protocol::Quic | Protocol::QuicV1 => Self::Quic,
+            _ => transport,
+        })
+    }
+}
+
+/// One of the addresses a route is reachable at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Address {
+    pub addr: Multiaddr,
+    pub transport: Transport,
+    pub ipv6: bool,
+}
+
+impl From<Multiaddr> for Address {
+    fn from(addr: Multiaddr) -> Self {
+        let transport = Transport::of(&addr);
+        let ipv6 = addr.iter().any(|p| matches!(p, Protocol::Ip6(_) | Protocol::Dns6(_)));
+        Self { addr, transport, ipv6 }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Route {
     id: U256,
-    pub addr: Multiaddr,
+    addrs: Vec<Address>,
+    health: Health,
+    weight: u32,
 }
 
 impl Route {
     #[must_use]
     pub fn new(id: ed25519::PublicKey, addr: Multiaddr) -> Self {
+        Self::with_addrs(id, [addr])
+    }
+
+    #[must_use]
+    pub fn with_addrs(id: ed25519::PublicKey, addrs: impl IntoIterator<Item = Multiaddr>) -> Self {
         let id = U256::from(id.to_bytes());
-        Self { id, addr }
+        let addrs = addrs.into_iter().map(Address::from).collect();
+        Self { id, addrs, health: Health::Unknown, weight: 1 }
+    }
+
+    /// Multiplies the virtual points of the route, usually by its stake. Has no effect with
+    /// [`Placement::Key`]. With zero weight the route has no points, so virtual placement
+    /// never picks it.
+    #[must_use]
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    #[must_use]
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    #[must_use]
+    pub fn addrs(&self) -> &[Address] {
+        &self.addrs
+    }
+
+    /// Addresses over `transports`, ordered by the position of their transport in it, ipv4
+    /// before ipv6.
+    pub fn preferred_addrs<'a>(
+        &'a self,
+        transports: &'a [Transport],
+    ) -> impl Iterator<Item = &'a Multiaddr> + 'a {
+        transports.iter().flat_map(move |&transport| {
+            let of_transport = move |ipv6| {
+                self.addrs.iter().filter(move |a| a.transport == transport && a.ipv6 == ipv6)
+            };
+            of_transport(false).chain(of_transport(true)).map(|a| &a.addr)
+        })
+    }
+
+    #[must_use]
+    pub fn health(&self) -> Health {
+        self.health
+    }
+
+    fn mark_alive(&mut self) {
+        self.health = Health::Alive;
+    }
+
+    fn mark_failed(&mut self) {
+        if !matches!(self.health, Health::Suspect(_)) {
+            self.health = Health::Suspect(Instant::now());
+        }
+    }
+
+    /// Suspect routes still count as alive for `grace`.
+    #[must_use]
+    pub fn is_alive(&self, grace: Duration) -> bool {
+        !matches!(self.health, Health::Suspect(since) if since.elapsed() > grace)
     }
 
     #[must_use]
@@ -210,6 +617,10 @@ impl Route {
 mod tests {
     use {super::*, libp2p::identity};
 
+    fn route(id: U256) -> Route {
+        Route { id, addrs: Vec::new(), health: Health::Unknown, weight: 1 }
+    }
+
     #[test]
     fn convert_peer_id() {
         let key = ed25519::Keypair::generate();
@@ -223,7 +634,8 @@ mod tests {
     fn closest_correct_len() {
         let count = 10;
         let table = RoutingTable {
-            routes: (0..count).map(|i| Route { id: i.into(), addr: Multiaddr::empty() }).collect(),
+            routes: (0..count).map(|i| route(i.into())).collect(),
+            ..Default::default()
         };
 
         assert_eq!(table.closest(&[]).count(), count);
@@ -232,17 +644,111 @@ mod tests {
     #[test]
     fn wrap_around() {
         let table = RoutingTable {
-            routes: vec![
-                Route { id: U256::from(2), addr: Multiaddr::empty() },
-                Route { id: U256::MAX / 2, addr: Multiaddr::empty() },
-                Route { id: U256::MAX, addr: Multiaddr::empty() },
-            ],
+            routes: vec![route(U256::from(2)), route(U256::MAX / 2), route(U256::MAX)],
+            ..Default::default()
         };
 
-        assert_eq!(table.closest_low(1.into()).collect::<Vec<_>>(), vec![
-            &table.routes[0],
-            &table.routes[2],
-            &table.routes[1]
+        assert_eq!(
+            table.closest_low(1.into()).collect::<Vec<_>>(),
+            vec![&table.routes[0], &table.routes[2], &table.routes[1]]
+        );
+    }
+
+    #[test]
+    fn suspect_loses_slot_after_grace() {
+        let keys = (0..3).map(|_| ed25519::Keypair::generate().public()).collect::<Vec<_>>();
+        let mut table = RoutingTable { suspect_grace: Duration::ZERO, ..Default::default() };
+        table.bulk_insert(keys.iter().map(|k| Route::new(k.clone(), Multiaddr::empty())));
+
+        let first = table.closest(&[]).next().unwrap().peer_id();
+        table.report_failure(first);
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert_eq!(table.closest_alive(&[]).count(), 2);
+        assert!(table.closest_alive(&[]).all(|r| r.peer_id() != first));
+        assert!(!table.is_alive(first));
+        assert_eq!(table.closest(&[]).next().unwrap().peer_id(), first);
+
+        table.report_alive(first);
+        assert!(table.is_alive(first));
+        assert_eq!(table.closest_alive(&[]).next().unwrap().peer_id(), first);
+    }
+
+    #[test]
+    fn virtual_placement_yields_each_route_once() {
+        let keys = (0..10).map(|_| ed25519::Keypair::generate().public()).collect::<Vec<_>>();
+        let mut table = RoutingTable::default();
+        table.bulk_insert(keys.iter().map(|k| Route::new(k.clone(), Multiaddr::empty())));
+        table.set_placement(Placement::Virtual { beacon: [7; 32], points: 16 });
+
+        let mut closest = table.closest(b"topic").map(Route::peer_id).collect::<Vec<_>>();
+        assert_eq!(closest.len(), keys.len());
+        closest.sort();
+        closest.dedup();
+        assert_eq!(closest.len(), keys.len());
+    }
+
+    #[test]
+    fn closest_to_key_follows_placement() {
+        let keys = (0..10).map(|_| ed25519::Keypair::generate().public()).collect::<Vec<_>>();
+        let mut table = RoutingTable::default();
+        table.bulk_insert(keys.iter().map(|k| Route::new(k.clone(), Multiaddr::empty())));
+
+        for placement in [Placement::Key, Placement::Virtual { beacon: [3; 32], points: 8 }] {
+            table.set_placement(placement);
+            let key = *blake3::hash(b"topic").as_bytes();
+            let by_key = table.closest_to_key(key).map(Route::peer_id).collect::<Vec<_>>();
+            let by_data = table.closest(b"topic").map(Route::peer_id).collect::<Vec<_>>();
+            assert_eq!(by_key, by_data);
+
+            let id = U256::from(key);
+            let nearest = placement.distance(id, table.route(by_key[0]).unwrap());
+            assert!(table.iter().all(|r| nearest <= placement.distance(id, r)));
+        }
+    }
+
+    #[test]
+    fn heavier_route_wins_more_data() {
+        let keys = (0..2).map(|_| ed25519::Keypair::generate().public()).collect::<Vec<_>>();
+        let mut table = RoutingTable::default();
+        table.bulk_insert([
+            Route::new(keys[0].clone(), Multiaddr::empty()).with_weight(9),
+            Route::new(keys[1].clone(), Multiaddr::empty()).with_weight(1),
         ]);
+        table.set_placement(Placement::Virtual { beacon: [0; 32], points: 32 });
+
+        let heavy = PublicKey::from(keys[0].clone()).to_peer_id();
+        let won = (0..1000u32)
+            .filter(|i| table.closest(&i.to_le_bytes()).next().unwrap().peer_id() == heavy)
+            .count();
+        assert!(won > 700, "{won}");
+    }
+
+    #[test]
+    fn zero_weight_route_is_not_placed() {
+        let keys = (0..3).map(|_| ed25519::Keypair::generate().public()).collect::<Vec<_>>();
+        let mut table = RoutingTable::default();
+        table.bulk_insert(keys.iter().enumerate().map(|(i, k)| {
+            Route::new(k.clone(), Multiaddr::empty()).with_weight(u32::from(i != 0))
+        }));
+        let idle = PublicKey::from(keys[0].clone()).to_peer_id();
+        table.set_placement(Placement::Virtual { beacon: [5; 32], points: 8 });
+
+        assert!(table.route(idle).is_some());
+        assert_eq!(table.closest(b"topic").count(), 2);
+        assert!(table.closest(b"topic").all(|r| r.peer_id() != idle));
+    }
+
+    #[test]
+    fn addresses_in_preference_order() {
+        let addrs = ["/ip6/::1/tcp/8800", "/ip4/127.0.0.1/tcp/8900/ws", "/ip4/127.0.0.1/tcp/8800"]
+            .map(|a| a.parse::<Multiaddr>().unwrap());
+        let route = Route::with_addrs(ed25519::Keypair::generate().public(), addrs.clone());
+
+        let preferred = route.preferred_addrs(&[Transport::Tcp, Transport::Ws]).collect::<Vec<_>>();
+        assert_eq!(preferred, vec![&addrs[2], &addrs[0], &addrs[1]]);
+
+        let preferred = route.preferred_addrs(&[Transport::Ws]).collect::<Vec<_>>();
+        assert_eq!(preferred, vec![&addrs[1]]);
     }
 }
@@ -0,0 +1,491 @@
+//! Iterative lookup for networks too large for every node to know every other one. Nodes are
+//! kept in k-buckets and found by asking the closest known nodes for closer ones until nothing
+//! closer turns up.
+//!
+//! Candidates are ordered by the distance [`RoutingTable::closest`] uses under the same
+//! [`Placement`], so a lookup of `blake3(topic)` ends at the same replicators as the full table
+//! would pick. Buckets are always organized by key distance.
+
+use {
+    crate::{
+        shortest_distance, try_peer_id_to_ed, Placement, Route, RoutingTable, DEFAULT_SUSPECT_GRACE,
+    },
+    component_utils::Codec,
+    instant::Duration,
+    libp2p::{identity::ed25519, Multiaddr, PeerId},
+    primitive_types::U256,
+    rpc::CallId,
+};
+
+/// Prefix of FIND_NODE rpc requests, protocols dispatched by prefix never get this far.
+pub const FIND_NODE_PREFIX: u8 = u8::MAX - 2;
+/// Size of a bucket and of the lookup result.
+pub const K: usize = 20;
+/// Requests a single lookup keeps in flight.
+pub const ALPHA: usize = 3;
+
+#[derive(Codec)]
+struct NodeRecord<'a> {
+    id: [u8; 32],
+    addrs: Vec<&'a [u8]>,
+    weight: u32,
+}
+
+/// Key under which [`RoutingTable::closest`] places `data`.
+#[must_use]
+pub fn data_key(data: &[u8]) -> [u8; 32] {
+    *blake3::hash(data).as_bytes()
+}
+
+#[must_use]
+pub fn find_node_request(target: [u8; 32]) -> Vec<u8> {
+    (FIND_NODE_PREFIX, target).to_bytes()
+}
+
+/// Target of the FIND_NODE request, `None` if `request` is something else.
+#[must_use]
+pub fn parse_find_node(request: &[u8]) -> Option<[u8; 32]> {
+    match <(u8, [u8; 32])>::decode(&mut &*request)? {
+        (FIND_NODE_PREFIX, target) => Some(target),
+        _ => None,
+    }
+}
+
+/// Answer to FIND_NODE, the closest [`K`] routes to `target` out of `table`.
+#[must_use]
+pub fn answer_find_node(table: &RoutingTable, target: [u8; 32]) -> Vec<u8> {
+    encode_routes(table.closest_to_key(target).take(K))
+}
+
+fn encode_routes<'a>(routes: impl IntoIterator<Item = &'a Route>) -> Vec<u8> {
+    routes
+        .into_iter()
+        .map(|r| NodeRecord {
+            id: r.id.into(),
+            addrs: r.addrs.iter().map(|a| a.addr.as_ref()).collect(),
+            weight: r.weight,
+        })
+        .collect::<Vec<_>>()
+        .to_bytes()
+}
+
+fn decode_routes(response: &[u8]) -> Option<Vec<Route>> {
+    let records = Vec::<NodeRecord>::decode(&mut &*response)?;
+    let routes = records
+        .into_iter()
+        .filter_map(|r| {
+            let id = ed25519::PublicKey::try_from_bytes(&r.id).ok()?;
+            let addrs = r.addrs.into_iter().filter_map(|a| Multiaddr::try_from(a.to_vec()).ok());
+            Some(Route::with_addrs(id, addrs).with_weight(r.weight))
+        })
+        .take(K)
+        .collect();
+    Some(routes)
+}
+
+/// Routes bucketed by the magnitude of their ring distance from the local key, each bucket
+/// holds at most [`K`] routes, so nodes know their surroundings fully and the rest sparsely.
+/// Routes that stay in a bucket are preferred over new ones unless they stopped responding.
+#[derive(Clone)]
+pub struct KBuckets {
+    local: U256,
+    buckets: Vec<Vec<Route>>,
+    /// How long a failing route keeps its place in a full bucket.
+    pub suspect_grace: Duration,
+}
+
+impl KBuckets {
+    #[must_use]
+    pub fn new(local: ed25519::PublicKey) -> Self {
+        Self {
+            local: U256::from(local.to_bytes()),
+            buckets: (0..256).map(|_| Vec::new()).collect(),
+            suspect_grace: DEFAULT_SUSPECT_GRACE,
+        }
+    }
+
+    fn bucket(&self, id: U256) -> Option<usize> {
+        let distance = shortest_distance(self.local, id);
+        (!distance.is_zero()).then(|| 255 - distance.leading_zeros() as usize)
+    }
+
+    /// Returns false if the bucket is full of live routes or the route is us.
+    pub fn insert(&mut self, mut route: Route) -> bool {
+        let Some(index) = self.bucket(route.id) else {
+            return false;
+        };
+        let grace = self.suspect_grace;
+        let bucket = &mut self.buckets[index];
+
+        if let Some(existing) = bucket.iter_mut().find(|r| r.id == route.id) {
+            // same address is most likely the same process
+            if existing.addrs == route.addrs {
+                route.health = existing.health;
+            }
+            *existing = route;
+            return true;
+        }
+
+        if bucket.len() < K {
+            bucket.push(route);
+            return true;
+        }
+
+        let Some(dead) = bucket.iter_mut().find(|r| !r.is_alive(grace)) else {
+            return false;
+        };
+        *dead = route;
+        true
+    }
+
+    pub fn remove(&mut self, peer: PeerId) -> Option<Route> {
+        let id = U256::from(try_peer_id_to_ed(peer)?);
+        let bucket = &mut self.buckets[self.bucket(id)?];
+        let index = bucket.iter().position(|r| r.id == id)?;
+        Some(bucket.swap_remove(index))
+    }
+
+    #[must_use]
+    pub fn route(&self, peer: PeerId) -> Option<&Route> {
+        let id = U256::from(try_peer_id_to_ed(peer)?);
+        self.buckets[self.bucket(id)?].iter().find(|r| r.id == id)
+    }
+
+    fn route_mut(&mut self, peer: PeerId) -> Option<&mut Route> {
+        let id = U256::from(try_peer_id_to_ed(peer)?);
+        let index = self.bucket(id)?;
+        self.buckets[index].iter_mut().find(|r| r.id == id)
+    }
+
+    pub fn report_alive(&mut self, peer: PeerId) {
+        if let Some(route) = self.route_mut(peer) {
+            route.mark_alive();
+        }
+    }
+
+    pub fn report_failure(&mut self, peer: PeerId) {
+        if let Some(route) = self.route_mut(peer) {
+            route.mark_failed();
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Route> {
+        self.buckets.iter().flatten()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(Vec::len).sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.buckets.iter().all(Vec::is_empty)
+    }
+
+    /// Up to [`K`] live routes closest to `target`.
+    #[must_use]
+    pub fn closest(&self, target: [u8; 32]) -> Vec<&Route> {
+        let target = U256::from(target);
+        let mut routes = self.iter().filter(|r| r.is_alive(self.suspect_grace)).collect::<Vec<_>>();
+        routes.sort_unstable_by_key(|r| shortest_distance(target, r.id));
+        routes.truncate(K);
+        routes
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Waiting,
+    Querying,
+    Answered,
+    Failed,
+}
+
+/// State of one iterative lookup, independent of how the requests are sent.
+#[derive(Clone)]
+pub struct Lookup {
+    target: U256,
+    placement: Placement,
+    // sorted by the distance to the target
+    candidates: Vec<(U256, Route, State)>,
+}
+
+impl Lookup {
+    #[must_use]
+    pub fn new(
+        target: [u8; 32],
+        placement: Placement,
+        seeds: impl IntoIterator<Item = Route>,
+    ) -> Self {
+        let mut lookup = Self { target: U256::from(target), placement, candidates: Vec::new() };
+        seeds.into_iter().for_each(|r| lookup.add(r));
+        lookup
+    }
+
+    #[must_use]
+    pub fn target(&self) -> [u8; 32] {
+        self.target.into()
+    }
+
+    fn add(&mut self, route: Route) {
+        if self.candidates.iter().any(|(_, r, _)| r.id == route.id) {
+            return;
+        }
+        let distance = self.placement.distance(self.target, &route);
+        let index = self.candidates.partition_point(|(d, ..)| *d < distance);
+        self.candidates.insert(index, (distance, route, State::Waiting));
+    }
+
+    fn state_mut(&mut self, peer: PeerId) -> Option<&mut State> {
+        let id = U256::from(try_peer_id_to_ed(peer)?);
+        self.candidates.iter_mut().find(|(_, r, _)| r.id == id).map(|(.., s)| s)
+    }
+
+    fn closest_candidates(&self) -> impl Iterator<Item = &(U256, Route, State)> {
+        self.candidates.iter().filter(|(.., s)| *s != State::Failed).take(K)
+    }
+
+    /// Peers to ask next, they are considered queried from now on.
+    pub fn next_queries(&mut self) -> Vec<PeerId> {
+        let in_flight = self.candidates.iter().filter(|(.., s)| *s == State::Querying).count();
+        let mut queries = Vec::new();
+        for (_, route, state) in
+            self.candidates.iter_mut().filter(|(.., s)| *s != State::Failed).take(K)
+        {
+            if in_flight + queries.len() >= ALPHA {
+                break;
+            }
+            if *state == State::Waiting {
+                *state = State::Querying;
+                queries.push(route.peer_id());
+            }
+        }
+        queries
+    }
+
+    pub fn on_answer(&mut self, peer: PeerId, routes: impl IntoIterator<Item = Route>) {
+        let Some(state) = self.state_mut(peer) else {
+            return;
+        };
+        *state = State::Answered;
+        routes.into_iter().for_each(|r| self.add(r));
+    }
+
+    pub fn on_failure(&mut self, peer: PeerId) {
+        if let Some(state) = self.state_mut(peer) {
+            *state = State::Failed;
+        }
+    }
+
+    /// All of the closest [`K`] responsive candidates answered.
+    #[must_use]
+    pub fn is_done(&self) -> bool {
+        self.closest_candidates().all(|(.., s)| *s == State::Answered)
+    }
+
+    #[must_use]
+    pub fn route(&self, peer: PeerId) -> Option<&Route> {
+        let id = U256::from(try_peer_id_to_ed(peer)?);
+        self.candidates.iter().map(|(_, r, _)| r).find(|r| r.id == id)
+    }
+
+    /// Closest routes that answered, closest first.
+    #[must_use]
+    pub fn into_closest(self) -> Vec<Route> {
+        self.candidates
+            .into_iter()
+            .filter(|(.., s)| *s == State::Answered)
+            .map(|(_, r, _)| r)
+            .take(K)
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LookupId(usize);
+
+/// Lookups sent over [`rpc::Behaviour`] with the buckets they learn into. The owner passes
+/// responses to [`Lookups::on_response`] before handling them itself.
+#[derive(Clone)]
+pub struct Lookups {
+    pub buckets: KBuckets,
+    /// Has to match the placement of the network for lookups to end at the replicators.
+    pub placement: Placement,
+    local: U256,
+    active: Vec<(LookupId, Lookup)>,
+    calls: Vec<(CallId, LookupId, PeerId)>,
+    finished: Vec<(LookupId, Vec<Route>)>,
+    next_id: usize,
+}
+
+impl Lookups {
+    #[must_use]
+    pub fn new(local: ed25519::PublicKey) -> Self {
+        Self {
+            local: U256::from(local.to_bytes()),
+            buckets: KBuckets::new(local),
+            placement: Placement::Key,
+            active: Vec::new(),
+            calls: Vec::new(),
+            finished: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Learns the boot nodes and looks up our own key so that the nodes around us learn about
+    /// us and we about them.
+    pub fn bootstrap(
+        &mut self,
+        boot_nodes: impl IntoIterator<Item = Route>,
+        rpc: &mut rpc::Behaviour,
+    ) -> LookupId {
+        for route in boot_nodes {
+            self.buckets.insert(route);
+        }
+        self.start(self.local.into(), rpc)
+    }
+
+    /// Finds the [`K`] nodes closest to `target`, use [`data_key`] for topics.
+    pub fn start(&mut self, target: [u8; 32], rpc: &mut rpc::Behaviour) -> LookupId {
+        let id = LookupId(self.next_id);
+        self.next_id += 1;
+        let seeds = self.buckets.closest(target).into_iter().cloned();
+        self.active.push((id, Lookup::new(target, self.placement, seeds)));
+        self.drive(rpc);
+        id
+    }
+
+    /// Returns false if the call does not belong to a lookup. `response` is `None` when the
+    /// call failed.
+    pub fn on_response(
+        &mut self,
+        peer: PeerId,
+        call: CallId,
+        response: Option<&[u8]>,
+        rpc: &mut rpc::Behaviour,
+    ) -> bool {
+        let Some(index) = self.calls.iter().position(|&(c, _, p)| c == call && p == peer) else {
+            return false;
+        };
+        let (_, lookup_id, _) = self.calls.swap_remove(index);
+        let Some((_, lookup)) = self.active.iter_mut().find(|(id, _)| *id == lookup_id) else {
+            return true;
+        };
+
+        match response.and_then(decode_routes) {
+            Some(routes) => {
+                self.buckets.report_alive(peer);
+                let local = self.local;
+                let routes = routes.into_iter().filter(|r| r.id != local).collect::<Vec<_>>();
+                for route in &routes {
+                    self.buckets.insert(route.clone());
+                }
+                lookup.on_answer(peer, routes);
+            }
+            None => {
+                self.buckets.report_failure(peer);
+                lookup.on_failure(peer);
+            }
+        }
+
+        self.drive(rpc);
+        true
+    }
+
+    /// Next lookup that finished with its closest routes.
+    pub fn take_finished(&mut self) -> Option<(LookupId, Vec<Route>)> {
+        self.finished.pop()
+    }
+
+    /// Looks into the buckets and all ongoing lookups.
+    #[must_use]
+    pub fn route(&self, peer: PeerId) -> Option<&Route> {
+        self.buckets.route(peer).or_else(|| self.active.iter().find_map(|(_, l)| l.route(peer)))
+    }
+
+    fn drive(&mut self, rpc: &mut rpc::Behaviour) {
+        for (id, lookup) in &mut self.active {
+            loop {
+                let queries = lookup.next_queries();
+                if queries.is_empty() {
+                    break;
+                }
+
+                let request = find_node_request(lookup.target());
+                for peer in queries {
+                    match rpc.request(peer, request.as_slice()) {
+                        Ok(call) => self.calls.push((call, *id, peer)),
+                        Err(_) => lookup.on_failure(peer),
+                    }
+                }
+            }
+        }
+
+        let mut i = 0;
+        while let Some((id, lookup)) = self.active.get(i) {
+            if !lookup.is_done() {
+                i += 1;
+                continue;
+            }
+
+            // queries outside of the closest candidates do not matter anymore
+            let id = *id;
+            self.calls.retain(|&(call, lookup_id, _)| {
+                if lookup_id == id {
+                    rpc.cancel(call);
+                }
+                lookup_id != id
+            });
+            let (id, lookup) = self.active.swap_remove(i);
+            self.finished.push((id, lookup.into_closest()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, libp2p::identity::PublicKey};
+
+    /// Answers lookups from full membership, each node knowing only its bucket neighbourhood.
+    #[test]
+    fn lookup_converges_to_full_table() {
+        let keys = (0..300).map(|_| ed25519::Keypair::generate().public()).collect::<Vec<_>>();
+        let routes = keys.iter().map(|k| Route::new(k.clone(), Multiaddr::empty()));
+        let mut full = RoutingTable::default();
+        full.bulk_insert(routes.clone());
+
+        let views = keys
+            .iter()
+            .map(|k| {
+                let mut buckets = KBuckets::new(k.clone());
+                for route in routes.clone() {
+                    buckets.insert(route);
+                }
+                (PublicKey::from(k.clone()).to_peer_id(), buckets)
+            })
+            .collect::<Vec<_>>();
+
+        let target = data_key(b"topic");
+        let seeds = views[0].1.closest(target).into_iter().cloned();
+        let mut lookup = Lookup::new(target, Placement::Key, seeds);
+        while !lookup.is_done() {
+            for peer in lookup.next_queries() {
+                let (_, view) = views.iter().find(|(p, _)| *p == peer).unwrap();
+                let answer = encode_routes(view.closest(target));
+                lookup.on_answer(peer, decode_routes(&answer).unwrap());
+            }
+        }
+
+        let found = lookup.into_closest().iter().map(Route::peer_id).collect::<Vec<_>>();
+        let expected = full.closest(b"topic").take(K).map(Route::peer_id).collect::<Vec<_>>();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn find_node_roundtrip() {
+        let target = data_key(b"topic");
+        assert_eq!(parse_find_node(&find_node_request(target)), Some(target));
+        assert_eq!(parse_find_node(&[0; 33]), None);
+    }
+}
@@ -42,6 +42,16 @@ pub struct Behaviour {
     pending_requests: Vec<Arc<StreamRequest>>,
     error_streams: FuturesUnordered<component_utils::ClosingStream<libp2p::swarm::Stream>>,
     buffer: Arc<spin::Mutex<[u8; 1 << 16]>>,
+    refused_streams: u64,
+}
+
+/// Snapshot of the routing state, `refused_streams` only grows.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub relayed_channels: usize,
+    pub pending_incoming: usize,
+    pub pending_outgoing: usize,
+    pub refused_streams: u64,
 }
 
 impl Behaviour {
@@ -57,6 +67,7 @@ impl Behaviour {
             pending_requests: Default::default(),
             error_streams: Default::default(),
             buffer: Arc::new(spin::Mutex::new([0; 1 << 16])),
+            refused_streams: 0,
         }
     }
 
@@ -64,6 +75,16 @@ impl Behaviour {
         &self.config
     }
 
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        Stats {
+            relayed_channels: self.router.len(),
+            pending_incoming: self.pending_connections.len(),
+            pending_outgoing: self.pending_requests.len(),
+            refused_streams: self.refused_streams,
+        }
+    }
+
     /// !!! Path is in reverse order of relay jumps (last element denotes entry node, first element
     /// denotes destination) !!!
     /// # Panics
@@ -117,6 +138,7 @@ impl Behaviour {
             .count();
         if valid_stream_count + self.pending_connections.len() > self.config.max_streams {
             log::info!("too many streams");
+            self.refused_streams += 1;
             if self.error_streams.len() > self.config.max_error_streams {
                 log::warn!("too many erroring streams");
                 return;
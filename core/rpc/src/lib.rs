@@ -1,34 +1,51 @@
 #![feature(extract_if)]
 #![feature(let_chains)]
-#![feature(iter_collect_into)]
 #![feature(type_alias_impl_trait)]
 #![feature(impl_trait_in_assoc_type)]
 #![feature(macro_metavar_expr)]
 
 use {
     component_utils::{Codec, FindAndRemove, PacketReader, PacketWriter, Reminder},
+    instant::{Duration, Instant},
     libp2p::{
-        futures::{stream::SelectAll, StreamExt},
+        futures::{stream::SelectAll, FutureExt, StreamExt},
         swarm::{ConnectionId, NetworkBehaviour, StreamUpgradeError},
         PeerId,
     },
-    std::{io, sync::Arc, task::Poll, time::Duration},
+    std::{collections::VecDeque, io, sync::Arc, task::Poll},
 };
 
 component_utils::decl_stream_protocol!(PROTOCOL_NAME = "rpc");
 
+/// Amount of chunks a responder can send before the caller grants more credit.
+pub const STREAM_WINDOW: usize = 16;
+/// Roundtrip times kept for hedging percentiles.
+const LATENCY_SAMPLES: usize = 64;
+
 pub struct Stream {
     writer: PacketWriter,
     reader: PacketReader,
     inner: Option<libp2p::Stream>,
+    /// Outgoing streams free their slot in `streaming` once dropped.
+    _slot: Option<streaming::Slot>,
     peer: PeerId,
-    last_packet: std::time::Instant,
+    last_packet: Instant,
 }
 
-type IsRequest = bool;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec)]
+pub enum PacketKind {
+    Response,
+    Request,
+    Chunk,
+    StreamEnd,
+    /// Payload is the amount of chunks the caller consumed.
+    Credit,
+    /// Caller is no longer interested in the response.
+    Cancel,
+}
 
 impl libp2p::futures::Stream for Stream {
-    type Item = (PeerId, io::Result<(CallId, Vec<u8>, IsRequest)>);
+    type Item = (PeerId, io::Result<(CallId, Vec<u8>, PacketKind)>);
 
     fn poll_next(
         mut self: std::pin::Pin<&mut Self>,
@@ -53,22 +70,22 @@ impl libp2p::futures::Stream for Stream {
             }
         };
 
-        let Some((call, is_request, Reminder(payload))) = <_>::decode(&mut &*read) else {
+        let Some((call, kind, Reminder(payload))) = <_>::decode(&mut &*read) else {
             this.inner.take();
             log::warn!("invalid packet from {}, {:?}", this.peer, read);
             return Poll::Ready(Some((this.peer, Err(io::ErrorKind::InvalidData.into()))));
         };
 
-        this.last_packet = std::time::Instant::now();
-        Poll::Ready(Some((this.peer, Ok((call, payload.to_vec(), is_request)))))
+        this.last_packet = Instant::now();
+        Poll::Ready(Some((this.peer, Ok((call, payload.to_vec(), kind)))))
     }
 }
 
 impl Stream {
-    pub fn write(&mut self, call: CallId, payload: &[u8], is_request: bool) -> io::Result<()> {
-        self.last_packet = std::time::Instant::now();
+    pub fn write(&mut self, call: CallId, payload: &[u8], kind: PacketKind) -> io::Result<()> {
+        self.last_packet = Instant::now();
         self.writer
-            .write_packet(&(call, is_request, Reminder(payload)))
+            .write_packet(&(call, kind, Reminder(payload)))
             .ok_or(io::ErrorKind::OutOfMemory)?;
         Ok(())
     }
@@ -77,44 +94,395 @@ impl Stream {
         self.inner.take();
     }
 
-    fn new(peer: PeerId, stream: libp2p::Stream, buffer_size: usize) -> Self {
+    fn new(
+        peer: PeerId,
+        stream: libp2p::Stream,
+        slot: Option<streaming::Slot>,
+        buffer_size: usize,
+    ) -> Self {
         Self {
             writer: PacketWriter::new(buffer_size),
             reader: PacketReader::default(),
             inner: Some(stream),
+            _slot: slot,
             peer,
-            last_packet: std::time::Instant::now(),
+            last_packet: Instant::now(),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Including the first attempt.
+    pub max_attempts: usize,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    #[must_use]
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let shift = u32::try_from(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        let factor = 1u32.checked_shl(shift).unwrap_or(u32::MAX);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequestOptions {
+    pub retry: RetryPolicy,
+    /// When set, the request is also sent to the next candidate if the current one does not
+    /// answer within this percentile of recent roundtrip times. First response wins.
+    pub hedge_percentile: Option<u8>,
+}
+
+/// Call that moves between candidate peers on failure or when hedged.
+struct ManagedCall {
+    call: CallId,
+    packet: Vec<u8>,
+    candidates: VecDeque<PeerId>,
+    options: RequestOptions,
+    attempts: usize,
+    retry_at: Option<Instant>,
+    hedge_at: Option<Instant>,
+}
+
+/// Streamed response on the responder side, chunks wait here until the caller grants credit.
+struct ChunkedResponse {
+    peer: PeerId,
+    call: CallId,
+    credit: usize,
+    queue: VecDeque<Vec<u8>>,
+    finished: bool,
+}
+
 #[derive(Default)]
 pub struct Behaviour {
     config: Config,
     streams: SelectAll<Stream>,
-    pending_requests: Vec<(PeerId, CallId, Vec<u8>, std::time::Instant)>,
-    ongoing_requests: Vec<(CallId, PeerId, std::time::Instant)>,
+    pending_requests: Vec<(PeerId, CallId, Vec<u8>, Instant)>,
+    ongoing_requests: Vec<(CallId, PeerId, Instant)>,
+    managed_calls: Vec<ManagedCall>,
+    latencies: VecDeque<Duration>,
+    /// Wakes us at the deadline it was armed for.
+    timer: Option<(Instant, futures_timer::Delay)>,
     pending_repsonses: Vec<(PeerId, CallId, Vec<u8>)>,
-    events: Vec<Event>,
+    chunked_responses: Vec<ChunkedResponse>,
+    /// Chunks consumed since we last granted credit, per streamed call.
+    consumed_chunks: Vec<(CallId, usize)>,
+    events: VecDeque<Event>,
+    counters: Stats,
 
     streaming: streaming::Behaviour,
 }
 
+/// Snapshot of the queues, `timeouts`, `retries` and `hedges` only grow.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub streams: usize,
+    pub in_flight: usize,
+    pub queued_requests: usize,
+    pub queued_responses: usize,
+    pub timeouts: u64,
+    pub retries: u64,
+    pub hedges: u64,
+}
+
 impl Behaviour {
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self { config, ..Default::default() }
+    }
+
     pub fn request(
         &mut self,
         peer: PeerId,
         packet: impl AsRef<[u8]> + Into<Vec<u8>>,
     ) -> io::Result<CallId> {
         let call = CallId::new();
-        if let Some(stream) = self.streams.iter_mut().find(|s| s.peer == peer) {
-            self.ongoing_requests.push((call, peer, std::time::Instant::now()));
-            stream.write(call, packet.as_ref(), true)?;
-        } else if !self.streaming.is_resolving_stream_for(peer) {
+        self.send(peer, call, packet)?;
+        Ok(call)
+    }
+
+    /// Sends to the first candidate and moves to the next ones according to `options`. All
+    /// attempts share the returned [`CallId`] and only one response is reported.
+    pub fn request_with(
+        &mut self,
+        candidates: impl IntoIterator<Item = PeerId>,
+        packet: impl Into<Vec<u8>>,
+        options: RequestOptions,
+    ) -> io::Result<CallId> {
+        let mut candidates = candidates.into_iter().collect::<VecDeque<_>>();
+        let peer = candidates.pop_front().ok_or(io::ErrorKind::NotFound)?;
+        let packet = packet.into();
+        let call = CallId::new();
+        self.send(peer, call, packet.as_slice())?;
+
+        let hedge_at = options
+            .hedge_percentile
+            .filter(|_| !candidates.is_empty())
+            .map(|p| Instant::now() + self.hedge_delay(p));
+        self.managed_calls.push(ManagedCall {
+            call,
+            packet,
+            candidates,
+            options,
+            attempts: 1,
+            retry_at: None,
+            hedge_at,
+        });
+        Ok(call)
+    }
+
+    /// Drops the call and tells the peers it was sent to that they can stop responding. No
+    /// events are reported for the call afterwards.
+    pub fn cancel(&mut self, call: CallId) {
+        self.managed_calls.retain(|m| m.call != call);
+        self.cancel_attempts(call, None);
+    }
+
+    fn cancel_attempts(&mut self, call: CallId, except: Option<PeerId>) {
+        self.pending_requests.retain(|(_, c, ..)| *c != call);
+        self.consumed_chunks.retain(|(c, _)| *c != call);
+        for (_, peer, _) in
+            self.ongoing_requests.extract_if(|&mut (c, p, _)| c == call && Some(p) != except)
+        {
+            if let Some(stream) = self.streams.iter_mut().find(|s| s.peer == peer) {
+                _ = stream.write(call, &[], PacketKind::Cancel);
+            }
+        }
+    }
+
+    /// Requests over the in-flight limit wait in a queue, [`io::ErrorKind::WouldBlock`] is
+    /// returned once that is full too.
+    fn send(
+        &mut self,
+        peer: PeerId,
+        call: CallId,
+        packet: impl AsRef<[u8]> + Into<Vec<u8>>,
+    ) -> io::Result<()> {
+        let queued = self.pending_requests.iter().filter(|(p, ..)| *p == peer).count();
+        if queued == 0
+            && self.in_flight(peer) < self.config.max_in_flight_per_peer
+            && let Some(stream) = self.streams.iter_mut().find(|s| s.peer == peer)
+            && stream.write(call, packet.as_ref(), PacketKind::Request).is_ok()
+        {
+            self.ongoing_requests.push((call, peer, Instant::now()));
+            return Ok(());
+        }
+
+        if queued >= self.config.max_queued_per_peer {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        if !self.streams.iter().any(|s| s.peer == peer)
+            && !self.streaming.is_resolving_stream_for(peer)
+        {
             self.streaming.create_stream(peer);
-            self.pending_requests.push((peer, call, packet.into(), std::time::Instant::now()));
         }
-        Ok(call)
+        self.pending_requests.push((peer, call, packet.into(), Instant::now()));
+        Ok(())
+    }
+
+    /// Requests sent to the peer and still waiting for the response.
+    #[must_use]
+    pub fn in_flight(&self, peer: PeerId) -> usize {
+        self.ongoing_requests.iter().filter(|(_, p, _)| *p == peer).count()
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        Stats {
+            streams: self.streams.len(),
+            in_flight: self.ongoing_requests.len(),
+            queued_requests: self.pending_requests.len(),
+            queued_responses: self.pending_repsonses.len()
+                + self.chunked_responses.iter().map(|r| r.queue.len()).sum::<usize>(),
+            ..self.counters
+        }
+    }
+
+    /// Responses to the peer could not be written right away. Handlers can use this to shed
+    /// load before responses start getting dropped.
+    #[must_use]
+    pub fn is_congested(&self, peer: PeerId) -> bool {
+        self.pending_repsonses.iter().any(|(p, ..)| *p == peer)
+    }
+
+    fn flush_requests(&mut self) {
+        let limit = self.config.max_in_flight_per_peer;
+        let (streams, ongoing) = (&mut self.streams, &mut self.ongoing_requests);
+        let mut blocked = Vec::new();
+        self.pending_requests.retain(|&(peer, call, ref packet, time)| {
+            if blocked.contains(&peer) {
+                return true;
+            }
+
+            let in_flight = ongoing.iter().filter(|(_, p, _)| *p == peer).count();
+            let Some(stream) = streams.iter_mut().find(|s| s.peer == peer) else {
+                blocked.push(peer);
+                return true;
+            };
+
+            // requests to one peer leave in the order they were made
+            if in_flight >= limit || stream.write(call, packet, PacketKind::Request).is_err() {
+                blocked.push(peer);
+                return true;
+            }
+
+            ongoing.push((call, peer, time));
+            false
+        });
+    }
+
+    fn flush_responses(&mut self) {
+        let streams = &mut self.streams;
+        let mut blocked = Vec::new();
+        self.pending_repsonses.retain(|(peer, call, payload)| {
+            if blocked.contains(peer) {
+                return true;
+            }
+
+            let Some(stream) = streams.iter_mut().find(|s| s.peer == *peer) else {
+                return true;
+            };
+
+            if stream.write(*call, payload, PacketKind::Response).is_err() {
+                // writer is full, keep the order and try again once it drains
+                blocked.push(*peer);
+                return true;
+            }
+
+            false
+        });
+    }
+
+    fn hedge_delay(&self, percentile: u8) -> Duration {
+        if self.latencies.is_empty() {
+            return self.config.request_timeout / 4;
+        }
+
+        let mut sorted = self.latencies.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        sorted[(sorted.len() - 1) * usize::from(percentile.min(100)) / 100]
+    }
+
+    fn record_latency(&mut self, latency: Duration) {
+        if self.latencies.len() == LATENCY_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+    }
+
+    /// Managed calls are retried while they have attempts left, the rest report the error. The
+    /// failed peer is tried again only once no untried candidate is left, after the backoff.
+    fn fail_attempt(&mut self, peer: PeerId, call: CallId, error: Arc<streaming::Error>) {
+        let Some(index) = self.managed_calls.iter().position(|m| m.call == call) else {
+            self.events.push_back(Event::Response(peer, call, Err(error)));
+            return;
+        };
+
+        // hedged attempt can still succeed
+        if self.ongoing_requests.iter().any(|(c, ..)| *c == call)
+            || self.pending_requests.iter().any(|(_, c, ..)| *c == call)
+        {
+            return;
+        }
+
+        let managed = &mut self.managed_calls[index];
+        if managed.attempts >= managed.options.retry.max_attempts {
+            self.managed_calls.swap_remove(index);
+            self.events.push_back(Event::Response(peer, call, Err(error)));
+            return;
+        }
+
+        log::debug!("retrying {:?} after failure on {}: {}", call, peer, error);
+        if managed.candidates.is_empty() {
+            managed.candidates.push_back(peer);
+        }
+        managed.hedge_at = None;
+        managed.retry_at = Some(Instant::now() + managed.options.retry.backoff(managed.attempts));
+    }
+
+    fn drive_timers(&mut self, now: Instant) {
+        let timeout = self.config.request_timeout;
+        let queued = self
+            .pending_requests
+            .extract_if(|(.., time)| now.duration_since(*time) >= timeout)
+            .map(|(p, c, ..)| (c, p))
+            .collect::<Vec<_>>();
+        self.counters.timeouts += queued.len() as u64;
+        for (call, peer) in queued {
+            self.fail_attempt(peer, call, Arc::new(StreamUpgradeError::Timeout));
+        }
+
+        let expired = self
+            .ongoing_requests
+            .extract_if(|(.., time)| now.duration_since(*time) >= timeout)
+            .map(|(c, p, _)| (c, p))
+            .collect::<Vec<_>>();
+        self.counters.timeouts += expired.len() as u64;
+        for (call, peer) in expired {
+            self.consumed_chunks.retain(|(c, _)| *c != call);
+            if let Some(stream) = self.streams.iter_mut().find(|s| s.peer == peer) {
+                _ = stream.write(call, &[], PacketKind::Cancel);
+            }
+            self.fail_attempt(peer, call, Arc::new(StreamUpgradeError::Timeout));
+        }
+
+        let mut due = Vec::new();
+        for managed in &mut self.managed_calls {
+            if !managed.retry_at.into_iter().chain(managed.hedge_at).any(|t| t <= now) {
+                continue;
+            }
+            let hedged = managed.hedge_at.is_some_and(|t| t <= now);
+            managed.retry_at = None;
+            managed.hedge_at = None;
+            if let Some(peer) = managed.candidates.pop_front() {
+                managed.attempts += 1;
+                *if hedged { &mut self.counters.hedges } else { &mut self.counters.retries } += 1;
+                due.push((peer, managed.call, managed.packet.clone()));
+            }
+        }
+
+        for (peer, call, packet) in due {
+            if let Err(e) = self.send(peer, call, packet) {
+                self.fail_attempt(peer, call, Arc::new(StreamUpgradeError::Io(e)));
+            }
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        let timeout = self.config.request_timeout;
+        let timeouts = self
+            .ongoing_requests
+            .iter()
+            .map(|&(.., time)| time)
+            .chain(self.pending_requests.iter().map(|&(.., time)| time))
+            .map(|time| time + timeout);
+        let managed =
+            self.managed_calls.iter().flat_map(|m| m.retry_at.into_iter().chain(m.hedge_at));
+        timeouts.chain(managed).min()
+    }
+
+    /// No request is waiting for a response and all responses were handed to streams.
+    #[must_use]
+    pub fn is_idle(&self) -> bool {
+        self.pending_requests.is_empty()
+            && self.ongoing_requests.is_empty()
+            && self.managed_calls.is_empty()
+            && self.pending_repsonses.is_empty()
+            && self.chunked_responses.is_empty()
     }
 
     pub fn respond(
@@ -123,25 +491,151 @@ impl Behaviour {
         call: CallId,
         payload: impl AsRef<[u8]> + Into<Vec<u8>>,
     ) {
-        if let Some(stream) = self.streams.iter_mut().find(|s| peer == s.peer) {
-            _ = stream.write(call, payload.as_ref(), false);
-        } else if !self.streaming.is_resolving_stream_for(peer) {
+        let queued = self.pending_repsonses.iter().filter(|(p, ..)| *p == peer).count();
+        if queued == 0
+            && let Some(stream) = self.streams.iter_mut().find(|s| peer == s.peer)
+            && stream.write(call, payload.as_ref(), PacketKind::Response).is_ok()
+        {
+            return;
+        }
+
+        if queued >= self.config.max_queued_per_peer {
+            log::warn!("dropping response {:?} to {}, too many queued", call, peer);
+            self.events.push_back(Event::WriteDropped(peer, call));
+            return;
+        }
+
+        if !self.streams.iter().any(|s| s.peer == peer)
+            && !self.streaming.is_resolving_stream_for(peer)
+        {
+            self.streaming.create_stream(peer);
+        }
+        self.pending_repsonses.push((peer, call, payload.into()));
+    }
+
+    /// Queues a part of a streamed response, the caller receives it as
+    /// [`Event::ResponseChunk`]. Chunks are sent as long as the caller keeps up with
+    /// [`STREAM_WINDOW`].
+    pub fn respond_chunk(&mut self, peer: PeerId, call: CallId, payload: impl Into<Vec<u8>>) {
+        self.chunked_response(peer, call).queue.push_back(payload.into());
+        self.flush_chunks();
+    }
+
+    /// Ends the streamed response after all queued chunks are sent.
+    pub fn finish_response(&mut self, peer: PeerId, call: CallId) {
+        self.chunked_response(peer, call).finished = true;
+        self.flush_chunks();
+    }
+
+    /// Chunks of the response that were not sent yet, `None` if the response was finished
+    /// and sent or the caller is gone. Call ids come from the callers so they are only unique
+    /// together with the peer.
+    #[must_use]
+    pub fn queued_chunks(&self, peer: PeerId, call: CallId) -> Option<usize> {
+        self.chunked_responses
+            .iter()
+            .find(|r| r.call == call && r.peer == peer)
+            .map(|r| r.queue.len())
+    }
+
+    fn chunked_response(&mut self, peer: PeerId, call: CallId) -> &mut ChunkedResponse {
+        if let Some(i) =
+            self.chunked_responses.iter().position(|r| r.call == call && r.peer == peer)
+        {
+            return &mut self.chunked_responses[i];
+        }
+
+        if !self.streams.iter().any(|s| s.peer == peer)
+            && !self.streaming.is_resolving_stream_for(peer)
+        {
             self.streaming.create_stream(peer);
-            self.pending_repsonses.push((peer, call, payload.into()));
+        }
+
+        self.chunked_responses.push(ChunkedResponse {
+            peer,
+            call,
+            credit: STREAM_WINDOW,
+            queue: VecDeque::new(),
+            finished: false,
+        });
+        self.chunked_responses.last_mut().unwrap()
+    }
+
+    fn flush_chunks(&mut self) {
+        let streams = &mut self.streams;
+        self.chunked_responses.retain_mut(|r| {
+            let Some(stream) = streams.iter_mut().find(|s| s.peer == r.peer) else {
+                return true;
+            };
+
+            while r.credit > 0
+                && let Some(chunk) = r.queue.front()
+            {
+                if stream.write(r.call, chunk, PacketKind::Chunk).is_err() {
+                    // writer is full, try again once it drains
+                    return true;
+                }
+                r.queue.pop_front();
+                r.credit -= 1;
+            }
+
+            !(r.finished
+                && r.queue.is_empty()
+                && stream.write(r.call, &[], PacketKind::StreamEnd).is_ok())
+        });
+    }
+
+    fn want_chunks(&mut self, peer: PeerId) {
+        let wanted = self
+            .chunked_responses
+            .iter()
+            .filter(|r| r.peer == peer && !r.finished && r.credit > 0 && r.queue.is_empty());
+        self.events.extend(wanted.map(|r| Event::ChunksWanted(r.peer, r.call)));
+    }
+
+    /// Marks a chunk of the streamed response as processed, the responder gets more credit
+    /// once half of [`STREAM_WINDOW`] is consumed. Chunks that are received but never consumed
+    /// stop the stream.
+    pub fn consume_chunk(&mut self, call: CallId) {
+        let Some(&(_, peer, _)) = self.ongoing_requests.iter().find(|(c, ..)| *c == call) else {
+            return;
+        };
+
+        let index =
+            self.consumed_chunks.iter().position(|(c, _)| *c == call).unwrap_or_else(|| {
+                self.consumed_chunks.push((call, 0));
+                self.consumed_chunks.len() - 1
+            });
+        let consumed = &mut self.consumed_chunks[index].1;
+
+        *consumed += 1;
+        if *consumed < STREAM_WINDOW / 2 {
+            return;
+        }
+
+        let Some(stream) = self.streams.iter_mut().find(|s| s.peer == peer) else {
+            return;
+        };
+        let credit = u32::try_from(*consumed).unwrap_or(u32::MAX).to_bytes();
+        if stream.write(call, &credit, PacketKind::Credit).is_ok() {
+            *consumed = 0;
         }
     }
 
     fn clean_failed_requests(&mut self, failed: PeerId, error: streaming::Error) {
         let error = Arc::new(error);
         self.pending_repsonses.retain(|(p, ..)| *p != failed);
-        self.ongoing_requests
+        self.chunked_responses.retain(|r| r.peer != failed);
+        let calls = self
+            .ongoing_requests
             .extract_if(|(_, p, ..)| *p == failed)
-            .map(|(c, p, ..)| (c, p))
-            .chain(
-                self.pending_requests.extract_if(|(p, ..)| *p == failed).map(|(p, c, ..)| (c, p)),
-            )
-            .map(|(c, p)| Event::Response(p, c, Err(error.clone())))
-            .collect_into(&mut self.events);
+            .map(|(c, ..)| c)
+            .chain(self.pending_requests.extract_if(|(p, ..)| *p == failed).map(|(_, c, ..)| c))
+            .collect::<Vec<_>>();
+        for call in calls {
+            self.consumed_chunks.retain(|(c, _)| *c != call);
+            self.fail_attempt(failed, call, error.clone());
+        }
     }
 }
 
@@ -156,7 +650,7 @@ impl NetworkBehaviour for Behaviour {
         _: &libp2p::Multiaddr,
         _: &libp2p::Multiaddr,
     ) -> Result<libp2p::swarm::THandler<Self>, libp2p::swarm::ConnectionDenied> {
-        Ok(streaming::Handler::new(|| PROTOCOL_NAME))
+        Ok(streaming::Handler::new(self.config.protocol.clone()))
     }
 
     fn handle_established_outbound_connection(
@@ -166,7 +660,7 @@ impl NetworkBehaviour for Behaviour {
         _: &libp2p::Multiaddr,
         _: libp2p::core::Endpoint,
     ) -> Result<libp2p::swarm::THandler<Self>, libp2p::swarm::ConnectionDenied> {
-        Ok(streaming::Handler::new(|| PROTOCOL_NAME))
+        Ok(streaming::Handler::new(self.config.protocol.clone()))
     }
 
     fn on_swarm_event(&mut self, event: libp2p::swarm::FromSwarm) {
@@ -186,18 +680,42 @@ impl NetworkBehaviour for Behaviour {
         &mut self,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<libp2p::swarm::ToSwarm<Self::ToSwarm, libp2p::swarm::THandlerInEvent<Self>>> {
+        let now = Instant::now();
+        self.drive_timers(now);
+        if let Some(deadline) = self.next_deadline() {
+            let wait = deadline.saturating_duration_since(now);
+            let (armed, timer) =
+                self.timer.get_or_insert_with(|| (deadline, futures_timer::Delay::new(wait)));
+            if *armed != deadline {
+                *armed = deadline;
+                timer.reset(wait);
+            }
+            if timer.poll_unpin(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        }
+
+        if let Some(ev) = self.events.pop_front() {
+            return Poll::Ready(libp2p::swarm::ToSwarm::GenerateEvent(ev));
+        }
+
+        self.flush_requests();
+        self.flush_responses();
+        self.flush_chunks();
+
         while let Poll::Ready(Some((pid, res))) = self.streams.poll_next_unpin(cx) {
             match res {
-                Ok((cid, content, false)) => {
-                    let Some((_, peer, time)) =
-                        self.ongoing_requests.find_and_remove(|(c, ..)| *c == cid)
+                Ok((cid, content, PacketKind::Response)) => {
+                    let Some((.., time)) =
+                        self.ongoing_requests.find_and_remove(|&(c, p, _)| c == cid && p == pid)
                     else {
-                        log::warn!("unexpected response {:?}", cid);
+                        // cancelled calls and hedges that lost can still arrive
+                        log::debug!("unexpected response {:?} from {:?}", cid, pid);
                         continue;
                     };
-                    if pid != peer {
-                        log::warn!("unexpected response {:?} from {:?}", cid, peer);
-                        continue;
+                    self.record_latency(time.elapsed());
+                    if self.managed_calls.find_and_remove(|m| m.call == cid).is_some() {
+                        self.cancel_attempts(cid, None);
                     }
                     return Poll::Ready(libp2p::swarm::ToSwarm::GenerateEvent(Event::Response(
                         pid,
@@ -205,55 +723,91 @@ impl NetworkBehaviour for Behaviour {
                         Ok((content, time.elapsed())),
                     )));
                 }
-                Ok((cid, content, true)) => {
+                Ok((cid, content, PacketKind::Request)) => {
                     return Poll::Ready(libp2p::swarm::ToSwarm::GenerateEvent(Event::Request(
                         pid, cid, content,
                     )));
                 }
-                Err(e) => self.clean_failed_requests(pid, StreamUpgradeError::Io(e)),
+                Ok((cid, content, PacketKind::Chunk)) => {
+                    let Some((.., time)) = self
+                        .ongoing_requests
+                        .iter_mut()
+                        .find(|&&mut (c, p, _)| c == cid && p == pid)
+                    else {
+                        log::debug!("unexpected response chunk {:?} from {:?}", cid, pid);
+                        continue;
+                    };
+                    // timeout applies to the gaps between chunks
+                    *time = Instant::now();
+                    if self.managed_calls.find_and_remove(|m| m.call == cid).is_some() {
+                        self.cancel_attempts(cid, Some(pid));
+                    }
+                    return Poll::Ready(libp2p::swarm::ToSwarm::GenerateEvent(
+                        Event::ResponseChunk(pid, cid, Some(content)),
+                    ));
+                }
+                Ok((cid, _, PacketKind::StreamEnd)) => {
+                    if self
+                        .ongoing_requests
+                        .find_and_remove(|&(c, p, ..)| c == cid && p == pid)
+                        .is_none()
+                    {
+                        log::debug!("unexpected stream end {:?} from {:?}", cid, pid);
+                        continue;
+                    }
+                    self.consumed_chunks.retain(|(c, _)| *c != cid);
+                    return Poll::Ready(libp2p::swarm::ToSwarm::GenerateEvent(
+                        Event::ResponseChunk(pid, cid, None),
+                    ));
+                }
+                Ok((cid, content, PacketKind::Credit)) => {
+                    let Some(credit) = u32::decode(&mut content.as_slice()) else {
+                        log::warn!("invalid credit packet from {:?}", pid);
+                        continue;
+                    };
+                    if let Some(r) =
+                        self.chunked_responses.iter_mut().find(|r| r.call == cid && r.peer == pid)
+                    {
+                        r.credit += credit as usize;
+                    }
+                    self.flush_chunks();
+                    self.want_chunks(pid);
+                }
+                Ok((cid, _, PacketKind::Cancel)) => {
+                    self.chunked_responses.retain(|r| !(r.call == cid && r.peer == pid));
+                    self.pending_repsonses.retain(|&(p, c, _)| !(c == cid && p == pid));
+                }
+                Err(e) => {
+                    self.clean_failed_requests(pid, StreamUpgradeError::Io(e));
+                }
             }
         }
 
         loop {
+            if let Some(ev) = self.events.pop_front() {
+                return Poll::Ready(libp2p::swarm::ToSwarm::GenerateEvent(ev));
+            }
+
             let ev = std::task::ready!(self.streaming.poll(cx));
 
             let libp2p::swarm::ToSwarm::GenerateEvent(ev) = ev else {
                 return Poll::Ready(ev.map_out(|_| unreachable!()));
             };
 
-            match ev {
-                streaming::Event::IncomingStream(p, s)
-                | streaming::Event::OutgoingStream(p, Ok(s)) => {
-                    let mut stream = Stream::new(p, s, self.config.buffer_size);
-
-                    for (peer, call, payload, time) in
-                        self.pending_requests.extract_if(|(op, ..)| *op == p)
-                    {
-                        if let Err(err) = stream.write(call, &payload, true) {
-                            self.events.push(Event::Response(
-                                peer,
-                                call,
-                                Err(StreamUpgradeError::Io(err).into()),
-                            ));
-                        } else {
-                            self.ongoing_requests.push((call, peer, time));
-                        }
-                    }
-
-                    for (_, call, payload) in self.pending_repsonses.extract_if(|(op, ..)| *op == p)
-                    {
-                        _ = stream.write(call, &payload, false);
-                    }
-
-                    self.streams.push(stream);
-                }
+            let (p, s, slot) = match ev {
+                streaming::Event::IncomingStream(p, s) => (p, s, None),
+                streaming::Event::OutgoingStream(p, Ok((s, slot))) => (p, s, Some(slot)),
                 streaming::Event::OutgoingStream(p, Err(err)) => {
-                    let err = Arc::new(err);
-                    for (peer, call, _) in self.pending_repsonses.extract_if(|(op, ..)| *op == p) {
-                        self.events.push(Event::Response(peer, call, Err(err.clone())));
-                    }
+                    self.clean_failed_requests(p, err);
+                    continue;
                 }
-            }
+            };
+
+            self.streams.push(Stream::new(p, s, slot, self.config.buffer_size));
+            self.flush_requests();
+            self.flush_responses();
+            self.flush_chunks();
+            self.want_chunks(p);
         }
     }
 }
@@ -263,6 +817,12 @@ component_utils::gen_config! {
     max_cached_connections: usize = 10,
     buffer_size: usize = 1 << 14,
     request_timeout: std::time::Duration = std::time::Duration::from_secs(10),
+    max_in_flight_per_peer: usize = 64,
+    /// Applies to requests and responses separately.
+    max_queued_per_peer: usize = 256,
+    /// Peers speaking a different protocol never open streams to each other, networks with
+    /// their own messages use their own name.
+    protocol: libp2p::StreamProtocol = PROTOCOL_NAME,
 }
 
 impl Default for Config {
@@ -278,7 +838,16 @@ component_utils::gen_unique_id!(pub CallId);
 #[allow(clippy::type_complexity)]
 pub enum Event {
     Response(PeerId, CallId, Result<(Vec<u8>, Duration), Arc<streaming::Error>>),
+    /// Part of a streamed response, `None` marks its end. Failure of the call is still
+    /// reported with [`Event::Response`]. Processed chunks are acknowledged with
+    /// [`Behaviour::consume_chunk`].
+    ResponseChunk(PeerId, CallId, Option<Vec<u8>>),
+    /// Streamed response sent everything queued and the caller has credit for more, chunks
+    /// can be produced lazily as long as [`Behaviour::queued_chunks`] is zero.
+    ChunksWanted(PeerId, CallId),
     Request(PeerId, CallId, Vec<u8>),
+    /// Response could not be queued because the peer is not reading fast enough.
+    WriteDropped(PeerId, CallId),
 }
 
 pub struct Response {
@@ -308,12 +877,12 @@ mod test {
         dht: dht::Behaviour,
     }
 
-    #[tokio::test(flavor = "current_thread")]
-    async fn test_random_rpc_calls() {
-        env_logger::init();
-
+    fn create_swarms(
+        count: usize,
+        first_port: u16,
+    ) -> (Vec<PeerId>, impl Iterator<Item = libp2p::Swarm<TestBehatiour>>) {
         let pks =
-            (0..10).map(|_| libp2p::identity::ed25519::Keypair::generate()).collect::<Vec<_>>();
+            (0..count).map(|_| libp2p::identity::ed25519::Keypair::generate()).collect::<Vec<_>>();
         let public_keys = pks.iter().map(|kp| kp.public()).collect::<Vec<_>>();
         let peer_ids =
             pks.iter().map(|kp| PublicKey::from(kp.public()).to_peer_id()).collect::<Vec<_>>();
@@ -336,7 +905,7 @@ mod test {
                 .listen_on(
                     Multiaddr::empty()
                         .with(Protocol::Ip4(Ipv4Addr::LOCALHOST))
-                        .with(Protocol::Tcp(3000 + i as u16)),
+                        .with(Protocol::Tcp(first_port + i as u16)),
                 )
                 .unwrap();
 
@@ -345,13 +914,22 @@ mod test {
                     pk.clone(),
                     Multiaddr::empty()
                         .with(Protocol::Ip4(Ipv4Addr::LOCALHOST))
-                        .with(Protocol::Tcp(3000 + j as u16)),
+                        .with(Protocol::Tcp(first_port + j as u16)),
                 ));
             }
 
             swarm
         });
 
+        (peer_ids, servers)
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_random_rpc_calls() {
+        _ = env_logger::try_init();
+
+        let (peer_ids, servers) = create_swarms(10, 3000);
+
         async fn run_server(mut swarm: libp2p::Swarm<TestBehatiour>, mut all_peers: Vec<PeerId>) {
             all_peers.retain(|p| p != swarm.local_peer_id());
             let max_pending_requests = 10;
@@ -405,4 +983,219 @@ mod test {
             .next()
             .await;
     }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_streamed_response() {
+        _ = env_logger::try_init();
+
+        const CHUNKS: usize = STREAM_WINDOW * 3;
+
+        let (peer_ids, mut servers) = create_swarms(2, 3100);
+        let (mut caller, mut responder) = (servers.next().unwrap(), servers.next().unwrap());
+        let call = caller.behaviour_mut().rpc.request(peer_ids[1], [0]).unwrap();
+
+        let mut received = 0;
+        loop {
+            libp2p::futures::select! {
+                e = caller.select_next_some() => match e {
+                    libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
+                        Event::ResponseChunk(_, cid, chunk),
+                    )) => {
+                        assert_eq!(cid, call);
+                        let Some(chunk) = chunk else { break };
+                        assert_eq!(chunk, [received as u8]);
+                        received += 1;
+                        caller.behaviour_mut().rpc.consume_chunk(cid);
+                    }
+                    libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
+                        Event::Response(.., res),
+                    )) => panic!("unexpected response: {:?}", res),
+                    _ => {}
+                },
+                e = responder.select_next_some() => {
+                    if let libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
+                        Event::Request(peer, cid, _),
+                    )) = e
+                    {
+                        let rpc = &mut responder.behaviour_mut().rpc;
+                        for i in 0..CHUNKS {
+                            rpc.respond_chunk(peer, cid, vec![i as u8]);
+                        }
+                        assert!(rpc.queued_chunks(peer, cid) >= Some(CHUNKS - STREAM_WINDOW));
+                        rpc.finish_response(peer, cid);
+                    }
+                },
+            }
+        }
+
+        assert_eq!(received, CHUNKS);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_lazy_streamed_response() {
+        _ = env_logger::try_init();
+
+        const CHUNKS: usize = STREAM_WINDOW * 3;
+
+        let (peer_ids, mut servers) = create_swarms(2, 3150);
+        let (mut caller, mut responder) = (servers.next().unwrap(), servers.next().unwrap());
+        let call = caller.behaviour_mut().rpc.request(peer_ids[1], [0]).unwrap();
+
+        let mut received = 0;
+        let mut produced = 0;
+        loop {
+            libp2p::futures::select! {
+                e = caller.select_next_some() => match e {
+                    libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
+                        Event::ResponseChunk(_, cid, chunk),
+                    )) => {
+                        assert_eq!(cid, call);
+                        let Some(chunk) = chunk else { break };
+                        assert_eq!(chunk, [received as u8]);
+                        received += 1;
+                        caller.behaviour_mut().rpc.consume_chunk(cid);
+                    }
+                    libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
+                        Event::Response(.., res),
+                    )) => panic!("unexpected response: {:?}", res),
+                    _ => {}
+                },
+                e = responder.select_next_some() => {
+                    let rpc = &mut responder.behaviour_mut().rpc;
+                    let (peer, cid) = match e {
+                        libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
+                            Event::Request(peer, cid, _),
+                        )) => {
+                            rpc.respond_chunk(peer, cid, vec![produced as u8]);
+                            produced += 1;
+                            (peer, cid)
+                        }
+                        libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
+                            Event::ChunksWanted(peer, cid),
+                        )) => (peer, cid),
+                        _ => continue,
+                    };
+
+                    while produced < CHUNKS && rpc.queued_chunks(peer, cid) == Some(0) {
+                        rpc.respond_chunk(peer, cid, vec![produced as u8]);
+                        produced += 1;
+                    }
+                    assert!(rpc.queued_chunks(peer, cid) <= Some(1));
+                    if produced == CHUNKS {
+                        rpc.finish_response(peer, cid);
+                    }
+                },
+            }
+        }
+
+        assert_eq!(received, CHUNKS);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_retry_on_next_candidate() {
+        _ = env_logger::try_init();
+
+        let (peer_ids, mut servers) = create_swarms(3, 3200);
+        let mut caller = servers.next().unwrap();
+        drop(servers.next());
+        let mut responder = servers.next().unwrap();
+
+        let options = RequestOptions {
+            retry: RetryPolicy { max_attempts: 2, ..Default::default() },
+            hedge_percentile: None,
+        };
+        let call = caller
+            .behaviour_mut()
+            .rpc
+            .request_with([peer_ids[1], peer_ids[2]], [1, 2, 3], options)
+            .unwrap();
+
+        loop {
+            libp2p::futures::select! {
+                e = caller.select_next_some() => {
+                    if let libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
+                        Event::Response(peer, cid, res),
+                    )) = e
+                    {
+                        assert_eq!(cid, call);
+                        assert_eq!(peer, peer_ids[2]);
+                        assert_eq!(res.unwrap().0, [1, 2, 3]);
+                        break;
+                    }
+                },
+                e = responder.select_next_some() => {
+                    if let libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
+                        Event::Request(peer, cid, body),
+                    )) = e
+                    {
+                        responder.behaviour_mut().rpc.respond(peer, cid, body);
+                    }
+                },
+            }
+        }
+
+        assert!(caller.behaviour().rpc.is_idle());
+    }
+
+    #[test]
+    fn test_failed_peer_is_tried_last() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let mut rpc = Behaviour::default();
+        let timeout = || Arc::new(StreamUpgradeError::Timeout);
+        let retry = RetryPolicy { max_attempts: 3, ..Default::default() };
+
+        let call = rpc
+            .request_with([a, b], [0], RequestOptions { retry, hedge_percentile: None })
+            .unwrap();
+        rpc.pending_requests.clear();
+        rpc.fail_attempt(a, call, timeout());
+        assert_eq!(rpc.managed_calls[0].candidates, [b]);
+        assert!(rpc.managed_calls[0].retry_at.is_some());
+
+        rpc.managed_calls[0].candidates.pop_front();
+        rpc.fail_attempt(b, call, timeout());
+        assert_eq!(rpc.managed_calls[0].candidates, [b]);
+
+        // hedge failing while the first attempt still runs is dropped
+        let call = rpc
+            .request_with([a, b], [1], RequestOptions { retry, hedge_percentile: Some(50) })
+            .unwrap();
+        rpc.managed_calls[1].candidates.pop_front();
+        rpc.fail_attempt(b, call, timeout());
+        assert!(rpc.managed_calls[1].candidates.is_empty());
+        assert!(rpc.managed_calls[1].retry_at.is_none());
+    }
+
+    #[test]
+    fn test_chunked_responses_are_per_caller() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let mut rpc = Behaviour::default();
+        let call = CallId::whatever();
+
+        rpc.respond_chunk(a, call, [0]);
+        rpc.respond_chunk(b, call, [1]);
+        rpc.respond_chunk(b, call, [2]);
+        rpc.finish_response(a, call);
+
+        assert_eq!(rpc.queued_chunks(a, call), Some(1));
+        assert_eq!(rpc.queued_chunks(b, call), Some(2));
+        let finished =
+            |peer| rpc.chunked_responses.iter().find(|r| r.peer == peer).unwrap().finished;
+        assert!(finished(a));
+        assert!(!finished(b));
+    }
+
+    #[test]
+    fn test_request_queue_limit() {
+        let config = Config::new().max_in_flight_per_peer(1).max_queued_per_peer(2);
+        let mut rpc = Behaviour::new(config);
+        let peer = PeerId::random();
+
+        rpc.request(peer, [0]).unwrap();
+        rpc.request(peer, [1]).unwrap();
+        let err = rpc.request(peer, [2]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        rpc.request(PeerId::random(), [3]).unwrap();
+    }
 }
@@ -0,0 +1,720 @@
+use {
+    arrayvec::ArrayVec,
+    component_utils::{
+        futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt},
+        Codec, Protocol, Reminder,
+    },
+    libp2p::{
+        identity::{ed25519, PublicKey},
+        swarm::{NetworkBehaviour, SwarmEvent},
+        PeerId, StreamProtocol,
+    },
+    rand_core::{OsRng, RngCore},
+    std::{
+        collections::{HashMap, VecDeque},
+        io,
+        ops::Range,
+        sync::Arc,
+        time::Duration,
+    },
+    storage_spec::{
+        metabase::{BlockId, StoreIdentity},
+        protocol::{
+            self, AllocateFile, AllocateFileError, Allocation, BlockPlacement, FetchPieces,
+            PieceIndex, Rejection, StorePieces, MAX_TRANSFER_PIECES,
+        },
+        ticket::AllocationTicket,
+        Codec as ErasureCodec, Data, Parity, Piece, RebuildError, ReconstructPiece, DATA_PIECES,
+        MAX_PIECES, PARITY_PIECES, PIECE_SIZE,
+    },
+};
+
+/// Bytes of the object one row of pieces carries, the object is padded to it.
+const ROW_SIZE: usize = DATA_PIECES * PIECE_SIZE;
+/// Uploads waiting for a response at once, the rest is queued.
+const MAX_IN_FLIGHT: usize = 64;
+/// Stores asked for a segment on top of the [`DATA_PIECES`] needed, the slowest ones are
+/// cancelled once enough shares arrive.
+const FETCH_HEDGE: usize = 2;
+/// Rows one encrypted frame takes, uploads read the object a frame at a time.
+const FRAME_ROWS: usize = MAX_TRANSFER_PIECES as usize;
+const FRAME_OVERHEAD: usize = crypto::TAG_SIZE + crypto::NONCE_SIZE;
+/// Bytes of the object encrypted together, the frame with its tag and nonce fills
+/// [`FRAME_ROWS`] rows.
+const FRAME_SIZE: usize = FRAME_ROWS * ROW_SIZE - FRAME_OVERHEAD;
+
+/// Everything needed to download the object again. It holds the object key so it should be
+/// kept private.
+#[derive(Clone, Codec)]
+pub struct ObjectHandle {
+    pub allocation: Allocation,
+    pub secret: crypto::SharedSecret,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("request failed: {0}")]
+    Request(Arc<streaming::Error>),
+    #[error("invalid response")]
+    InvalidResponse,
+    #[error(transparent)]
+    Rejected(#[from] Rejection),
+    #[error("allocation failed: {0}")]
+    Allocate(#[from] AllocateFileError),
+    #[error("too few stores hold pieces of block {0}")]
+    NotEnoughShares(BlockId),
+    #[error("failed to rebuild the pieces: {0}")]
+    Rebuild(#[from] RebuildError),
+    #[error("object is corrupted or the key is wrong")]
+    Decrypt,
+}
+
+/// Uploads objects through the satellite to its stores and downloads them back. Objects are
+/// encrypted with their own key and erasure coded, so any [`DATA_PIECES`] out of the
+/// [`MAX_PIECES`] stores of a block are enough to get it back.
+pub struct Client {
+    swarm: libp2p::Swarm<Behaviour>,
+    satelite: PeerId,
+    codec: ErasureCodec,
+}
+
+impl Client {
+    pub fn new(identity: ed25519::Keypair, satelite: dht::Route) -> Self {
+        let mut swarm = new_swarm(identity);
+        let satelite_id = satelite.peer_id();
+        swarm.behaviour_mut().dht.table.insert(satelite);
+
+        Self { swarm, satelite: satelite_id, codec: ErasureCodec::default() }
+    }
+
+    /// Stores are dialed through the routing table, pieces of unknown stores count as lost.
+    pub fn add_store(&mut self, route: dht::Route) {
+        self.swarm.behaviour_mut().dht.table.insert(route);
+    }
+
+    /// Reads, encrypts and erasure codes the object a few rows at a time while spreading the
+    /// pieces over the allocated stores, `size` is the number of bytes `object` yields. Upload
+    /// succeeds as long as each block has [`DATA_PIECES`] stores that accepted it.
+    pub async fn upload(
+        &mut self,
+        object: impl AsyncRead + Unpin,
+        size: u64,
+    ) -> Result<ObjectHandle, Error> {
+        let secret = crypto::new_secret(OsRng);
+        // the object is not read yet, so the id can not be its hash
+        let mut file = storage_spec::ObjectId::default();
+        OsRng.fill_bytes(&mut file);
+        let (allocation, ticket) = self.allocate(file, encrypted_size(size)).await?;
+
+        let mut upload = Upload {
+            queue: VecDeque::new(),
+            pending: HashMap::new(),
+            failed: allocation
+                .blocks
+                .iter()
+                .map(|placement| placement.stores.map(|identity| store_peer(identity).is_none()))
+                .collect(),
+        };
+
+        let mut object = Encryptor::new(object, size, secret);
+        let mut ciphertext = vec![];
+        for (segment, (placement, pieces)) in segments(&allocation).enumerate() {
+            for start in pieces.clone().step_by(MAX_TRANSFER_PIECES as usize) {
+                let end = pieces.end.min(start + MAX_TRANSFER_PIECES);
+                ciphertext.resize((end - start) as usize * ROW_SIZE, 0);
+                object.read(&mut ciphertext).await?;
+                let rows = encode_rows(&self.codec, &ciphertext);
+
+                for (store, &identity) in placement.stores.iter().enumerate() {
+                    let Some(peer) = store_peer(identity) else {
+                        continue;
+                    };
+                    let share = rows.iter().flat_map(|row| row[store]).collect::<Vec<_>>();
+                    let request = (ticket.clone(), placement.block, start, Reminder(&share));
+                    let body = (StorePieces::PREFIX, request).to_bytes();
+                    upload.queue.push_back((segment, store, peer, body));
+                }
+
+                self.drive_upload(&mut upload, MAX_PIECES).await;
+            }
+        }
+        self.drive_upload(&mut upload, 0).await;
+
+        for (failed, placement) in upload.failed.iter().zip(&allocation.blocks) {
+            if failed.iter().filter(|&&f| f).count() > PARITY_PIECES {
+                return Err(Error::NotEnoughShares(placement.block));
+            }
+        }
+
+        Ok(ObjectHandle { allocation, secret })
+    }
+
+    /// Sends queued uploads and handles their responses until at most `max_queued` requests
+    /// wait, zero also waits for every response.
+    async fn drive_upload(&mut self, upload: &mut Upload, max_queued: usize) {
+        loop {
+            while upload.pending.len() < MAX_IN_FLIGHT {
+                let Some((segment, store, peer, body)) = upload.queue.pop_front() else {
+                    break;
+                };
+                if upload.failed[segment][store] {
+                    continue;
+                }
+
+                match self.swarm.behaviour_mut().rpc.request(peer, body) {
+                    Ok(call) => {
+                        upload.pending.insert(call, (segment, store));
+                    }
+                    Err(e) => {
+                        log::warn!("failed to upload to {peer}: {e}");
+                        upload.failed[segment][store] = true;
+                    }
+                }
+            }
+
+            if upload.queue.len() <= max_queued && (max_queued != 0 || upload.pending.is_empty()) {
+                return;
+            }
+
+            let rpc::Event::Response(peer, call, res) = self.next_rpc().await else {
+                continue;
+            };
+            let Some((segment, store)) = upload.pending.remove(&call) else {
+                continue;
+            };
+
+            let res = res
+                .map(|(body, _)| <StorePieces as Protocol>::Response::decode(&mut body.as_slice()));
+            let error = match res {
+                Ok(Some(Ok(()))) => continue,
+                Ok(Some(Err(e))) => e.to_string(),
+                Ok(None) => "invalid response".into(),
+                Err(e) => e.to_string(),
+            };
+            log::warn!("upload to {peer} failed: {error}");
+            upload.failed[segment][store] = true;
+        }
+    }
+
+    /// Downloads, rebuilds and decrypts the object a segment at a time. Frames are verified
+    /// before they are written, so on error the writer holds a verified prefix of the object.
+    pub async fn download(
+        &mut self,
+        handle: &ObjectHandle,
+        writer: impl AsyncWrite + Unpin,
+    ) -> Result<(), Error> {
+        let allocation = &handle.allocation;
+        let mut object = Decryptor::new(writer, allocation.size, handle.secret);
+        let mut segment = vec![];
+        for (placement, pieces) in segments(allocation) {
+            segment.clear();
+            self.fetch_segment(allocation.file, placement, pieces, &mut segment).await?;
+            object.write(&segment).await?;
+        }
+        object.finish()
+    }
+
+    async fn allocate(
+        &mut self,
+        file: storage_spec::ObjectId,
+        size: u64,
+    ) -> Result<(Allocation, AllocationTicket), Error> {
+        let request = (AllocateFile::PREFIX, (file, size)).to_bytes();
+        let call = self.swarm.behaviour_mut().rpc.request(self.satelite, request)?;
+        let body = loop {
+            match self.next_rpc().await {
+                rpc::Event::Response(_, c, res) if c == call => {
+                    break res.map_err(Error::Request)?.0
+                }
+                _ => {}
+            }
+        };
+
+        if let Some(rejection) = Rejection::decode(&mut body.as_slice()) {
+            return Err(rejection.into());
+        }
+        Ok(<AllocateFile as Protocol>::Response::decode(&mut body.as_slice())
+            .ok_or(Error::InvalidResponse)??)
+    }
+
+    /// Data pieces need no decoding so their stores are asked first, [`FETCH_HEDGE`] parity
+    /// stores race them and each failure brings in the next store. The first [`DATA_PIECES`]
+    /// shares to arrive are used.
+    async fn fetch_segment(
+        &mut self,
+        file: storage_spec::ObjectId,
+        placement: &BlockPlacement,
+        pieces: Range<PieceIndex>,
+        out: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let share_len = pieces.len() * PIECE_SIZE;
+        let body = (FetchPieces::PREFIX, (file, placement.block, pieces.clone())).to_bytes();
+
+        let mut shares = vec![vec![]; MAX_PIECES];
+        let mut pending = HashMap::new();
+        let mut completed = ArrayVec::<usize, DATA_PIECES>::new();
+        let mut next_store = 0;
+        loop {
+            while pending.len() + completed.len() < DATA_PIECES + FETCH_HEDGE
+                && next_store < MAX_PIECES
+            {
+                let store = next_store;
+                next_store += 1;
+                let Some(peer) = store_peer(placement.stores[store]) else {
+                    continue;
+                };
+                match self.swarm.behaviour_mut().rpc.request(peer, body.as_slice()) {
+                    Ok(call) => {
+                        pending.insert(call, store);
+                    }
+                    Err(e) => log::warn!("failed to fetch from {peer}: {e}"),
+                }
+            }
+
+            if completed.is_full() {
+                for &call in pending.keys() {
+                    self.swarm.behaviour_mut().rpc.cancel(call);
+                }
+                break;
+            }
+
+            if pending.is_empty() {
+                return Err(Error::NotEnoughShares(placement.block));
+            }
+
+            let (peer, call, body, ended) = match self.next_rpc().await {
+                rpc::Event::ResponseChunk(peer, call, Some(body)) => {
+                    (peer, call, Some(body), false)
+                }
+                rpc::Event::ResponseChunk(peer, call, None) => (peer, call, None, true),
+                // errors are not streamed
+                rpc::Event::Response(peer, call, Ok((body, _))) => (peer, call, Some(body), true),
+                rpc::Event::Response(peer, call, Err(e)) => {
+                    if let Some(store) = pending.remove(&call) {
+                        log::warn!("fetch from {peer} failed: {e}");
+                        shares[store].clear();
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let Some(&store) = pending.get(&call) else {
+                continue;
+            };
+
+            let decoded =
+                body.as_deref().map(|mut b| <FetchPieces as Protocol>::Response::decode(&mut b));
+            let error = match decoded {
+                Some(Some(Ok(Reminder(data)))) if shares[store].len() + data.len() <= share_len => {
+                    shares[store].extend_from_slice(data);
+                    None
+                }
+                Some(Some(Ok(_))) => Some("too many pieces".into()),
+                Some(Some(Err(e))) => Some(e.to_string()),
+                Some(None) => Some("invalid response".into()),
+                None => None,
+            };
+            // the store sends more once we take the chunk in
+            self.swarm.behaviour_mut().rpc.consume_chunk(call);
+
+            if error.is_none() && !ended {
+                continue;
+            }
+
+            pending.remove(&call);
+            match error {
+                None if shares[store].len() == share_len => completed.push(store),
+                error => {
+                    let error = error.unwrap_or_else(|| "missing pieces".into());
+                    log::warn!("fetch from {peer} failed: {error}");
+                    shares[store].clear();
+                    if !ended {
+                        self.swarm.behaviour_mut().rpc.cancel(call);
+                    }
+                }
+            }
+        }
+
+        let mut bundle = completed
+            .into_iter()
+            .map(|store| (store, std::mem::take(&mut shares[store])))
+            .collect::<Vec<_>>();
+        rebuild_segment(&mut self.codec, &mut bundle, out)?;
+        Ok(())
+    }
+
+    async fn next_rpc(&mut self) -> rpc::Event {
+        loop {
+            if let SwarmEvent::Behaviour(BehaviourEvent::Rpc(e)) =
+                self.swarm.select_next_some().await
+            {
+                return e;
+            }
+        }
+    }
+}
+
+/// Store requests of an upload, a store that failed once is skipped for the rest of its block.
+struct Upload {
+    queue: VecDeque<(usize, usize, PeerId, Vec<u8>)>,
+    pending: HashMap<rpc::CallId, (usize, usize)>,
+    failed: Vec<[bool; MAX_PIECES]>,
+}
+
+/// Encrypts the object frame by frame as the upload reads it. Each frame has its own key
+/// derived from its position so stores can not reorder them.
+struct Encryptor<R> {
+    reader: R,
+    secret: crypto::SharedSecret,
+    frames: Range<u64>,
+    remaining: u64,
+    frame: Vec<u8>,
+    taken: usize,
+}
+
+impl<R: AsyncRead + Unpin> Encryptor<R> {
+    fn new(reader: R, size: u64, secret: crypto::SharedSecret) -> Self {
+        Self {
+            reader,
+            secret,
+            frames: 0..frame_count(size),
+            remaining: size,
+            frame: vec![],
+            taken: 0,
+        }
+    }
+
+    /// Fills `out` with the following ciphertext, zeroes past its end.
+    async fn read(&mut self, out: &mut [u8]) -> io::Result<()> {
+        let mut written = 0;
+        while written < out.len() {
+            if self.taken == self.frame.len() && !self.next_frame().await? {
+                out[written..].fill(0);
+                break;
+            }
+
+            let len = (out.len() - written).min(self.frame.len() - self.taken);
+            out[written..written + len].copy_from_slice(&self.frame[self.taken..][..len]);
+            self.taken += len;
+            written += len;
+        }
+
+        Ok(())
+    }
+
+    async fn next_frame(&mut self) -> io::Result<bool> {
+        let Some(index) = self.frames.next() else {
+            return Ok(false);
+        };
+
+        let len = self.remaining.min(FRAME_SIZE as u64);
+        self.remaining -= len;
+        self.frame.resize(len as usize, 0);
+        self.reader.read_exact(&mut self.frame).await?;
+        let tail = crypto::encrypt(&mut self.frame, frame_key(self.secret, index), OsRng);
+        self.frame.extend(tail);
+        self.taken = 0;
+        Ok(true)
+    }
+}
+
+/// Counterpart of [`Encryptor`], decrypts and writes each frame once all of its ciphertext
+/// arrives.
+struct Decryptor<W> {
+    writer: W,
+    secret: crypto::SharedSecret,
+    index: u64,
+    /// Ciphertext not taken in yet, padding of the last segment is past it.
+    remaining: u64,
+    frame: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> Decryptor<W> {
+    fn new(writer: W, encrypted_size: u64, secret: crypto::SharedSecret) -> Self {
+        Self { writer, secret, index: 0, remaining: encrypted_size, frame: vec![] }
+    }
+
+    async fn write(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        data = &data[..data.len().min(self.remaining as usize)];
+        while !data.is_empty() {
+            let frame_len = (self.frame.len() as u64 + self.remaining)
+                .min((FRAME_SIZE + FRAME_OVERHEAD) as u64) as usize;
+            let len = (frame_len - self.frame.len()).min(data.len());
+            self.frame.extend_from_slice(&data[..len]);
+            self.remaining -= len as u64;
+            data = &data[len..];
+
+            if self.frame.len() == frame_len {
+                let key = frame_key(self.secret, self.index);
+                let plain = crypto::decrypt(&mut self.frame, key).ok_or(Error::Decrypt)?;
+                self.writer.write_all(plain).await?;
+                self.frame.clear();
+                self.index += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        if self.remaining != 0 || !self.frame.is_empty() {
+            return Err(Error::Decrypt);
+        }
+        Ok(())
+    }
+}
+
+fn frame_count(size: u64) -> u64 {
+    size.div_ceil(FRAME_SIZE as u64).max(1)
+}
+
+fn encrypted_size(size: u64) -> u64 {
+    size + frame_count(size) * FRAME_OVERHEAD as u64
+}
+
+fn frame_key(secret: crypto::SharedSecret, frame: u64) -> crypto::SharedSecret {
+    crypto::hash::with_nonce(&secret, frame)
+}
+
+fn new_swarm(identity: ed25519::Keypair) -> libp2p::Swarm<Behaviour> {
+    libp2p::SwarmBuilder::with_existing_identity(identity.into())
+        .with_tokio()
+        .with_quic()
+        .with_behaviour(|_| Behaviour {
+            dht: dht::Behaviour::default().with_transports(vec![dht::Transport::Quic]),
+            rpc: rpc::Behaviour::new(
+                rpc::Config::new().protocol(StreamProtocol::new(protocol::PROTOCOL_NAME)),
+            ),
+            streaming: streaming::Behaviour::default(),
+        })
+        .unwrap_or_else(|e| match e {})
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build()
+}
+
+/// Blocks of the allocation with the pieces the object takes in them.
+fn segments(
+    allocation: &Allocation,
+) -> impl Iterator<Item = (&BlockPlacement, Range<PieceIndex>)> + '_ {
+    allocation.blocks.iter().zip(allocation.block_pieces()).map(|(p, (_, pieces))| (p, pieces))
+}
+
+fn store_peer(identity: StoreIdentity) -> Option<PeerId> {
+    let key = ed25519::PublicKey::try_from_bytes(&identity).ok()?;
+    Some(PublicKey::from(key).to_peer_id())
+}
+
+/// Splits the data into rows of [`DATA_PIECES`] pieces followed by their parity, the last row
+/// is padded with zeroes.
+fn encode_rows(codec: &ErasureCodec, data: &[u8]) -> Vec<[Piece; MAX_PIECES]> {
+    data.chunks(ROW_SIZE)
+        .map(|chunk| {
+            let mut row = [[0; PIECE_SIZE]; MAX_PIECES];
+            for (piece, bytes) in row.iter_mut().zip(chunk.chunks(PIECE_SIZE)) {
+                piece[..bytes.len()].copy_from_slice(bytes);
+            }
+
+            let (data, parity) = row.split_at_mut(DATA_PIECES);
+            let data = <&Data>::try_from(&*data).expect("split at the data pieces");
+            let parity = <&mut Parity>::try_from(parity).expect("rest is parity");
+            codec.encode(data, parity);
+            row
+        })
+        .collect()
+}
+
+/// Appends the data pieces of each row, `shares` are [`DATA_PIECES`] of `(position, pieces)`.
+fn rebuild_segment(
+    codec: &mut ErasureCodec,
+    shares: &mut [(usize, Vec<u8>)],
+    out: &mut Vec<u8>,
+) -> Result<(), RebuildError> {
+    let rows = shares.first().map_or(0, |(_, s)| s.len() / PIECE_SIZE);
+    for row in 0..rows {
+        let range = row * PIECE_SIZE..(row + 1) * PIECE_SIZE;
+        let bundle = shares
+            .iter_mut()
+            .map(|(store, share)| ReconstructPiece::new(*store, &mut share[range.clone()]))
+            .collect::<ArrayVec<_, DATA_PIECES>>();
+        let Ok(mut bundle) = bundle.into_inner() else {
+            return Err(RebuildError::NotEnoughShares);
+        };
+
+        codec.reconstruct(&mut bundle)?;
+        for piece in &bundle {
+            out.extend_from_slice(piece.data());
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(NetworkBehaviour)]
+struct Behaviour {
+    dht: dht::Behaviour,
+    rpc: rpc::Behaviour,
+    streaming: streaming::Behaviour,
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        libp2p::{multiaddr::Protocol as Proto, Multiaddr},
+        std::net::Ipv4Addr,
+        storage_spec::{
+            protocol::{FetchPiecesError, StorePiecesError},
+            ticket::{Ticket, TicketBlock, TicketKind},
+        },
+    };
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Fetches {
+        Serve,
+        Ignore,
+        Fail,
+    }
+
+    fn node(port: u16) -> (ed25519::Keypair, dht::Route, libp2p::Swarm<Behaviour>) {
+        let identity = ed25519::Keypair::generate();
+        let addr = Multiaddr::empty()
+            .with(Proto::Ip4(Ipv4Addr::LOCALHOST))
+            .with(Proto::Udp(port))
+            .with(Proto::QuicV1);
+        let mut swarm = new_swarm(identity.clone());
+        swarm.listen_on(addr.clone()).unwrap();
+        let route = dht::Route::new(identity.public(), addr);
+        (identity, route, swarm)
+    }
+
+    async fn next_request(swarm: &mut libp2p::Swarm<Behaviour>) -> (PeerId, rpc::CallId, Vec<u8>) {
+        loop {
+            if let SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::Request(
+                peer,
+                call,
+                body,
+            ))) = swarm.select_next_some().await
+            {
+                return (peer, call, body);
+            }
+        }
+    }
+
+    /// Places the whole object in block 0 of the stores.
+    async fn run_satelite(
+        mut swarm: libp2p::Swarm<Behaviour>,
+        stores: [StoreIdentity; MAX_PIECES],
+    ) {
+        let keys = crypto::sign::Keypair::new(OsRng);
+        loop {
+            let (peer, call, body) = next_request(&mut swarm).await;
+            let (file, size) =
+                <AllocateFile as Protocol>::Request::decode(&mut &body[1..]).unwrap();
+            let piece_count = size.div_ceil(ROW_SIZE as u64);
+            let allocation = Allocation {
+                file,
+                size,
+                piece_count,
+                in_block_start: 0,
+                in_block_end: piece_count as PieceIndex,
+                blocks: vec![BlockPlacement { block: 0, stores }],
+            };
+            let ticket = Ticket {
+                kind: TicketKind::Upload,
+                file,
+                blocks: vec![TicketBlock { block: 0, pieces: 0..piece_count as PieceIndex }],
+                expiry: u64::MAX,
+                user: dht::try_peer_id_to_ed(peer).unwrap(),
+            };
+            let ticket = AllocationTicket::new(&keys, ticket, OsRng);
+            let response = Ok::<_, AllocateFileError>((allocation, ticket)).to_bytes();
+            swarm.behaviour_mut().rpc.respond(peer, call, response);
+        }
+    }
+
+    async fn run_store(mut swarm: libp2p::Swarm<Behaviour>, fetches: Fetches) {
+        let mut pieces = HashMap::<(BlockId, PieceIndex), Piece>::new();
+        loop {
+            let (peer, call, body) = next_request(&mut swarm).await;
+            let rpc = &mut swarm.behaviour_mut().rpc;
+            let (&prefix, mut body) = body.split_first().unwrap();
+            match prefix {
+                StorePieces::PREFIX => {
+                    let (_, block, start, Reminder(data)) =
+                        <StorePieces as Protocol>::Request::decode(&mut body).unwrap();
+                    for (i, piece) in data.chunks(PIECE_SIZE).enumerate() {
+                        pieces.insert((block, start + i as PieceIndex), piece.try_into().unwrap());
+                    }
+                    rpc.respond(peer, call, Ok::<_, StorePiecesError>(()).to_bytes());
+                }
+                FetchPieces::PREFIX if fetches == Fetches::Ignore => {}
+                FetchPieces::PREFIX if fetches == Fetches::Fail => {
+                    let res = Err::<Reminder, _>(FetchPiecesError::NotFound);
+                    rpc.respond(peer, call, res.to_bytes());
+                }
+                FetchPieces::PREFIX => {
+                    let (_, block, range) =
+                        <FetchPieces as Protocol>::Request::decode(&mut body).unwrap();
+                    for start in range.clone().step_by(MAX_TRANSFER_PIECES as usize) {
+                        let end = range.end.min(start + MAX_TRANSFER_PIECES);
+                        let chunk =
+                            (start..end).flat_map(|i| pieces[&(block, i)]).collect::<Vec<_>>();
+                        let res = Ok::<_, FetchPiecesError>(Reminder(&chunk));
+                        rpc.respond_chunk(peer, call, res.to_bytes());
+                    }
+                    rpc.finish_response(peer, call);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_and_download() {
+        let (_, satelite_route, satelite) = node(7400);
+        let stores = (0..MAX_PIECES).map(|i| node(7401 + i as u16)).collect::<Vec<_>>();
+        let identities = stores.iter().map(|(identity, ..)| identity.public().to_bytes());
+        let identities = identities.collect::<ArrayVec<_, MAX_PIECES>>().into_inner().unwrap();
+        tokio::spawn(run_satelite(satelite, identities));
+
+        let mut client = Client::new(ed25519::Keypair::generate(), satelite_route);
+        for (i, (_, route, swarm)) in stores.into_iter().enumerate() {
+            client.add_store(route);
+            // a silent data store is raced past, a failing one is replaced
+            let fetches = match i {
+                0 => Fetches::Ignore,
+                1 => Fetches::Fail,
+                _ => Fetches::Serve,
+            };
+            tokio::spawn(run_store(swarm, fetches));
+        }
+
+        let object = (0..FRAME_SIZE * 3 + 100).map(|i| i as u8).collect::<Vec<_>>();
+        let handle = client.upload(object.as_slice(), object.len() as u64).await.unwrap();
+        assert_eq!(handle.allocation.size, encrypted_size(object.len() as u64));
+
+        let mut downloaded = vec![];
+        client.download(&handle, &mut downloaded).await.unwrap();
+        assert_eq!(downloaded, object);
+    }
+
+    #[test]
+    fn rebuild_from_any_data_pieces_worth_of_shares() {
+        let data = (0..ROW_SIZE * 2 + 100).map(|i| i as u8).collect::<Vec<_>>();
+        let mut codec = ErasureCodec::default();
+        let rows = encode_rows(&codec, &data);
+        assert_eq!(rows.len(), 3);
+
+        // keep the last shares only so that most of the data pieces are lost
+        let mut shares = (MAX_PIECES - DATA_PIECES..MAX_PIECES)
+            .map(|store| (store, rows.iter().flat_map(|row| row[store]).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        let mut out = vec![];
+        rebuild_segment(&mut codec, &mut shares, &mut out).unwrap();
+
+        assert_eq!(out.len(), ROW_SIZE * 3);
+        assert_eq!(out[..data.len()], data);
+    }
+}
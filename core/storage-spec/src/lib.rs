@@ -13,6 +13,7 @@ use arrayvec::ArrayVec;
 pub mod metabase;
 pub mod protocol;
 pub mod sorted_compact_vec;
+pub mod ticket;
 
 pub const PIECE_SIZE: usize = 1024;
 pub const DATA_PIECES: usize = if cfg!(debug_assertions) { 4 } else { 16 };
@@ -51,6 +52,7 @@ impl Codec {
         self.inner.encode(data.flatten(), parity.flatten_mut()).unwrap();
     }
 
+    /// Rebuilds the data pieces in place, afterwards `shards[i]` holds the `i`-th one.
     pub fn reconstruct(
         &mut self,
         shards: &mut ReconstructBundle,
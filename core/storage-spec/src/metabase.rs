@@ -69,7 +69,7 @@ macro_rules! decl_db {
 
 decl_db! {
     DbContext, Db;
-    stores: StoreId, StoreCx, StoreDb, u16, u16, Store;
+    stores: StoreId, StoreCx, StoreDb, u32, u16, Store;
     blocks: BlockId, BlockCx, BlockDb, u32, u32, Block;
     files: FileId, FileCx, FileDb, u64, u64, FileMeta;
     block_lists: BlockListId, BlockListCx, BlockListDb, u32, u32, BlockChunk;
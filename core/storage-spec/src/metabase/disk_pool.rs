@@ -19,6 +19,8 @@ const CONTEXT_FILE_NAME: &str = "context.dp";
 const GROUP_FILE_NAME_PREFIX: &str = "group";
 
 const FILE_COUNT: usize = 1024;
+/// Ids past this would land outside of the group files.
+const MAX_RECORDS: usize = FILE_COUNT << (32 - FILE_COUNT.ilog2());
 
 /// # Safety
 /// The type needs to be safely transmutabe to array of bytes.
@@ -168,7 +170,9 @@ impl<T: SortedElement + for<'a> Codec<'a>> Context<T> {
 
 impl<T: SortedElement> Default for Context<T> {
     fn default() -> Self {
-        Self { free_blocks: SortedCompactVec::new() }
+        let mut free_blocks = SortedCompactVec::allocated();
+        free_blocks.push_range(T::MIN..T::try_from(MAX_RECORDS).unwrap_or(T::MAX));
+        Self { free_blocks }
     }
 }
 
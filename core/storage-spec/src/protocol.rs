@@ -1 +1,204 @@
-component_utils::compose_protocols! {}
+use {
+    crate::{
+        metabase::{BlockId, StoreId, StoreIdentity},
+        ticket::{AllocationTicket, TicketError},
+        ObjectId, BLOCK_PIECES, MAX_PIECES,
+    },
+    component_utils::{arrayvec::ArrayVec, Codec, Reminder},
+    std::ops::Range,
+};
+
+/// Rpc protocol spoken between clients, stores and satellites. The trailing version is bumped
+/// whenever a message below changes shape or the order of messages changes, since the order
+/// decides the prefixes, so that mismatched peers fail to negotiate instead of misreading.
+pub const PROTOCOL_NAME: &str = "/storage/6";
+
+/// Index of a piece inside a block fragment held by one store.
+pub type PieceIndex = u32;
+
+/// Most pieces moved by one [`StorePieces`] request or one chunk of a [`FetchPieces`]
+/// response, keeps the packets under the rpc buffer size.
+pub const MAX_TRANSFER_PIECES: PieceIndex = 8;
+
+/// Block of the allocation together with the stores holding its pieces, `stores[i]` holds the
+/// `i`-th erasure code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec)]
+pub struct BlockPlacement {
+    pub block: BlockId,
+    pub stores: [StoreIdentity; MAX_PIECES],
+}
+
+/// Where the pieces of a file live. The file starts at `in_block_start` of the first block and
+/// ends at `in_block_end` of the last one, blocks in between are used fully.
+#[derive(Clone, Debug, PartialEq, Eq, Codec)]
+pub struct Allocation {
+    pub file: ObjectId,
+    pub size: u64,
+    pub piece_count: u64,
+    pub in_block_start: PieceIndex,
+    pub in_block_end: PieceIndex,
+    pub blocks: Vec<BlockPlacement>,
+}
+
+impl Allocation {
+    /// Pieces the file occupies in each of its blocks.
+    pub fn block_pieces(&self) -> impl Iterator<Item = (BlockId, Range<PieceIndex>)> + '_ {
+        let last = self.blocks.len().saturating_sub(1);
+        self.blocks.iter().enumerate().map(move |(i, placement)| {
+            let start = if i == 0 { self.in_block_start } else { 0 };
+            let end = if i == last { self.in_block_end } else { BLOCK_PIECES as PieceIndex };
+            (placement.block, start..end)
+        })
+    }
+}
+
+/// Pieces of one block as received from its stores, position in the block group is kept so
+/// that the satellite can tell who sent what.
+#[derive(Clone, Codec)]
+pub struct CheaterReport<'a> {
+    pub file: ObjectId,
+    pub block: BlockId,
+    pub pieces: Range<PieceIndex>,
+    pub shards: ArrayVec<(u8, &'a [u8]), MAX_PIECES>,
+}
+
+component_utils::compose_protocols! {
+    fn AllocateFile(ObjectId, u64) -> Result<(Allocation, AllocationTicket), AllocateFileError>;
+    fn StorePieces<'a>(AllocationTicket, BlockId, PieceIndex, Reminder<'a>) -> Result<(), StorePiecesError>;
+    // answered with chunks of at most MAX_TRANSFER_PIECES pieces, each encoded as the response
+    fn FetchPieces<'a>(ObjectId, BlockId, Range<PieceIndex>) -> Result<Reminder<'a>, FetchPiecesError>;
+    fn DeleteFile(ObjectId) -> Result<(), DeleteFileError>;
+    fn ExtendFile(ObjectId, u64) -> Result<(Allocation, AllocationTicket), ExtendFileError>;
+    fn ReportCheater<'a>(CheaterReport<'a>) -> Result<ArrayVec<StoreIdentity, MAX_PIECES>, ReportCheaterError>;
+    // ticket holder to store, the covered ranges are swap removed from the block fragments
+    fn FreePieces(AllocationTicket) -> Result<(), FreePiecesError>;
+    // store to satellite, number of block fragments the store can still take
+    fn ReportCapacity(u32) -> Result<StoreId, ReportCapacityError>;
+}
+
+/// Sent in place of the response when the node does not serve the request prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Rejection {
+    #[error("request is not served by this node")]
+    Unsupported,
+}
+
+impl Rejection {
+    /// Responses are `Result`s starting with a bool so this can not be mistaken for them.
+    const TAG: u8 = 2;
+}
+
+impl<'a> Codec<'a> for Rejection {
+    fn encode(&self, buffer: &mut impl component_utils::Buffer) -> Option<()> {
+        buffer.push(Self::TAG)?;
+        buffer.push(match self {
+            Self::Unsupported => 0,
+        })
+    }
+
+    fn decode(buffer: &mut &'a [u8]) -> Option<Self> {
+        if *buffer.take_first()? != Self::TAG {
+            return None;
+        }
+        match buffer.take_first()? {
+            0 => Some(Self::Unsupported),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
+pub enum AllocateFileError {
+    #[error("file already exists")]
+    AlreadyExists,
+    #[error("file is empty")]
+    Empty,
+    #[error("not enough stores with free space")]
+    NotEnoughSpace,
+    #[error("satellite failed to record the allocation")]
+    Internal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
+pub enum StorePiecesError {
+    #[error("pieces are not allocated to this store")]
+    NotAllocated,
+    #[error("pieces do not fit the allocation")]
+    OutOfBounds,
+    #[error("data is not made of whole pieces")]
+    PartialPiece,
+    #[error("store ran out of disk space")]
+    Full,
+    #[error(transparent)]
+    InvalidTicket(TicketError),
+    #[error("store failed to write the pieces")]
+    Internal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
+pub enum FetchPiecesError {
+    #[error("block not found")]
+    NotFound,
+    #[error("pieces do not fit the block")]
+    OutOfBounds,
+    #[error("bandwidth allocation exhausted")]
+    BandwidthExhausted,
+    #[error("store failed to read the pieces")]
+    Internal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
+pub enum DeleteFileError {
+    #[error("file not found")]
+    NotFound,
+    #[error("only the owner can delete the file")]
+    NotOwner,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
+pub enum ExtendFileError {
+    #[error("file not found")]
+    NotFound,
+    #[error("only the owner can extend the file")]
+    NotOwner,
+    #[error("new size {0} is not larger than the current one")]
+    NotLarger(u64),
+    #[error("not enough stores with free space")]
+    NotEnoughSpace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
+pub enum ReportCheaterError {
+    #[error("file not found")]
+    NotFound,
+    #[error("reported pieces do not belong to the file")]
+    InvalidReport,
+    #[error("not enough shards to find the cheater")]
+    NotEnoughShards,
+    #[error("all shards are consistent")]
+    NoCheater,
+    #[error("too many reports, try later")]
+    RateLimited,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
+pub enum FreePiecesError {
+    #[error("block not found")]
+    NotFound,
+    #[error("pieces do not fit the block")]
+    OutOfBounds,
+    #[error(transparent)]
+    InvalidTicket(TicketError),
+    #[error("store failed to free the pieces")]
+    Internal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
+pub enum ReportCapacityError {
+    #[error("satellite can not register more stores")]
+    TooManyStores,
+    #[error("store is not configured on the satellite")]
+    Unknown,
+    #[error("satellite failed to record the capacity")]
+    Internal,
+}
@@ -0,0 +1,160 @@
+use {
+    crate::{metabase::BlockId, protocol::PieceIndex, ObjectId},
+    component_utils::Codec,
+    crypto::{rand_core::CryptoRngCore, sign, Serialized, TransmutationCircle},
+    std::ops::Range,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec)]
+pub enum TicketKind {
+    Upload,
+    Delete,
+}
+
+/// Pieces of one block the ticket lets the user touch.
+#[derive(Clone, Debug, PartialEq, Eq, Codec)]
+pub struct TicketBlock {
+    pub block: BlockId,
+    pub pieces: Range<PieceIndex>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Codec)]
+pub struct Ticket {
+    pub kind: TicketKind,
+    pub file: ObjectId,
+    pub blocks: Vec<TicketBlock>,
+    /// Unix time in seconds, stores refuse the ticket after it.
+    pub expiry: u64,
+    /// Ed25519 key of the peer allowed to present the ticket.
+    pub user: sign::Ed,
+}
+
+/// Metadata the whitepaper wants signed by the satellite, stores check it on every upload and
+/// deletion instead of waiting for the satellite to tell them about the file.
+#[derive(Clone, Codec)]
+pub struct AllocationTicket {
+    pub ticket: Ticket,
+    pub satelite: Serialized<sign::PublicKey>,
+    pub signature: Serialized<sign::Signature>,
+}
+
+impl AllocationTicket {
+    pub fn new(keys: &sign::Keypair, ticket: Ticket, rng: impl CryptoRngCore) -> Self {
+        Self {
+            signature: keys.sign(&ticket.to_bytes(), rng).into_bytes(),
+            satelite: keys.public_key().into_bytes(),
+            ticket,
+        }
+    }
+
+    /// Checks whether `user` can use the ticket for `kind` at `now`. Returned hash of the
+    /// signing key has to match the satellite the store works for.
+    pub fn verify(
+        &self,
+        kind: TicketKind,
+        user: sign::Ed,
+        now: u64,
+    ) -> Result<crypto::Hash, TicketError> {
+        if self.ticket.kind != kind {
+            return Err(TicketError::WrongKind);
+        }
+
+        if self.ticket.user != user {
+            return Err(TicketError::WrongUser);
+        }
+
+        if self.ticket.expiry < now {
+            return Err(TicketError::Expired);
+        }
+
+        let pk = sign::PublicKey::from_ref(&self.satelite);
+        pk.verify(&self.ticket.to_bytes(), sign::Signature::from_ref(&self.signature))
+            .map_err(|_| TicketError::InvalidSignature)?;
+        Ok(crypto::hash::new(pk))
+    }
+
+    #[must_use]
+    pub fn pieces(&self, block: BlockId) -> Option<Range<PieceIndex>> {
+        self.ticket.blocks.iter().find(|b| b.block == block).map(|b| b.pieces.clone())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Codec, thiserror::Error)]
+pub enum TicketError {
+    #[error("ticket is for a different operation")]
+    WrongKind,
+    #[error("ticket was issued to someone else")]
+    WrongUser,
+    #[error("ticket expired")]
+    Expired,
+    #[error("ticket signature is invalid")]
+    InvalidSignature,
+    #[error("ticket was not issued by our satellite")]
+    UnknownSatelite,
+    #[error("ticket does not cover the pieces")]
+    NotCovered,
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, rand_core::OsRng};
+
+    const EXPIRY: u64 = 1000;
+    const USER: sign::Ed = [7; 32];
+
+    fn ticket(satelite: &sign::Keypair) -> AllocationTicket {
+        let ticket = Ticket {
+            kind: TicketKind::Upload,
+            file: [1; 32],
+            blocks: vec![TicketBlock { block: 2, pieces: 0..4 }],
+            expiry: EXPIRY,
+            user: USER,
+        };
+        AllocationTicket::new(satelite, ticket, OsRng)
+    }
+
+    #[test]
+    fn verify_checks_every_field() {
+        let satelite = sign::Keypair::new(OsRng);
+        let our_satelite = crypto::hash::new(&satelite.public_key());
+        let ticket = ticket(&satelite);
+
+        assert_eq!(ticket.verify(TicketKind::Upload, USER, EXPIRY), Ok(our_satelite));
+        assert_eq!(ticket.verify(TicketKind::Upload, USER, EXPIRY + 1), Err(TicketError::Expired));
+        assert_eq!(ticket.verify(TicketKind::Upload, [8; 32], 0), Err(TicketError::WrongUser));
+        assert_eq!(ticket.verify(TicketKind::Delete, USER, 0), Err(TicketError::WrongKind));
+    }
+
+    #[test]
+    fn verify_rejects_tampering() {
+        let satelite = sign::Keypair::new(OsRng);
+        let ticket = ticket(&satelite);
+
+        let mut wider = ticket.clone();
+        wider.ticket.blocks[0].pieces.end += 1;
+        assert_eq!(wider.verify(TicketKind::Upload, USER, 0), Err(TicketError::InvalidSignature));
+
+        // the file id follows the kind
+        let mut bytes = ticket.to_bytes();
+        bytes[1] ^= 1;
+        let flipped = AllocationTicket::decode(&mut bytes.as_slice()).unwrap();
+        assert_eq!(flipped.verify(TicketKind::Upload, USER, 0), Err(TicketError::InvalidSignature));
+
+        let other = sign::Keypair::new(OsRng);
+        let mut resigned = ticket.clone();
+        resigned.satelite = other.public_key().into_bytes();
+        assert_eq!(
+            resigned.verify(TicketKind::Upload, USER, 0),
+            Err(TicketError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn verify_returns_the_signing_satelite() {
+        let satelite = sign::Keypair::new(OsRng);
+        let other = sign::Keypair::new(OsRng);
+        let foreign = ticket(&other).verify(TicketKind::Upload, USER, 0).unwrap();
+        assert_eq!(foreign, crypto::hash::new(&other.public_key()));
+        assert_ne!(foreign, crypto::hash::new(&satelite.public_key()));
+    }
+}
@@ -17,29 +17,85 @@ use {
         },
         PeerId, StreamProtocol,
     },
-    std::{io, iter, task::Poll},
+    std::{
+        io, iter,
+        sync::{Arc, Weak},
+        task::Poll,
+    },
 };
 
 component_utils::decl_stream_protocol!(PROTOCOL_NAME = "streaming");
 
+component_utils::gen_config! {
+    ;;
+    /// Outgoing streams that can be open or opening towards one peer at once, requests over
+    /// the limit wait until some stream is released or closed.
+    max_streams_per_peer: usize = 32,
+    /// Keep released streams for later requests, only for protocols that can continue on
+    /// a used stream.
+    reuse_streams: bool = false,
+    max_pooled_per_peer: usize = 4,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Holds the slot of a handed out outgoing stream, the slot is freed once this is dropped or the
+/// connection of the stream closes.
+#[derive(Debug)]
+pub struct Slot {
+    peer: PeerId,
+    connection: ConnectionId,
+    _token: Arc<()>,
+}
+
 #[derive(Default)]
 pub struct Behaviour {
-    stream_requests: Vec<(PeerId, ConnectionId)>,
+    config: Config,
+    /// The last element counts how many times the request failed over to other connection.
+    stream_requests: Vec<(PeerId, ConnectionId, usize)>,
     dial_requests: Vec<(PeerId, Option<ConnectionId>, usize)>,
-    connected_peers: LinearMap<PeerId, ConnectionId>,
+    /// Streams are requested on the first connection, the rest are there for failover.
+    connected_peers: LinearMap<PeerId, Vec<ConnectionId>>,
+    /// Outgoing streams requested and not yet handed out.
+    requested_streams: LinearMap<PeerId, usize>,
+    handed_out: Vec<(PeerId, ConnectionId, Weak<()>)>,
+    waiting: Vec<PeerId>,
+    pool: Vec<(PeerId, ConnectionId, libp2p::Stream)>,
     events: Vec<Event>,
 }
 
 impl Behaviour {
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self { config, ..Default::default() }
+    }
+
     #[must_use]
     pub fn is_resolving_stream_for(&self, peer: PeerId) -> bool {
         self.stream_requests.iter().any(|(p, ..)| *p == peer)
             || self.dial_requests.iter().any(|(p, ..)| *p == peer)
+            || self.waiting.contains(&peer)
     }
 
     pub fn create_stream(&mut self, with: PeerId) {
-        if let Some(&id) = self.connected_peers.get(&with) {
-            self.stream_requests.push((with, id));
+        if let Some((_, connection, stream)) = self.pool.find_and_remove(|(p, ..)| *p == with) {
+            let slot = self.hand_out(with, connection);
+            self.events.push(Event::OutgoingStream(with, Ok((stream, slot))));
+            return;
+        }
+
+        if self.open_streams(with) >= self.config.max_streams_per_peer {
+            self.waiting.push(with);
+            return;
+        }
+        *self.requested_streams.entry(with) += 1;
+
+        if let Some(&id) = self.connected_peers.get(&with).and_then(|c| c.first()) {
+            self.stream_requests.push((with, id, 0));
         } else if let Some((_, _, count)) = self.dial_requests.iter_mut().find(|(p, ..)| *p == with)
         {
             *count += 1;
@@ -47,6 +103,52 @@ impl Behaviour {
             self.dial_requests.push((with, None, 0));
         }
     }
+
+    /// Hands back an outgoing stream the caller is done with, it is pooled if reuse is enabled.
+    /// Dropping the stream with its [`Slot`] frees the slot as well.
+    pub fn release_stream(&mut self, stream: libp2p::Stream, slot: Slot) {
+        let Slot { peer, connection, .. } = slot;
+        if self.config.reuse_streams
+            && self.connected_peers.get(&peer).is_some_and(|c| c.contains(&connection))
+            && self.pool.iter().filter(|(p, ..)| *p == peer).count()
+                < self.config.max_pooled_per_peer
+        {
+            self.pool.push((peer, connection, stream));
+        }
+        self.wake_waiting();
+    }
+
+    fn open_streams(&self, peer: PeerId) -> usize {
+        self.requested_streams.get(&peer).copied().unwrap_or(0)
+            + self.handed_out.iter().filter(|(p, _, t)| *p == peer && t.strong_count() != 0).count()
+    }
+
+    fn hand_out(&mut self, peer: PeerId, connection: ConnectionId) -> Slot {
+        let token = Arc::new(());
+        self.handed_out.push((peer, connection, Arc::downgrade(&token)));
+        Slot { peer, connection, _token: token }
+    }
+
+    /// Frees the slot of a requested stream that was handed out or failed.
+    fn request_finished(&mut self, peer: PeerId) {
+        if let Some(open) = self.requested_streams.get_mut(&peer) {
+            *open = open.saturating_sub(1);
+        }
+        self.wake_waiting();
+    }
+
+    fn wake_waiting(&mut self) {
+        self.handed_out.retain(|(.., t)| t.strong_count() != 0);
+        let mut i = 0;
+        while let Some(&peer) = self.waiting.get(i) {
+            if self.open_streams(peer) < self.config.max_streams_per_peer {
+                self.waiting.remove(i);
+                self.create_stream(peer);
+            } else {
+                i += 1;
+            }
+        }
+    }
 }
 
 fn new_dial_opts(peer: PeerId, id: &mut Option<ConnectionId>) -> DialOpts {
@@ -66,7 +168,7 @@ impl NetworkBehaviour for Behaviour {
         _: &libp2p::Multiaddr,
         _: &libp2p::Multiaddr,
     ) -> Result<libp2p::swarm::THandler<Self>, libp2p::swarm::ConnectionDenied> {
-        Ok(Handler::new(|| PROTOCOL_NAME))
+        Ok(Handler::new(PROTOCOL_NAME))
     }
 
     fn handle_established_outbound_connection(
@@ -76,7 +178,7 @@ impl NetworkBehaviour for Behaviour {
         _: &libp2p::Multiaddr,
         _: libp2p::core::Endpoint,
     ) -> Result<libp2p::swarm::THandler<Self>, libp2p::swarm::ConnectionDenied> {
-        Ok(Handler::new(|| PROTOCOL_NAME))
+        Ok(Handler::new(PROTOCOL_NAME))
     }
 
     fn on_swarm_event(&mut self, event: libp2p::swarm::FromSwarm) {
@@ -93,15 +195,34 @@ impl NetworkBehaviour for Behaviour {
             {
                 if let DialError::DialPeerConditionFalse(_) = error {
                 } else {
+                    self.dial_requests.retain(|(p, ..)| *p != peer);
                     for _ in 0..=count {
                         self.events.push(Event::OutgoingStream(
                             peer,
                             Err(StreamUpgradeError::Io(io::Error::other(error.to_string()))),
                         ));
+                        self.request_finished(peer);
                     }
                 }
             }
             libp2p::swarm::FromSwarm::ConnectionClosed(c) => {
+                // streams of the connection are dead even if their holders did not drop them yet
+                self.handed_out.retain(|(_, id, _)| *id != c.connection_id);
+                self.pool.retain(|(_, id, _)| *id != c.connection_id);
+                self.wake_waiting();
+
+                let remaining = self.connected_peers.get_mut(&c.peer_id).and_then(|connections| {
+                    connections.retain(|&id| id != c.connection_id);
+                    connections.first().copied()
+                });
+                if let Some(other) = remaining {
+                    self.stream_requests
+                        .iter_mut()
+                        .filter(|(p, id, _)| *p == c.peer_id && *id == c.connection_id)
+                        .for_each(|(_, id, _)| *id = other);
+                    return;
+                }
+
                 self.connected_peers.remove(&c.peer_id);
 
                 let count = self.stream_requests.extract_if(|(p, ..)| *p == c.peer_id).count();
@@ -121,9 +242,9 @@ impl NetworkBehaviour for Behaviour {
                     self.dial_requests.find_and_remove(|(p, ..)| *p == c.peer_id)
                 {
                     self.stream_requests
-                        .extend(iter::repeat((c.peer_id, c.connection_id)).take(count + 1));
+                        .extend(iter::repeat((c.peer_id, c.connection_id, 0)).take(count + 1));
                 }
-                self.connected_peers.insert(c.peer_id, c.connection_id);
+                self.connected_peers.entry(c.peer_id).push(c.connection_id);
             }
             _ => {}
         }
@@ -132,23 +253,43 @@ impl NetworkBehaviour for Behaviour {
     fn on_connection_handler_event(
         &mut self,
         peer_id: PeerId,
-        _connection_id: ConnectionId,
+        connection_id: ConnectionId,
         event: libp2p::swarm::THandlerOutEvent<Self>,
     ) {
-        self.events.push(match event {
+        let event = match event {
             Ok(stream) => Event::IncomingStream(peer_id, stream),
-            Err(e) => Event::OutgoingStream(peer_id, e),
-        });
+            Err(Ok(stream)) => {
+                let slot = self.hand_out(peer_id, connection_id);
+                self.request_finished(peer_id);
+                Event::OutgoingStream(peer_id, Ok((stream, slot)))
+            }
+            Err(Err((e, failovers))) => {
+                let connections = self.connected_peers.get(&peer_id).map_or(&[][..], Vec::as_slice);
+                if failovers + 1 < connections.len()
+                    && let Some(&other) = connections.iter().find(|&&id| id != connection_id)
+                {
+                    log::debug!("stream to {peer_id} failed with {e}, trying other connection");
+                    self.stream_requests.push((peer_id, other, failovers + 1));
+                    return;
+                }
+
+                self.request_finished(peer_id);
+                Event::OutgoingStream(peer_id, Err(e))
+            }
+        };
+        self.events.push(event);
     }
 
     fn poll(
         &mut self,
         _: &mut std::task::Context<'_>,
     ) -> Poll<libp2p::swarm::ToSwarm<Self::ToSwarm, libp2p::swarm::THandlerInEvent<Self>>> {
+        self.wake_waiting();
+
         self.dial_requests.retain(|(p, _, count)| {
-            if let Some(id) = self.connected_peers.get(p) {
+            if let Some(id) = self.connected_peers.get(p).and_then(|c| c.first()) {
                 for _ in 0..=*count {
-                    self.stream_requests.push((*p, *id));
+                    self.stream_requests.push((*p, *id, 0));
                 }
                 false
             } else {
@@ -160,11 +301,11 @@ impl NetworkBehaviour for Behaviour {
             return Poll::Ready(libp2p::swarm::ToSwarm::Dial { opts: new_dial_opts(*peer, id) });
         }
 
-        if let Some((peer_id, conn_id)) = self.stream_requests.pop() {
+        if let Some((peer_id, conn_id, failovers)) = self.stream_requests.pop() {
             return Poll::Ready(libp2p::swarm::ToSwarm::NotifyHandler {
                 peer_id,
                 handler: NotifyHandler::One(conn_id),
-                event: (),
+                event: failovers,
             });
         }
 
@@ -181,36 +322,38 @@ impl NetworkBehaviour for Behaviour {
 #[allow(clippy::type_complexity)]
 pub enum Event {
     IncomingStream(PeerId, libp2p::Stream),
-    OutgoingStream(PeerId, Result<libp2p::Stream, Error>),
+    /// The stream counts towards the peer limit until the [`Slot`] is dropped or released.
+    OutgoingStream(PeerId, Result<(libp2p::Stream, Slot), Error>),
 }
 
 pub type Error = StreamUpgradeError<void::Void>;
 
 pub struct Handler {
-    requested: usize,
-    stream: Vec<Result<libp2p::Stream, Result<libp2p::Stream, Error>>>,
+    /// Failover counts of the requested streams.
+    requested: Vec<usize>,
+    stream: Vec<Result<libp2p::Stream, Result<libp2p::Stream, (Error, usize)>>>,
     waker: Option<std::task::Waker>,
-    proto: fn() -> StreamProtocol,
+    proto: StreamProtocol,
 }
 
 impl Handler {
-    pub fn new(proto: fn() -> StreamProtocol) -> Self {
-        Self { requested: 0, stream: Vec::new(), waker: None, proto }
+    pub fn new(proto: StreamProtocol) -> Self {
+        Self { requested: Vec::new(), stream: Vec::new(), waker: None, proto }
     }
 }
 
 impl ConnectionHandler for Handler {
-    type FromBehaviour = ();
+    type FromBehaviour = usize;
     type InboundOpenInfo = ();
     type InboundProtocol = ReadyUpgrade<StreamProtocol>;
-    type OutboundOpenInfo = ();
+    type OutboundOpenInfo = usize;
     type OutboundProtocol = ReadyUpgrade<StreamProtocol>;
-    type ToBehaviour = Result<libp2p::Stream, Result<libp2p::Stream, Error>>;
+    type ToBehaviour = Result<libp2p::Stream, Result<libp2p::Stream, (Error, usize)>>;
 
     fn listen_protocol(
         &self,
     ) -> libp2p::swarm::SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
-        libp2p::swarm::SubstreamProtocol::new(ReadyUpgrade::new((self.proto)()), ())
+        libp2p::swarm::SubstreamProtocol::new(ReadyUpgrade::new(self.proto.clone()), ())
     }
 
     fn poll(
@@ -228,20 +371,19 @@ impl ConnectionHandler for Handler {
             return Poll::Ready(libp2p::swarm::ConnectionHandlerEvent::NotifyBehaviour(stream));
         }
 
-        if let Some(next_requested) = self.requested.checked_sub(1) {
-            self.requested = next_requested;
+        if let Some(failovers) = self.requested.pop() {
             return Poll::Ready(libp2p::swarm::ConnectionHandlerEvent::OutboundSubstreamRequest {
-                protocol: SubstreamProtocol::new(ReadyUpgrade::new((self.proto)()), ()),
+                protocol: SubstreamProtocol::new(ReadyUpgrade::new(self.proto.clone()), failovers),
             });
         }
         Poll::Pending
     }
 
-    fn on_behaviour_event(&mut self, (): Self::FromBehaviour) {
+    fn on_behaviour_event(&mut self, failovers: Self::FromBehaviour) {
         if let Some(waker) = self.waker.take() {
             waker.wake();
         }
-        self.requested += 1;
+        self.requested.push(failovers);
     }
 
     fn on_connection_event(
@@ -257,7 +399,7 @@ impl ConnectionHandler for Handler {
         let ev = match event {
             E::FullyNegotiatedInbound(i) => Ok(i.protocol),
             E::FullyNegotiatedOutbound(o) => Err(Ok(o.protocol)),
-            E::DialUpgradeError(e) => Err(Err(e.error)),
+            E::DialUpgradeError(e) => Err(Err((e.error, e.info))),
             _ => return,
         };
         if let Some(waker) = self.waker.take() {
@@ -357,7 +499,8 @@ mod test {
                     libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
                         Event::OutgoingStream(_, e),
                     )) => {
-                        e.unwrap();
+                        let (stream, slot) = e.unwrap();
+                        swarm.behaviour_mut().rpc.release_stream(stream, slot);
                         pending_request_count -= 1;
                     }
                     libp2p::swarm::SwarmEvent::Behaviour(TestBehatiourEvent::Rpc(
@@ -380,4 +523,34 @@ mod test {
             .next()
             .await;
     }
+
+    #[test]
+    fn test_stream_limit() {
+        let mut streaming = Behaviour::new(Config::new().max_streams_per_peer(1));
+        let peer = PeerId::random();
+
+        streaming.create_stream(peer);
+        streaming.create_stream(peer);
+        assert_eq!(streaming.waiting, [peer]);
+        assert_eq!(streaming.dial_requests, [(peer, None, 0)]);
+
+        streaming.request_finished(peer);
+        assert!(streaming.waiting.is_empty());
+        assert_eq!(streaming.dial_requests, [(peer, None, 1)]);
+    }
+
+    #[test]
+    fn test_dropped_stream_frees_slot() {
+        let mut streaming = Behaviour::new(Config::new().max_streams_per_peer(1));
+        let peer = PeerId::random();
+
+        let slot = streaming.hand_out(peer, ConnectionId::new_unchecked(0));
+        streaming.create_stream(peer);
+        assert_eq!(streaming.waiting, [peer]);
+
+        drop(slot);
+        streaming.wake_waiting();
+        assert!(streaming.waiting.is_empty());
+        assert_eq!(streaming.dial_requests, [(peer, None, 0)]);
+    }
 }
@@ -7,7 +7,9 @@ use {
     web_sys::js_sys::wasm_bindgen,
 };
 
-pub async fn node(name: UserName) -> Result<chain_api::Client<WebSigner>, chain_api::Error> {
+pub type Client = chain_api::Client<WebSigner>;
+
+pub async fn node(name: UserName) -> Result<Client, chain_api::Error> {
     component_utils::build_env!(CHAIN_NODE);
     chain_api::Client::with_signer(CHAIN_NODE, WebSigner(name)).await
 }
@@ -27,6 +29,13 @@ pub fn min_nodes() -> usize {
     MIN_NODES.parse().unwrap()
 }
 
+/// Websocket addresses with a `/p2p` suffix, separated by commas. Lookups start from them so
+/// that the node list does not have to be fetched, without them it is.
+pub fn boot_nodes() -> Vec<libp2p::Multiaddr> {
+    component_utils::build_env!(CLIENT_BOOT_NODES);
+    CLIENT_BOOT_NODES.split(',').filter(|s| !s.is_empty()).map(|s| s.parse().unwrap()).collect()
+}
+
 async fn sign_with_wallet(payload: &str) -> Result<Vec<u8>, JsValue> {
     #[wasm_bindgen::prelude::wasm_bindgen]
     extern "C" {
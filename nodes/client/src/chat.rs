@@ -6,7 +6,7 @@ use {
     },
     anyhow::Context,
     chat_spec::{
-        username_to_raw, ChatEvent, ChatName, CreateChat, FetchMessages, FetchProfile, UserName,
+        username_to_raw, ChatEvent, ChatName, FetchMessages, FetchProfile, SendMail, UserName,
     },
     component_utils::{Codec, DropFn, Reminder},
     crypto::{
@@ -190,7 +190,21 @@ pub fn Chat(state: crate::State) -> impl IntoView {
             let (mut sub, owner) = requests().subscribe(chat)?;
             log::info!("subscribed to chat: {:?}", chat);
             subscription_owner.set_value(Some(owner)); // drop old subscription
-            while let Some(ChatEvent::Message(proof, Reminder(message))) = sub.next().await {
+            while let Some(event) = sub.next().await {
+                let (proof, message) = match event {
+                    ChatEvent::Message(proof, Reminder(message)) => (proof, message),
+                    // TODO: display typing indicators and presence
+                    ChatEvent::Ephemeral(..) => continue,
+                    ChatEvent::Deleted(proof) if proof.verify() => {
+                        log::info!("chat {:?} was deleted", chat);
+                        break;
+                    }
+                    ChatEvent::Deleted(_) => {
+                        log::warn!("received chat deletion with invalid proof");
+                        continue;
+                    }
+                };
+
                 if !proof.verify() {
                     log::warn!("received message with invalid proof");
                     continue;
@@ -266,7 +280,7 @@ pub fn Chat(state: crate::State) -> impl IntoView {
                 anyhow::bail!("chat already exists");
             }
 
-            requests().dispatch::<CreateChat>((chat, my_id)).await.context("creating chat")?;
+            requests().dispatch_create_chat(chat, my_id).await.context("creating chat")?;
 
             let meta = node::ChatMeta::new();
             state.vault.update(|v| _ = v.chats.insert(chat, meta));
@@ -334,7 +348,7 @@ pub fn Chat(state: crate::State) -> impl IntoView {
             .into_bytes();
         let invite = Mail::ChatInvite { chat, cp }.to_bytes();
         requests
-            .dispatch_mail((invitee.sign, Reminder(invite.as_slice())))
+            .dispatch::<SendMail>((invitee.sign, Reminder(invite.as_slice())))
             .await
             .context("sending invite")?;
 
@@ -388,7 +402,7 @@ pub fn Chat(state: crate::State) -> impl IntoView {
             .to_bytes();
 
         requests
-            .dispatch_mail((invitee.sign, Reminder(&invite)))
+            .dispatch::<SendMail>((invitee.sign, Reminder(&invite)))
             .await
             .context("sending invite")?;
 
@@ -465,7 +479,7 @@ pub fn Chat(state: crate::State) -> impl IntoView {
                 .to_bytes();
                 async move {
                     requests()
-                        .dispatch_mail((member.identity, Reminder(&message)))
+                        .dispatch::<SendMail>((member.identity, Reminder(&message)))
                         .await
                         .with_context(|| format!("sending message to {name}"))
                 }
@@ -1,6 +1,7 @@
 use {
     crate::handle_js_err,
-    chat_spec::{ChatName, UserName},
+    chat_spec::{ChatName, EventSeq, Identity, UserName},
+    leptos::web_sys,
 };
 
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
@@ -41,6 +42,29 @@ pub async fn save_messages(messages: Vec<Message>) -> anyhow::Result<()> {
     ffi::saveMessages(messages).await.map_err(handle_js_err)
 }
 
+fn mail_seq_key(identity: Identity) -> String {
+    format!("mail-seq-{}", hex::encode(identity))
+}
+
+fn local_storage() -> anyhow::Result<web_sys::Storage> {
+    web_sys::window()
+        .and_then(|w| w.local_storage().ok().flatten())
+        .ok_or_else(|| anyhow::anyhow!("local storage is not available"))
+}
+
+/// Sequence of the first mail we have not processed yet, acknowledged with the next read.
+pub fn next_mail_seq(identity: Identity) -> EventSeq {
+    local_storage()
+        .ok()
+        .and_then(|s| s.get_item(&mail_seq_key(identity)).ok().flatten())
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn save_next_mail_seq(identity: Identity, seq: EventSeq) -> anyhow::Result<()> {
+    local_storage()?.set_item(&mail_seq_key(identity), &seq.to_string()).map_err(handle_js_err)
+}
+
 #[derive(Clone)]
 pub struct MessageCursor {
     inner: ffi::MessageCursor,
@@ -18,8 +18,8 @@ use {
     argon2::Argon2,
     chain_api::UserIdentity,
     chat_spec::{
-        username_to_raw, ChatName, FetchProfile, Identity, Nonce, Proof, ReadMail, SetVault,
-        UserName,
+        username_to_raw, ChatName, FetchProfile, Identity, Nonce, Proof, ReadMail, SendMail,
+        SetVault, UserName,
     },
     component_utils::{Codec, Reminder},
     crypto::{
@@ -220,7 +220,7 @@ fn App() -> impl IntoView {
         .to_bytes();
 
         dispatch
-            .dispatch_mail((identity_hashes.sign, Reminder(&invite)))
+            .dispatch::<SendMail>((identity_hashes.sign, Reminder(&invite)))
             .await
             .context("sending invite")?;
 
@@ -395,11 +395,12 @@ fn App() -> impl IntoView {
                 Node::new(keys, wboot_phase).await.inspect_err(|_| navigate_to("/login"))?;
 
             let mut dispatch_clone = dispatch.clone();
-            let cloned_enc = enc.clone();
-            handled_spawn_local("reading mail", async move {
+            let listen = async move {
                 let proof = state.next_mail_proof().unwrap();
                 let inner_dispatch = dispatch_clone.clone();
-                let Reminder(list) = dispatch_clone.dispatch::<ReadMail>(proof).await?;
+                let from = db::next_mail_seq(identity);
+                let (next_seq, Reminder(list)) =
+                    dispatch_clone.dispatch::<ReadMail>((proof, from)).await?;
 
                 let mut new_messages = Vec::new();
                 for mail in chat_spec::unpack_mail(list) {
@@ -407,7 +408,7 @@ fn App() -> impl IntoView {
                         handle_mail(
                             mail,
                             &inner_dispatch,
-                            cloned_enc.clone(),
+                            enc.clone(),
                             my_id,
                             my_name,
                             &mut new_messages,
@@ -415,14 +416,13 @@ fn App() -> impl IntoView {
                         .context("receiving a mail"),
                     );
                 }
-                db::save_messages(new_messages).await
-            });
+                handle_error(db::save_messages(new_messages).await);
+                handle_error(db::save_next_mail_seq(identity, next_seq));
 
-            let (mut account, id) = dispatch.subscribe(identity).unwrap();
-            let dispatch_clone = dispatch.clone();
-            account_sub.set_value(Some(id));
-            let listen = async move {
-                while let Some(Reminder(mail)) = account.next().await {
+                // mail that arrived after the read is replayed before anything new
+                let (mut account, id) = dispatch_clone.subscribe_profile(identity, next_seq)?;
+                account_sub.set_value(Some(id));
+                while let Some((seq, Reminder(mail))) = account.next().await {
                     let mut new_messages = Vec::new();
                     handle_error(
                         handle_mail(
@@ -436,6 +436,7 @@ fn App() -> impl IntoView {
                         .context("receiving a mail"),
                     );
                     handle_error(db::save_messages(new_messages).await);
+                    handle_error(db::save_next_mail_seq(identity, seq + 1));
                 }
 
                 anyhow::Result::Ok(())
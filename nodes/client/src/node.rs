@@ -3,16 +3,20 @@ use {
     anyhow::Context,
     chain_api::RawUserName,
     chat_spec::{
-        username_to_raw, CallId, ChatName, CreateProfile, FetchVault, Identity, Nonce,
-        PossibleTopic, Proof, RawChatName, Repl, UserName, REPLICATION_FACTOR,
+        pow_difficulty, username_to_raw, CallId, ChatName, CreateAccountError, CreateProfile,
+        FetchVault, Identity, NetworkParams, Nonce, PossibleTopic, Proof, RawChatName, Repl,
+        ReplError, UserName,
+    },
+    component_utils::{
+        futures::{self, future::LocalBoxFuture, stream::FuturesUnordered},
+        Codec, FindAndRemove, LinearMap, Reminder,
     },
-    component_utils::{futures, Codec, FindAndRemove, LinearMap, Reminder},
     crypto::{
         decrypt,
         enc::{self, ChoosenCiphertext, Ciphertext},
         sign, FixedAesPayload, Serialized, TransmutationCircle,
     },
-    dht::Route,
+    dht::{lookup::LookupId, Route},
     leptos::signal_prelude::*,
     libp2p::{
         core::upgrade::Version,
@@ -27,6 +31,7 @@ use {
         collections::{HashMap, HashSet},
         io,
         net::IpAddr,
+        rc::Rc,
         task::Poll,
         time::Duration,
     },
@@ -183,6 +188,13 @@ pub struct Node {
     pending_requests: LinearMap<CallId, libp2p::futures::channel::oneshot::Sender<RawResponse>>,
     pending_topic_search: LinearMap<PathId, Vec<RequestInit>>,
     requests: RequestStream,
+    params: NetworkParams,
+    chain: Rc<crate::chain::Client>,
+    /// Topics whose replicators were looked up and confirmed, unused without lookups.
+    found_topics: HashSet<PossibleTopic>,
+    /// Searches waiting for a lookup, its confirmation or a key of the replicators.
+    searches: Vec<(PossibleTopic, Option<LookupId>, Vec<RequestInit>)>,
+    confirmations: FuturesUnordered<LocalBoxFuture<'static, (PossibleTopic, Vec<Route>)>>,
 }
 
 impl Node {
@@ -194,12 +206,26 @@ impl Node {
 
         set_state!(FetchNodesAndProfile);
 
+        let boot_nodes = crate::chain::boot_nodes();
         let (mut request_dispatch, commands) = RequestDispatch::new();
         let chain_api = crate::chain::node(keys.name).await?;
-        let node_request = chain_api.list(crate::chain::node_contract());
+        let node_request = async {
+            if boot_nodes.is_empty() {
+                chain_api.list(crate::chain::node_contract()).await
+            } else {
+                Ok(Vec::new())
+            }
+        };
+        let params_request = chain_api.network_params(crate::chain::node_contract());
         let profile_request = chain_api
             .get_profile_by_name(crate::chain::user_contract(), username_to_raw(keys.name));
-        let (node_data, profile_hash) = futures::try_join!(node_request, profile_request)?;
+        let (node_data, params, profile_hash) =
+            futures::try_join!(node_request, params_request, profile_request)?;
+        let mut params = NetworkParams::try_from(params).context("validating network params")?;
+        if params.virtual_nodes != 0 {
+            params.placement_beacon =
+                chain_api.placement_beacon(crate::chain::node_contract()).await?;
+        }
         let profile_hash = profile_hash.context("profile not found")?;
         let profile = keys.to_identity();
 
@@ -210,15 +236,18 @@ impl Node {
 
         set_state!(InitiateConnection);
 
-        let keypair = identity::Keypair::generate_ed25519();
+        let ed_keypair = ed25519::Keypair::generate();
+        let keypair = identity::Keypair::from(ed_keypair.clone());
         let peer_id = keypair.public().to_peer_id();
 
+        let dht = dht::Behaviour::default().with_transports(vec![dht::Transport::Ws]);
         let behaviour = Behaviour {
             onion: onion::Behaviour::new(
                 onion::Config::new(None, peer_id).keep_alive_interval(Duration::from_secs(100)),
             ),
             key_share: onion::key_share::Behaviour::default(),
-            dht: dht::Behaviour::default(),
+            dht: if boot_nodes.is_empty() { dht } else { dht.with_lookups(ed_keypair.public()) },
+            rpc: rpc::Behaviour::default(),
         };
         let transport = websocket_websys::Transport::new(100)
             .upgrade(Version::V1)
@@ -234,58 +263,35 @@ impl Node {
                 .with_idle_connection_timeout(Duration::from_secs(2)),
         );
 
-        fn unpack_node_id(id: sign::Ed) -> anyhow::Result<ed25519::PublicKey> {
-            libp2p::identity::ed25519::PublicKey::try_from_bytes(&id)
-                .context("deriving ed signature")
-        }
-
-        fn unpack_node_addr(addr: chain_api::NodeAddress) -> Multiaddr {
-            let (addr, port) = addr.into();
-            Multiaddr::empty()
-                .with(match addr {
-                    IpAddr::V4(ip) => multiaddr::Protocol::Ip4(ip),
-                    IpAddr::V6(ip) => multiaddr::Protocol::Ip6(ip),
-                })
-                .with(multiaddr::Protocol::Tcp(port + 100))
-                .with(multiaddr::Protocol::Ws("/".into()))
-        }
-
-        let node_count = node_data.len();
-        let tolerance = 0;
-        set_state!(CollecringKeys(
-            node_count - swarm.behaviour_mut().key_share.keys.len() - tolerance
-        ));
-
         let nodes = node_data
             .into_iter()
-            .map(|(node, ip)| {
-                let id = unpack_node_id(node.id).unwrap();
-                let addr = unpack_node_addr(ip);
-                Ok(Route::new(id, addr))
+            .map(|(node, endpoints, weight)| {
+                let id = unpack_node_id(node.id)?;
+                let addrs = endpoints.into_iter().filter_map(unpack_endpoint);
+                Ok(Route::with_addrs(id, addrs).with_weight(weight))
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
-        swarm.behaviour_mut().dht.table.bulk_insert(nodes);
-
-        let routes = swarm.behaviour_mut().dht.table.iter().map(Route::peer_id).collect::<Vec<_>>();
-        for route in routes {
-            _ = swarm.dial(route);
+        let placement = dht::Placement::new(params.virtual_nodes, params.placement_beacon);
+        let dht = &mut swarm.behaviour_mut().dht;
+        dht.table.set_placement(placement);
+        dht.table.bulk_insert(nodes);
+
+        if let Some(lookups) = &mut dht.lookups {
+            lookups.placement = placement;
+            for route in boot_nodes.iter().filter_map(boot_route) {
+                lookups.buckets.insert(route);
+            }
         }
 
-        loop {
-            // TODO: add timeout instead
-            match swarm.select_next_some().await {
-                SwarmEvent::Behaviour(BehaviourEvent::KeyShare(..)) => {
-                    let remining =
-                        node_count - swarm.behaviour_mut().key_share.keys.len() - tolerance;
-                    set_state!(CollecringKeys(remining));
-                    if remining == 0 {
-                        break;
-                    }
-                }
-                e => log::debug!("{:?}", e),
-            }
+        // only the nodes around the profile are contacted, chats are looked up later
+        if swarm.behaviour().dht.lookups.is_some() {
+            let routes = look_up(&mut swarm, PossibleTopic::Profile(profile_hash.sign)).await;
+            let routes = confirm_routes(&chain_api, routes).await;
+            swarm.behaviour_mut().dht.table.insert_all(routes);
         }
 
+        collect_keys(&mut swarm, |remining| set_state!(CollecringKeys(remining))).await;
+
         let nodes = &swarm.behaviour_mut().key_share.keys;
         anyhow::ensure!(
             nodes.len() >= crate::chain::min_nodes(),
@@ -299,7 +305,7 @@ impl Node {
             .dht
             .table
             .closest(profile_hash.sign.as_ref())
-            .take(REPLICATION_FACTOR.get() + 1);
+            .take(params.replicator_count());
 
         set_state!(ProfileOpen);
         let pick = members.choose(&mut rand::thread_rng()).unwrap().peer_id();
@@ -326,13 +332,21 @@ impl Node {
         let vault = if vault.is_empty() && vault_nonce == 0 {
             set_state!(ProfileCreate);
             let proof = Proof::new(&keys.sign, &mut vault_nonce, &[][..], OsRng);
-            request_dispatch
-                .dispatch_direct::<Repl<CreateProfile>>(
-                    &mut profile_stream,
-                    &(proof, keys.enc.public_key().into_bytes()),
-                )
-                .await
-                .context("creating account")?;
+            let enc = keys.enc.public_key().into_bytes();
+            let mut pow = 0;
+            loop {
+                match request_dispatch
+                    .dispatch_direct::<Repl<CreateProfile>>(&mut profile_stream, &(proof, enc, pow))
+                    .await
+                {
+                    Err(RequestError::Handler(ReplError::Inner(
+                        CreateAccountError::InsufficientWork(difficulty),
+                    ))) if pow_difficulty(profile_hash.sign.into(), pow) < difficulty => {
+                        pow = solve_pow(profile_hash.sign.into(), difficulty).await?;
+                    }
+                    res => break res.context("creating account")?,
+                }
+            }
 
             Default::default()
         } else {
@@ -345,6 +359,15 @@ impl Node {
 
         set_state!(ChatSearch);
 
+        if swarm.behaviour().dht.lookups.is_some() {
+            for &chat in vault.chats.keys() {
+                let routes = look_up(&mut swarm, PossibleTopic::Chat(chat)).await;
+                let routes = confirm_routes(&chain_api, routes).await;
+                swarm.behaviour_mut().dht.table.insert_all(routes);
+            }
+            collect_keys(&mut swarm, |_| {}).await;
+        }
+
         let mut profile_sub = Subscription {
             id: profile_stream_id,
             peer_id: profile_stream_peer,
@@ -360,7 +383,7 @@ impl Node {
                 .dht
                 .table
                 .closest(c.as_bytes())
-                .take(REPLICATION_FACTOR.get() + 1)
+                .take(params.replicator_count())
                 .map(move |peer| (peer.peer_id(), c))
         });
         for (peer, chat) in iter {
@@ -437,6 +460,11 @@ impl Node {
                 pending_requests: Default::default(),
                 pending_topic_search: Default::default(),
                 requests: commands,
+                params,
+                chain: Rc::new(chain_api),
+                found_topics: Default::default(),
+                searches: Default::default(),
+                confirmations: Default::default(),
             },
             vault,
             request_dispatch,
@@ -451,6 +479,7 @@ impl Node {
                 event = self.swarm.select_next_some() => self.handle_swarm_event(event),
                 command = self.requests.select_next_some() => self.handle_command(command),
                 (id, response) = self.subscriptions.select_next_some() => self.handle_subscription_response(id, response).await,
+                (topic, routes) = self.confirmations.select_next_some() => self.handle_confirmed_routes(topic, routes),
             }
         }
     }
@@ -466,13 +495,27 @@ impl Node {
             return;
         }
 
+        if let Some((.., l)) = self.searches.iter_mut().find(|(t, ..)| *t == search_key) {
+            l.push(command);
+            return;
+        }
+
+        let beh = self.swarm.behaviour_mut();
+        let lookups = beh.dht.lookups.as_mut().filter(|_| !self.found_topics.contains(&search_key));
+        if let Some(lookups) = lookups {
+            let id = lookups.start(dht::lookup::data_key(search_key.as_bytes()), &mut beh.rpc);
+            self.searches.push((search_key, Some(id), vec![command]));
+            self.take_finished_lookups();
+            return;
+        }
+
         let peers = self
             .swarm
             .behaviour()
             .dht
             .table
             .closest(search_key.as_bytes())
-            .take(REPLICATION_FACTOR.get() + 1)
+            .take(self.params.replicator_count())
             .map(Route::peer_id)
             .collect::<Vec<_>>();
 
@@ -483,7 +526,14 @@ impl Node {
             return;
         }
 
-        let Some(pick) = peers.into_iter().choose(&mut rand::thread_rng()) else {
+        let beh = self.swarm.behaviour();
+        let mut peers = peers.into_iter().filter(|p| beh.key_share.keys.contains_key(p));
+        let Some(pick) = peers.choose(&mut rand::thread_rng()) else {
+            if beh.dht.lookups.is_some() {
+                // keys of the found replicators are still on the way
+                self.searches.push((search_key, None, vec![command]));
+                return;
+            }
             log::error!("search response does not contain any peers");
             return;
         };
@@ -557,10 +607,72 @@ impl Node {
                     req.into_iter().for_each(|r| self.handle_command(r));
                 }
             }
+            SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::Response(peer, call, res))) => {
+                let beh = self.swarm.behaviour_mut();
+                if let Some(lookups) = &mut beh.dht.lookups {
+                    let response = res.as_ref().ok().map(|(r, _)| r.as_slice());
+                    lookups.on_response(peer, call, response, &mut beh.rpc);
+                    self.take_finished_lookups();
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::KeyShare(..)) => self.resume_searches(),
             e => log::debug!("{:?}", e),
         }
     }
 
+    fn take_finished_lookups(&mut self) {
+        let Some(lookups) = &mut self.swarm.behaviour_mut().dht.lookups else {
+            return;
+        };
+        while let Some((id, routes)) = lookups.take_finished() {
+            let Some((topic, lookup, _)) =
+                self.searches.iter_mut().find(|(_, l, _)| *l == Some(id))
+            else {
+                continue;
+            };
+            *lookup = None;
+            let (topic, chain) = (*topic, self.chain.clone());
+            self.confirmations
+                .push(Box::pin(async move { (topic, confirm_routes(&chain, routes).await) }));
+        }
+    }
+
+    fn handle_confirmed_routes(&mut self, topic: PossibleTopic, routes: Vec<Route>) {
+        if routes.is_empty() {
+            log::error!("no staked nodes found for {topic:?}");
+            self.searches.retain(|(t, ..)| *t != topic);
+            return;
+        }
+
+        let peers = routes.iter().map(Route::peer_id).collect::<Vec<_>>();
+        self.swarm.behaviour_mut().dht.table.insert_all(routes);
+        self.found_topics.insert(topic);
+        for peer in peers {
+            if !self.swarm.behaviour().key_share.keys.contains_key(&peer) {
+                _ = self.swarm.dial(peer);
+            }
+        }
+        self.resume_searches();
+    }
+
+    /// Searches continue once any replicator of the topic shared its key.
+    fn resume_searches(&mut self) {
+        let beh = self.swarm.behaviour();
+        let ready = |topic: &PossibleTopic| {
+            beh.dht
+                .table
+                .closest(topic.as_bytes())
+                .take(self.params.replicator_count())
+                .any(|r| beh.key_share.keys.contains_key(&r.peer_id()))
+        };
+        let (ready, waiting) =
+            std::mem::take(&mut self.searches).into_iter().partition::<Vec<_>, _>(|(t, l, _)| {
+                l.is_none() && self.found_topics.contains(t) && ready(t)
+            });
+        self.searches = waiting;
+        ready.into_iter().flat_map(|(.., c)| c).for_each(|c| self.handle_command(c));
+    }
+
     async fn handle_subscription_response(&mut self, id: PathId, request: io::Result<Vec<u8>>) {
         let Ok(msg) = request.inspect_err(|e| log::error!("chat subscription error: {e}")) else {
             return;
@@ -610,6 +722,118 @@ fn pick_route(
     picked.try_into().unwrap()
 }
 
+fn unpack_node_id(id: sign::Ed) -> anyhow::Result<ed25519::PublicKey> {
+    libp2p::identity::ed25519::PublicKey::try_from_bytes(&id).context("deriving ed signature")
+}
+
+/// Only websocket endpoints are usable from the browser.
+fn unpack_endpoint(endpoint: chain_api::NodeEndpoint) -> Option<Multiaddr> {
+    let chain_api::NodeTransport::Ws = endpoint.transport else {
+        return None;
+    };
+    let (addr, port) = endpoint.addr.into();
+    let addr = Multiaddr::empty()
+        .with(match addr {
+            IpAddr::V4(ip) => multiaddr::Protocol::Ip4(ip),
+            IpAddr::V6(ip) => multiaddr::Protocol::Ip6(ip),
+        })
+        .with(multiaddr::Protocol::Tcp(port))
+        .with(multiaddr::Protocol::Ws("/".into()));
+    Some(addr)
+}
+
+fn boot_route(addr: &Multiaddr) -> Option<Route> {
+    let mut addr = addr.clone();
+    let Some(multiaddr::Protocol::P2p(peer)) = addr.pop() else {
+        return None;
+    };
+    let pk = unpack_node_id(dht::try_peer_id_to_ed(peer)?).ok()?;
+    Some(Route::new(pk, addr))
+}
+
+/// Drives the swarm until the lookup for the `topic` replicators finishes.
+async fn look_up(swarm: &mut Swarm<Behaviour>, topic: PossibleTopic) -> Vec<Route> {
+    let beh = swarm.behaviour_mut();
+    let lookups = beh.dht.lookups.as_mut().expect("lookups to be enabled");
+    let id = lookups.start(dht::lookup::data_key(topic.as_bytes()), &mut beh.rpc);
+    loop {
+        let beh = swarm.behaviour_mut();
+        let lookups = beh.dht.lookups.as_mut().expect("lookups to be enabled");
+        if let Some((_, routes)) = lookups.take_finished().filter(|(i, _)| *i == id) {
+            return routes;
+        }
+
+        match swarm.select_next_some().await {
+            SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::Response(peer, call, res))) => {
+                let beh = swarm.behaviour_mut();
+                let lookups = beh.dht.lookups.as_mut().expect("lookups to be enabled");
+                let response = res.as_ref().ok().map(|(r, _)| r.as_slice());
+                lookups.on_response(peer, call, response, &mut beh.rpc);
+            }
+            e => log::debug!("{:?}", e),
+        }
+    }
+}
+
+/// Routes found by a lookup are only trusted once the chain confirms their stake, the
+/// endpoints and weight are taken from the chain as well.
+async fn confirm_routes(chain: &crate::chain::Client, found: Vec<Route>) -> Vec<Route> {
+    let checks = found
+        .iter()
+        .filter_map(|r| dht::try_peer_id_to_ed(r.peer_id()))
+        .map(|id| async move { (id, chain.node(crate::chain::node_contract(), id).await) });
+    futures::future::join_all(checks)
+        .await
+        .into_iter()
+        .filter_map(|(id, res)| match res {
+            Ok(Some((node, endpoints, weight))) => {
+                let pk = unpack_node_id(node.id).ok()?;
+                let addrs = endpoints.into_iter().filter_map(unpack_endpoint);
+                Some(Route::with_addrs(pk, addrs).with_weight(weight))
+            }
+            Ok(None) => {
+                log::warn!("found node {} is not staked", hex::encode(id));
+                None
+            }
+            Err(e) => {
+                log::error!("confirming found node {}: {e}", hex::encode(id));
+                None
+            }
+        })
+        .collect()
+}
+
+/// Dials every route without a key and waits until all keys are shared.
+async fn collect_keys(swarm: &mut Swarm<Behaviour>, mut progress: impl FnMut(usize)) {
+    let missing = |swarm: &Swarm<Behaviour>| {
+        let beh = swarm.behaviour();
+        beh.dht.table.iter().filter(|r| !beh.key_share.keys.contains_key(&r.peer_id())).count()
+    };
+
+    let routes = swarm
+        .behaviour()
+        .dht
+        .table
+        .iter()
+        .map(Route::peer_id)
+        .filter(|p| !swarm.behaviour().key_share.keys.contains_key(p))
+        .collect::<Vec<_>>();
+    for route in routes {
+        _ = swarm.dial(route);
+    }
+
+    let mut remining = missing(swarm);
+    while remining != 0 {
+        progress(remining);
+        // TODO: add timeout instead
+        match swarm.select_next_some().await {
+            SwarmEvent::Behaviour(BehaviourEvent::KeyShare(..)) => remining = missing(swarm),
+            e => log::debug!("{:?}", e),
+        }
+    }
+    progress(0);
+}
+
 #[allow(deprecated)]
 type SE = libp2p::swarm::SwarmEvent<<Behaviour as NetworkBehaviour>::ToSwarm>;
 
@@ -637,4 +861,5 @@ struct Behaviour {
     onion: onion::Behaviour,
     key_share: onion::key_share::Behaviour,
     dht: dht::Behaviour,
+    rpc: rpc::Behaviour,
 }
@@ -1,8 +1,17 @@
 use {
-    anyhow::Context, chat_spec::*, component_utils::Codec, libp2p::futures::StreamExt,
-    onion::EncryptedStream, std::convert::Infallible,
+    anyhow::Context,
+    chat_spec::*,
+    component_utils::Codec,
+    libp2p::futures::StreamExt,
+    onion::EncryptedStream,
+    std::{convert::Infallible, time::Duration},
 };
 
+/// Work a server can ask for, every extra bit doubles the time the tab spends solving it.
+const MAX_POW_DIFFICULTY: u8 = 24;
+/// Nonces tried before the event loop gets to run again.
+const POW_SLICE: PowNonce = 1 << 12;
+
 pub struct RequestDispatch {
     buffer: Vec<u8>,
     sink: libp2p::futures::channel::mpsc::Sender<RequestInit>,
@@ -73,14 +82,21 @@ impl RequestDispatch {
         self.dispatch::<PerformChatAction>((proof, action)).await.map_err(Into::into)
     }
 
-    pub async fn dispatch_mail(
+    pub async fn dispatch_create_chat(
         &mut self,
-        request: <Repl<SendMail> as Protocol>::Request<'_>,
-    ) -> Result<<Repl<SendMail> as Protocol>::Response<'_>, RequestError<Repl<SendMail>>> {
-        self.dispatch::<SendMail>(request).await.or_else(|e| match e {
-            RequestError::Handler(ReplError::Inner(SendMailError::SentDirectly)) => Ok(()),
-            e => Err(e),
-        })
+        chat: ChatName,
+        owner: Identity,
+    ) -> anyhow::Result<()> {
+        let topic = PossibleTopic::Chat(chat);
+        let Err(RequestError::Handler(ReplError::Inner(CreateChatError::InsufficientWork(
+            difficulty,
+        )))) = self.dispatch::<CreateChat>((chat, owner, 0)).await
+        else {
+            return Ok(());
+        };
+
+        let pow = solve_pow(topic, difficulty).await?;
+        self.dispatch::<CreateChat>((chat, owner, pow)).await.map_err(Into::into)
     }
 
     pub async fn dispatch_direct<P: Protocol>(
@@ -104,6 +120,10 @@ impl RequestDispatch {
     pub fn parse_response<P: Protocol>(
         response: &[u8],
     ) -> Result<P::Response<'_>, RequestError<P>> {
+        if let Some((_, rejection)) = <(CallId, Rejection)>::decode(&mut &response[..]) {
+            return Err(RequestError::Rejected(rejection));
+        }
+
         <(CallId, ProtocolResult<'_, P>)>::decode(&mut &response[..])
             .ok_or(RequestError::InvalidResponse)
             .and_then(|(_, resp)| resp.map_err(RequestError::Handler))
@@ -112,14 +132,34 @@ impl RequestDispatch {
     pub fn subscribe<P: Topic>(
         &mut self,
         topic: P,
+    ) -> Result<(Subscription<P>, SubsOwner<P>), RequestError<Infallible>> {
+        let topic: PossibleTopic = topic.into();
+        self.subscribe_low(topic, |id| (<Subscribe as Protocol>::PREFIX, id, &topic).to_bytes())
+    }
+
+    /// Same as [`Self::subscribe`] but the server first replays unread mail starting from
+    /// `from`.
+    pub fn subscribe_profile(
+        &mut self,
+        identity: Identity,
+        from: EventSeq,
+    ) -> Result<(Subscription<Identity>, SubsOwner<Identity>), RequestError<Infallible>> {
+        self.subscribe_low(identity.into(), |id| {
+            (<SubscribeProfile as Protocol>::PREFIX, id, (identity, from)).to_bytes()
+        })
+    }
+
+    fn subscribe_low<P: Topic>(
+        &mut self,
+        topic: PossibleTopic,
+        payload: impl FnOnce(CallId) -> Vec<u8>,
     ) -> Result<(Subscription<P>, SubsOwner<P>), RequestError<Infallible>> {
         let (tx, rx) = libp2p::futures::channel::mpsc::channel(0);
         let id = CallId::new();
-        let topic: PossibleTopic = topic.into();
         self.sink
             .try_send(RequestInit::Subscription(SubscriptionInit {
                 id,
-                payload: (<Subscribe as Protocol>::PREFIX, id, &topic).to_bytes(),
+                payload: payload(id),
                 topic,
                 channel: tx,
             }))
@@ -132,6 +172,28 @@ impl RequestDispatch {
     }
 }
 
+/// Same as [`chat_spec::solve_pow`] but it runs on the main thread in slices so the page
+/// keeps responding, demands over [`MAX_POW_DIFFICULTY`] are refused.
+pub async fn solve_pow(topic: PossibleTopic, difficulty: u8) -> anyhow::Result<PowNonce> {
+    anyhow::ensure!(
+        difficulty <= MAX_POW_DIFFICULTY,
+        "server asks for too much work ({difficulty} bits, at most {MAX_POW_DIFFICULTY})"
+    );
+
+    let mut start = 0;
+    loop {
+        let slice = start..start + POW_SLICE;
+        if let Some(nonce) = slice.clone().find(|&n| pow_difficulty(topic, n) >= difficulty) {
+            return Ok(nonce);
+        }
+        start = slice.end;
+
+        let (tx, rx) = libp2p::futures::channel::oneshot::channel();
+        leptos::set_timeout(move || _ = tx.send(()), Duration::ZERO);
+        _ = rx.await;
+    }
+}
+
 pub struct SubsOwner<H: Topic> {
     id: CallId,
     send_back: libp2p::futures::channel::mpsc::Sender<RequestInit>,
@@ -209,6 +271,7 @@ pub enum RequestError<H: Protocol> {
     InvalidResponse,
     ChannelClosed,
     ServerIsOwervhelmed,
+    Rejected(Rejection),
     Handler(H::Error),
 }
 
@@ -218,6 +281,7 @@ impl<H: Protocol> std::fmt::Debug for RequestError<H> {
             Self::InvalidResponse => write!(f, "invalid response"),
             Self::ChannelClosed => write!(f, "channel closed"),
             Self::ServerIsOwervhelmed => write!(f, "server is owervhelmed"),
+            Self::Rejected(r) => write!(f, "request rejected: {}", r),
             Self::Handler(e) => write!(f, "handler error: {}", e),
         }
     }
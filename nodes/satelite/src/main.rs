@@ -1,9 +1,30 @@
 use {
     self::storage::Storage,
     anyhow::Context,
-    component_utils::futures::StreamExt,
-    libp2p::swarm::{NetworkBehaviour, SwarmEvent},
-    std::net::Ipv4Addr,
+    component_utils::{futures::StreamExt, Codec, Protocol},
+    crypto::{sign, TransmutationCircle},
+    libp2p::{
+        core::ConnectedPoint,
+        identity::ed25519,
+        swarm::{NetworkBehaviour, SwarmEvent},
+        Multiaddr, PeerId, StreamProtocol,
+    },
+    rand_core::OsRng,
+    std::{
+        collections::HashMap,
+        fs, io,
+        net::Ipv4Addr,
+        path::Path,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    storage_spec::{
+        metabase::StoreIdentity,
+        protocol::{
+            self, AllocateFile, AllocateFileError, Allocation, Rejection, ReportCapacity,
+            ReportCapacityError,
+        },
+        ticket::{AllocationTicket, Ticket, TicketBlock, TicketKind},
+    },
 };
 
 mod storage;
@@ -13,13 +34,21 @@ config::env_config! {
         port: u16 = "8080",
         external_ip: Ipv4Addr = "127.0.0.1",
         identity_ed: config::Hex = "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+        /// Directory with the metabase and ticket signing key, kept between restarts.
+        storage_dir: String = "satelite",
+        /// Seconds an allocation ticket stays valid.
+        ticket_lifetime: u64 = "3600",
+        /// Ed25519 keys of the stores allowed to register, registered stores stay allowed.
+        stores: config::List<config::Hex> = "",
     }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let config = Config::from_env();
-    let db = Storage::new();
+    env_logger::init();
+
+    let config = Config::load()?;
+    let db = Storage::open(config.storage_dir.clone().into()).context("opening the metabase")?;
     let mut satelite = Satelite::new(config, db)?;
     satelite.run().await
 }
@@ -27,25 +56,61 @@ async fn main() -> anyhow::Result<()> {
 pub struct Satelite {
     swarm: libp2p::Swarm<Behaviour>,
     db: Storage,
+    sign: sign::Keypair,
+    ticket_lifetime: u64,
+    stores: Vec<StoreIdentity>,
+    /// Where the connected peers dial from, quic reuses the listening port so it is dialable.
+    peer_addrs: HashMap<PeerId, Multiaddr>,
 }
 
 impl Satelite {
     fn new(config: Config, store: Storage) -> anyhow::Result<Self> {
         let identity: libp2p::identity::Keypair =
-            libp2p::identity::ed25519::Keypair::try_from_bytes(&mut config.identity_ed.to_bytes())
+            ed25519::Keypair::try_from_bytes(&mut config.identity_ed.to_bytes())
                 .context("invalid identity")?
                 .into();
 
-        let swarm = libp2p::SwarmBuilder::with_existing_identity(identity)
+        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(identity)
             .with_tokio()
             .with_quic()
-            .with_behaviour(|_| Behaviour::default())?
+            .with_behaviour(|_| Behaviour {
+                dht: dht::Behaviour::default(),
+                rpc: rpc::Behaviour::new(
+                    rpc::Config::new().protocol(StreamProtocol::new(protocol::PROTOCOL_NAME)),
+                ),
+                streaming: streaming::Behaviour::default(),
+            })?
             .with_swarm_config(|c| {
                 c.with_idle_connection_timeout(std::time::Duration::from_secs(60))
             })
             .build();
 
-        Ok(Self { swarm, db: store })
+        swarm.listen_on(
+            Multiaddr::empty()
+                .with(libp2p::multiaddr::Protocol::Ip4(Ipv4Addr::UNSPECIFIED))
+                .with(libp2p::multiaddr::Protocol::Udp(config.port))
+                .with(libp2p::multiaddr::Protocol::QuicV1),
+        )?;
+        log::info!(
+            "satellite {} listening on {}:{}",
+            swarm.local_peer_id(),
+            config.external_ip,
+            config.port
+        );
+
+        let sign = load_sign_keys(Path::new(&config.storage_dir)).context("loading sign keys")?;
+        let sign_hash = crypto::hash::new(&sign.public_key());
+        let sign_hash = sign_hash.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        log::info!("tickets are signed by {sign_hash}");
+
+        Ok(Self {
+            swarm,
+            db: store,
+            sign,
+            ticket_lifetime: config.ticket_lifetime,
+            stores: config.stores.0.iter().map(config::Hex::to_bytes).collect(),
+            peer_addrs: HashMap::new(),
+        })
     }
 
     async fn run(&mut self) -> ! {
@@ -56,10 +121,137 @@ impl Satelite {
         }
     }
 
-    fn swarm_event(&mut self, _event: SwarmEvent<BehaviourEvent>) {}
+    fn swarm_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::Request(peer, call, body))) => {
+                let Some((&prefix, body)) = body.split_first() else {
+                    log::info!("empty rpc request from {peer}");
+                    return;
+                };
+                if let Err(e) = self.handle_request(peer, call, prefix, body) {
+                    log::warn!("failed to handle request {prefix} from {peer}: {e:#}");
+                }
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                endpoint: ConnectedPoint::Listener { send_back_addr, .. },
+                ..
+            } => {
+                self.peer_addrs.insert(peer_id, send_back_addr);
+            }
+            SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                self.peer_addrs.remove(&peer_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        peer: PeerId,
+        call: rpc::CallId,
+        prefix: u8,
+        mut body: &[u8],
+    ) -> anyhow::Result<()> {
+        match prefix {
+            AllocateFile::PREFIX => {
+                let (file, size) = <AllocateFile as Protocol>::Request::decode(&mut body)
+                    .context("invalid request")?;
+                let user = dht::try_peer_id_to_ed(peer).context("peer is not ed25519")?;
+                let response = self
+                    .db
+                    .allocate(file, size)
+                    .unwrap_or_else(|e| internal_error(e, AllocateFileError::Internal))
+                    .map(|allocation| {
+                        let ticket = self.ticket(&allocation, TicketKind::Upload, user);
+                        (allocation, ticket)
+                    });
+                self.swarm.behaviour_mut().rpc.respond(peer, call, response.to_bytes());
+            }
+            ReportCapacity::PREFIX => {
+                let free_blocks = <ReportCapacity as Protocol>::Request::decode(&mut body)
+                    .context("invalid request")?;
+                let identity = dht::try_peer_id_to_ed(peer).context("peer is not ed25519")?;
+                let res = if self.stores.contains(&identity) || self.db.is_registered(identity) {
+                    self.db
+                        .report_capacity(identity, free_blocks)
+                        .unwrap_or_else(|e| internal_error(e, ReportCapacityError::Internal))
+                } else {
+                    log::warn!("rejecting capacity report from unknown store {peer}");
+                    Err(ReportCapacityError::Unknown)
+                };
+                if res.is_ok() {
+                    self.add_store_route(peer, identity);
+                }
+                self.swarm.behaviour_mut().rpc.respond(peer, call, res.to_bytes());
+            }
+            _ => {
+                log::info!("rejecting unsupported request {prefix} from {peer}");
+                let rejection = Rejection::Unsupported.to_bytes();
+                self.swarm.behaviour_mut().rpc.respond(peer, call, rejection);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stores are dialed back on the address they reported from.
+    fn add_store_route(&mut self, peer: PeerId, identity: [u8; 32]) {
+        let Some(addr) = self.peer_addrs.get(&peer) else {
+            return;
+        };
+        let Ok(key) = ed25519::PublicKey::try_from_bytes(&identity) else {
+            return;
+        };
+        let table = &mut self.swarm.behaviour_mut().dht.table;
+        if table.get(peer).is_none() {
+            table.insert(dht::Route::new(key, addr.clone()));
+        }
+    }
+
+    fn ticket(
+        &self,
+        allocation: &Allocation,
+        kind: TicketKind,
+        user: sign::Ed,
+    ) -> AllocationTicket {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let ticket = Ticket {
+            kind,
+            file: allocation.file,
+            blocks: allocation
+                .block_pieces()
+                .map(|(block, pieces)| TicketBlock { block, pieces })
+                .collect(),
+            expiry: now + self.ticket_lifetime,
+            user,
+        };
+        AllocationTicket::new(&self.sign, ticket, OsRng)
+    }
+}
+
+/// Local failures are logged here, the requester only learns that the satellite is at fault.
+fn internal_error<T, E>(err: io::Error, res: E) -> Result<T, E> {
+    log::error!("metabase failure: {err}");
+    Err(res)
+}
+
+fn load_sign_keys(dir: &Path) -> io::Result<sign::Keypair> {
+    let path = dir.join("sign.keys");
+    match fs::read(&path) {
+        Ok(bytes) => sign::Keypair::try_from_slice(&bytes)
+            .copied()
+            .ok_or_else(|| io::Error::other("invalid key file")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let keys = sign::Keypair::new(OsRng);
+            fs::write(path, keys.into_bytes())?;
+            Ok(keys)
+        }
+        Err(e) => Err(e),
+    }
 }
 
-#[derive(NetworkBehaviour, Default)]
+#[derive(NetworkBehaviour)]
 pub struct Behaviour {
     dht: dht::Behaviour,
     rpc: rpc::Behaviour,
@@ -1,7 +1,309 @@
-pub struct Storage {}
+use {
+    component_utils::Codec,
+    std::{fs, io, path::PathBuf},
+    storage_spec::{
+        metabase::{
+            Block, BlockChunk, BlockHolders, BlockId, Db, DbContext, FileId, FileMeta, Store,
+            StoreId, StoreIdentity, BLOCK_CHUNK_SIZE,
+        },
+        protocol::{
+            AllocateFileError, Allocation, BlockPlacement, PieceIndex, ReportCapacityError,
+        },
+        ObjectId, BLOCK_PIECES, DATA_PIECES, MAX_PIECES, PIECE_SIZE,
+    },
+};
+
+const STATE_FILE_NAME: &str = "state";
+const STATE_TMP_FILE_NAME: &str = "state.tmp";
+
+/// Bytes of the original data one piece per store covers, allocations are rounded up to it.
+const ROW_SIZE: u64 = (DATA_PIECES * PIECE_SIZE) as u64;
+
+/// Indexes the metabase can not answer, the records themselves live in [`Db`].
+#[derive(Codec, Default)]
+struct State {
+    /// Sorted by the identity.
+    stores: Vec<(StoreIdentity, StoreId)>,
+    /// Sorted by the object id.
+    files: Vec<(ObjectId, FileId)>,
+    /// Blocks with free pieces at their end, together with the number of free pieces.
+    partial_blocks: Vec<(BlockId, PieceIndex)>,
+}
+
+pub struct Storage {
+    root: PathBuf,
+    db: Db,
+    cx: DbContext,
+    state: State,
+    /// Free block fragments as last reported by the stores, indexed like `state.stores`.
+    capacity: Vec<u32>,
+}
 
 impl Storage {
-    pub fn new() -> Self {
-        Self {}
+    pub fn open(root: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&root)?;
+        let state = match fs::read(root.join(STATE_FILE_NAME)) {
+            Ok(bytes) => State::decode(&mut bytes.as_slice()).ok_or(io::ErrorKind::InvalidData)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            db: Db::open(&root)?,
+            cx: DbContext::open(&root)?,
+            // nothing is allocated on stores until they report again
+            capacity: vec![0; state.stores.len()],
+            state,
+            root,
+        })
+    }
+
+    #[must_use]
+    pub fn is_registered(&self, identity: StoreIdentity) -> bool {
+        self.state.stores.binary_search_by_key(&identity, |&(i, _)| i).is_ok()
+    }
+
+    /// Registers the store on its first report, reported capacity replaces the previous one.
+    pub fn report_capacity(
+        &mut self,
+        identity: StoreIdentity,
+        free_blocks: u32,
+    ) -> io::Result<Result<StoreId, ReportCapacityError>> {
+        let index = match self.state.stores.binary_search_by_key(&identity, |&(i, _)| i) {
+            Ok(index) => index,
+            Err(index) => {
+                let store = Store { identity, block_cunks: 0..0, block_count: 0 };
+                let id = match self
+                    .db
+                    .stores
+                    .push_exact(&mut &[store][..], &mut self.cx.stores.lock().unwrap())
+                {
+                    Ok(ids) => ids.start,
+                    Err(e) if e.kind() == io::ErrorKind::OutOfMemory => {
+                        return Ok(Err(ReportCapacityError::TooManyStores))
+                    }
+                    Err(e) => return Err(e),
+                };
+                self.state.stores.insert(index, (identity, id));
+                self.capacity.insert(index, 0);
+                self.save()?;
+                index
+            }
+        };
+
+        self.capacity[index] = free_blocks;
+        Ok(Ok(self.state.stores[index].1))
+    }
+
+    /// Places the file the way the whitepaper describes: the head goes to the free end of a
+    /// partially used block, the rest takes fresh blocks, each on [`MAX_PIECES`] distinct
+    /// stores, and the free end of the last one is left for later files.
+    pub fn allocate(
+        &mut self,
+        file: ObjectId,
+        size: u64,
+    ) -> io::Result<Result<Allocation, AllocateFileError>> {
+        if size == 0 {
+            return Ok(Err(AllocateFileError::Empty));
+        }
+
+        let Err(file_index) = self.state.files.binary_search_by_key(&file, |&(f, _)| f) else {
+            return Ok(Err(AllocateFileError::AlreadyExists));
+        };
+
+        let piece_count = size.div_ceil(ROW_SIZE);
+        let block_pieces = BLOCK_PIECES as u64;
+
+        // best fit for files smaller than a block, the roomiest block otherwise
+        let head = self
+            .state
+            .partial_blocks
+            .iter()
+            .enumerate()
+            .filter(|&(_, &(_, free))| u64::from(free) >= piece_count)
+            .min_by_key(|&(_, &(_, free))| free)
+            .or_else(|| {
+                self.state.partial_blocks.iter().enumerate().max_by_key(|&(_, &(_, free))| free)
+            })
+            .map(|(i, &(block, free))| (i, block, free));
+
+        let head_pieces = head.map_or(0, |(.., free)| piece_count.min(u64::from(free)));
+        let fresh_blocks = (piece_count - head_pieces).div_ceil(block_pieces);
+        let Some(groups) = self.pick_groups(fresh_blocks) else {
+            return Ok(Err(AllocateFileError::NotEnoughSpace));
+        };
+
+        let mut placements = Vec::with_capacity(groups.len() + 1);
+        let mut block_ids = Vec::with_capacity(groups.len() + 1);
+        let mut in_block_start = 0;
+
+        if let Some((index, block, free)) = head {
+            let head_pieces = head_pieces as PieceIndex;
+            let mut record = self.fetch_block(block)?;
+            record.free_space -= head_pieces as u16;
+            self.db.blocks.write(block..block + 1, &mut &[record][..])?;
+
+            if free == head_pieces {
+                self.state.partial_blocks.swap_remove(index);
+            } else {
+                self.state.partial_blocks[index].1 -= head_pieces;
+            }
+
+            in_block_start = BLOCK_PIECES as PieceIndex - free;
+            placements.push(self.placement(block, record.group));
+            block_ids.push(block);
+        }
+
+        let mut remaining = piece_count - head_pieces;
+        let mut in_block_end = in_block_start + head_pieces as PieceIndex;
+        for group in groups {
+            let pieces = remaining.min(block_pieces) as PieceIndex;
+            remaining -= u64::from(pieces);
+
+            let free = BLOCK_PIECES as PieceIndex - pieces;
+            let record = Block { free_space: free as u16, group };
+            let block = self
+                .db
+                .blocks
+                .push_exact(&mut &[record][..], &mut self.cx.blocks.lock().unwrap())?
+                .start;
+            if free != 0 {
+                self.state.partial_blocks.push((block, free));
+            }
+
+            for store in group {
+                self.add_block_to_store(store)?;
+            }
+
+            in_block_end = pieces;
+            placements.push(self.placement(block, group));
+            block_ids.push(block);
+        }
+
+        let chunks = block_ids
+            .chunks(BLOCK_CHUNK_SIZE)
+            .map(|ids| {
+                let mut chunk: BlockChunk = [ids[0]; BLOCK_CHUNK_SIZE];
+                chunk[..ids.len()].copy_from_slice(ids);
+                chunk
+            })
+            .collect::<Vec<_>>();
+        let blocks = self
+            .db
+            .block_lists
+            .push_exact(&mut chunks.as_slice(), &mut self.cx.block_lists.lock().unwrap())?;
+
+        let meta = FileMeta {
+            in_block_start,
+            in_block_end,
+            piece_count,
+            blocks,
+            block_count: block_ids.len() as u32,
+        };
+        let id = self.db.files.push_exact(&mut &[meta][..], &mut self.cx.files.lock().unwrap())?;
+        self.state.files.insert(file_index, (file, id.start));
+        self.save()?;
+
+        let allocation = Allocation {
+            file,
+            size,
+            piece_count,
+            in_block_start,
+            in_block_end,
+            blocks: placements,
+        };
+        Ok(Ok(allocation))
+    }
+
+    /// Groups of distinct stores for fresh blocks, stores with most room go first.
+    fn pick_groups(&self, count: u64) -> Option<Vec<BlockHolders>> {
+        let mut capacity = self.capacity.clone();
+        let mut order = (0..capacity.len()).collect::<Vec<_>>();
+        (0..count)
+            .map(|_| {
+                order.sort_unstable_by_key(|&i| std::cmp::Reverse(capacity[i]));
+                let chosen = order.get(..MAX_PIECES)?;
+                if chosen.iter().any(|&i| capacity[i] == 0) {
+                    return None;
+                }
+
+                let mut group = [0; MAX_PIECES];
+                for (slot, &i) in group.iter_mut().zip(chosen) {
+                    capacity[i] -= 1;
+                    *slot = self.state.stores[i].1;
+                }
+                Some(group)
+            })
+            .collect()
+    }
+
+    fn add_block_to_store(&mut self, store: StoreId) -> io::Result<()> {
+        let index = self.state.stores.iter().position(|&(_, id)| id == store).expect("picked");
+        self.capacity[index] -= 1;
+
+        let mut record = [Store { identity: [0; 32], block_cunks: 0..0, block_count: 0 }];
+        let mut record = self.db.stores.fetch(store, &mut &mut record[..]).map(|r| r[0].clone())?;
+        record.block_count += 1;
+        self.db.stores.write(store..store + 1, &mut &[record][..])
+    }
+
+    fn fetch_block(&self, block: BlockId) -> io::Result<Block> {
+        let mut record = [Block { free_space: 0, group: [0; MAX_PIECES] }];
+        self.db.blocks.fetch(block, &mut &mut record[..]).map(|r| r[0])
+    }
+
+    fn placement(&self, block: BlockId, group: BlockHolders) -> BlockPlacement {
+        BlockPlacement { block, stores: group.map(|id| self.identity(id)) }
+    }
+
+    fn identity(&self, store: StoreId) -> StoreIdentity {
+        self.state.stores.iter().find(|&&(_, id)| id == store).expect("registered").0
+    }
+
+    fn save(&self) -> io::Result<()> {
+        self.cx.save(&self.root)?;
+        // rename is atomic, a crash never leaves half written state behind
+        let tmp = self.root.join(STATE_TMP_FILE_NAME);
+        fs::write(&tmp, self.state.to_bytes())?;
+        fs::rename(tmp, self.root.join(STATE_FILE_NAME))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_files_share_blocks() {
+        let root = std::env::temp_dir().join(format!("orion-satelite-{}", std::process::id()));
+        _ = fs::remove_dir_all(&root);
+
+        let mut storage = Storage::open(root.clone()).unwrap();
+        for i in 0..MAX_PIECES as u8 {
+            storage.report_capacity([i + 1; 32], 1).unwrap().unwrap();
+        }
+
+        let first = storage.allocate([1; 32], ROW_SIZE * 3).unwrap().unwrap();
+        assert_eq!(first.block_pieces().collect::<Vec<_>>(), [(first.blocks[0].block, 0..3)]);
+        let mut stores = first.blocks[0].stores.to_vec();
+        stores.sort_unstable();
+        stores.dedup();
+        assert_eq!(stores.len(), MAX_PIECES);
+
+        let second = storage.allocate([2; 32], 1).unwrap().unwrap();
+        assert_eq!(second.blocks[0].block, first.blocks[0].block);
+        assert_eq!((second.in_block_start, second.in_block_end), (3, 4));
+
+        assert_eq!(
+            storage.allocate([2; 32], 1).unwrap().err(),
+            Some(AllocateFileError::AlreadyExists)
+        );
+        let too_big = ROW_SIZE * BLOCK_PIECES as u64 * 2;
+        assert_eq!(
+            storage.allocate([3; 32], too_big).unwrap().err(),
+            Some(AllocateFileError::NotEnoughSpace)
+        );
+
+        fs::remove_dir_all(root).unwrap();
     }
 }
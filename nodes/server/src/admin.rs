@@ -0,0 +1,270 @@
+use {
+    crate::{other_replicators_for, replicators_for, seal_rpc, util::Hex, Server},
+    anyhow::Context as _,
+    chat_spec::{solve_pow, ChatName, CreateChat, CreateProfile, PossibleTopic, Proof, Protocol},
+    component_utils::Codec,
+    libp2p::{
+        futures::{
+            channel::{mpsc, oneshot},
+            stream::FuturesUnordered,
+            SinkExt,
+        },
+        PeerId,
+    },
+    std::{fmt::Write as _, io},
+    tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+        task::JoinHandle,
+    },
+};
+
+const HELP: &str = "\
+profiles                list stored profiles
+chats                   list stored chats
+routes                  show the routing table
+replicators <topic>     show the replication group of a topic
+stage <chat>            dump block stage of a chat
+evict <topic>           drop the topic from this node
+replicate <topic>       push our copy of the profile, or recreate the chat, on the other replicators
+topic is either `chat:<name>` or `profile:<hex identity>`
+";
+
+pub type Commands = mpsc::Receiver<Command>;
+
+pub struct Command {
+    pub line: String,
+    pub reply: oneshot::Sender<String>,
+}
+
+/// Replications waiting for their proof of work, solved on the blocking pool.
+pub type Replications = FuturesUnordered<JoinHandle<Replication>>;
+
+pub struct Replication {
+    request: Vec<u8>,
+    targets: Vec<PeerId>,
+    reply: Option<oneshot::Sender<String>>,
+}
+
+/// Accepts one command per connection and writes back the textual reply.
+pub async fn listen(path: String, sink: mpsc::Sender<Command>) -> io::Result<()> {
+    // stale socket from previous run would make the bind fail
+    _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    log::info!("admin socket listening on {path}");
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let sink = sink.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, sink).await {
+                log::info!("admin connection failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: UnixStream, mut sink: mpsc::Sender<Command>) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(read).read_line(&mut line).await?;
+
+    let (reply, rx) = oneshot::channel();
+    sink.send(Command { line, reply }).await.map_err(|_| io::Error::other("server stopped"))?;
+    let reply = rx.await.map_err(|_| io::Error::other("server dropped the command"))?;
+
+    write.write_all(reply.as_bytes()).await
+}
+
+fn parse_topic(arg: Option<&str>) -> anyhow::Result<PossibleTopic> {
+    let arg = arg.context("missing topic")?;
+    if let Some(name) = arg.strip_prefix("chat:") {
+        let name = ChatName::from(name).ok().context("chat name too long")?;
+        return Ok(PossibleTopic::Chat(name));
+    }
+
+    if let Some(identity) = arg.strip_prefix("profile:") {
+        let identity = identity.parse::<config::Hex>().map_err(anyhow::Error::msg)?;
+        return Ok(PossibleTopic::Profile(identity.to_bytes()));
+    }
+
+    anyhow::bail!("topic has to start with `chat:` or `profile:`")
+}
+
+impl Server {
+    pub(crate) fn handle_admin_command(&mut self, Command { line, reply }: Command) {
+        let mut out = String::new();
+        match self.run_admin_command(&line, &mut out) {
+            Ok(Some((topic, request))) => {
+                let us = *self.swarm.local_peer_id();
+                let table = &self.swarm.behaviour().dht.table;
+                let targets = other_replicators_for(table, topic, us, &self.params).collect();
+                // replying is left to `push_replication`
+                return self.replicate(topic, request, targets, Some(reply));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                out.clear();
+                _ = writeln!(out, "error: {e:#}");
+            }
+        }
+        _ = reply.send(out);
+    }
+
+    /// Request that recreates the topic on another replicator, the pow nonce goes last so the
+    /// request is completed by appending it.
+    pub(crate) fn replication_request(&self, topic: PossibleTopic) -> anyhow::Result<Vec<u8>> {
+        Ok(match topic {
+            PossibleTopic::Profile(identity) => {
+                let profile = self.storage.profiles.get(&identity).context("profile not found")?;
+                // profile keeps the signature of its latest vault so peers can verify it
+                let proof = Proof {
+                    pk: profile.sign,
+                    nonce: profile.vault_version,
+                    signature: profile.last_sig,
+                    context: profile.vault.as_slice(),
+                };
+                (CreateProfile::PREFIX, proof, profile.enc).to_bytes()
+            }
+            // replicators that lost the chat get it back with its owner, the messages
+            // finalized before stay with the replicators that kept it
+            PossibleTopic::Chat(name) => {
+                let chat = self.storage.chats.get(&name).context("chat not found")?;
+                (CreateChat::PREFIX, name, chat.owner()).to_bytes()
+            }
+        })
+    }
+
+    /// The proof of work is solved on the blocking pool, `push_replication` sends the result.
+    pub(crate) fn replicate(
+        &mut self,
+        topic: PossibleTopic,
+        mut request: Vec<u8>,
+        targets: Vec<PeerId>,
+        reply: Option<oneshot::Sender<String>>,
+    ) {
+        let difficulty = self.admission.difficulty(topic);
+        self.replications.push(tokio::task::spawn_blocking(move || {
+            solve_pow(topic, difficulty).encode(&mut request);
+            Replication { request, targets, reply }
+        }));
+    }
+
+    pub(crate) fn push_replication(
+        &mut self,
+        Replication { request, targets, reply }: Replication,
+    ) {
+        let request = seal_rpc(self.rpc_signer.as_ref(), &request);
+        let rpc = &mut self.swarm.behaviour_mut().rpc;
+        let mut out = String::new();
+        for peer in targets {
+            _ = match rpc.request(peer, request.as_ref()) {
+                Ok(_) => writeln!(out, "sent to {peer}"),
+                Err(e) => writeln!(out, "failed to send to {peer}: {e}"),
+            };
+        }
+        if let Some(reply) = reply {
+            _ = reply.send(out);
+        }
+    }
+
+    /// `replicate` is answered later, the topic and its request are returned instead.
+    fn run_admin_command(
+        &mut self,
+        line: &str,
+        out: &mut String,
+    ) -> anyhow::Result<Option<(PossibleTopic, Vec<u8>)>> {
+        let mut args = line.split_whitespace();
+        match args.next() {
+            Some("profiles") => {
+                for (identity, profile) in &self.storage.profiles {
+                    writeln!(
+                        out,
+                        "{} vault={}B mail={}B mail_seq={}",
+                        Hex(identity),
+                        profile.vault.len(),
+                        profile.mail.len(),
+                        profile.mail_seq,
+                    )?;
+                }
+            }
+            Some("chats") => {
+                for (name, chat) in &self.storage.chats {
+                    writeln!(
+                        out,
+                        "{name} owner={} members={} block={} size={}B",
+                        Hex(&chat.owner()),
+                        chat.member_count(),
+                        chat.block_number,
+                        chat.size(),
+                    )?;
+                }
+                for name in self.storage.tombstones.keys() {
+                    writeln!(out, "{name} deleted")?;
+                }
+            }
+            Some("routes") => {
+                for route in self.swarm.behaviour().dht.table.iter() {
+                    write!(out, "{}", route.peer_id())?;
+                    route.addrs().iter().try_for_each(|a| write!(out, " {}", a.addr))?;
+                    writeln!(out)?;
+                }
+            }
+            Some("replicators") => {
+                let topic = parse_topic(args.next())?;
+                let us = *self.swarm.local_peer_id();
+                let table = &self.swarm.behaviour().dht.table;
+                for peer in replicators_for(table, topic, &self.params) {
+                    writeln!(out, "{peer}{}", if peer == us { " (us)" } else { "" })?;
+                }
+            }
+            Some("stage") => {
+                let PossibleTopic::Chat(name) = parse_topic(args.next())? else {
+                    anyhow::bail!("only chats have block stages");
+                };
+                let chat = self.storage.chats.get(&name).context("chat not found")?;
+                write!(out, "{}", chat.stage())?;
+            }
+            Some("evict") => {
+                let found = match parse_topic(args.next())? {
+                    PossibleTopic::Profile(identity) => {
+                        self.storage.profiles.remove(&identity).is_some()
+                    }
+                    PossibleTopic::Chat(name) => self.storage.chats.remove(&name).is_some(),
+                };
+                anyhow::ensure!(found, "topic not found");
+                writeln!(out, "evicted")?;
+            }
+            Some("replicate") => {
+                let topic = parse_topic(args.next())?;
+                return Ok(Some((topic, self.replication_request(topic)?)));
+            }
+            Some("help") | None => out.push_str(HELP),
+            Some(other) => anyhow::bail!("unknown command `{other}`, try `help`"),
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics_are_parsed() {
+        let chat = ChatName::from("general").unwrap();
+        assert!(
+            matches!(parse_topic(Some("chat:general")), Ok(PossibleTopic::Chat(c)) if c == chat)
+        );
+
+        let identity = [0xab; 32];
+        let arg = format!("profile:{}", Hex(&identity));
+        assert!(matches!(parse_topic(Some(&arg)), Ok(PossibleTopic::Profile(i)) if i == identity));
+
+        assert!(parse_topic(None).is_err());
+        assert!(parse_topic(Some("general")).is_err());
+        assert!(parse_topic(Some("profile:zz")).is_err());
+        assert!(parse_topic(Some(&format!("chat:{}", "a".repeat(64)))).is_err());
+    }
+}
@@ -0,0 +1,35 @@
+//! Sends a single command to the admin socket of a running server and prints the reply.
+//!
+//! usage: server-admin [--socket <path>] <command> [args...]
+//! the socket defaults to `$ADMIN_SOCKET`, run `server-admin help` to list commands
+use std::{
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+};
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    let socket = if args.peek().is_some_and(|a| a == "--socket") {
+        args.next();
+        args.next()
+    } else {
+        std::env::var("ADMIN_SOCKET").ok()
+    };
+    let Some(socket) = socket else {
+        eprintln!("admin socket not specified, use --socket or ADMIN_SOCKET");
+        std::process::exit(1);
+    };
+
+    let command = args.collect::<Vec<_>>().join(" ");
+
+    let mut stream = UnixStream::connect(&socket)?;
+    writeln!(stream, "{command}")?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    print!("{reply}");
+
+    Ok(())
+}
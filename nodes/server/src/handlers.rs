@@ -1,4 +1,4 @@
-pub use {chat::*, profile::*, replicated::*, retry::*};
+pub use {admission::*, chat::*, replicated::*, retry::*};
 use {
     chat_spec::{Protocol, ProtocolResult, Subscribe},
     component_utils::{codec, Codec},
@@ -80,15 +80,22 @@ macro_rules! compose_handlers {
                 bp: &mut impl component_utils::codec::Buffer,
             ) -> Result<$crate::handlers::ExitedEarly, $crate::handlers::HandlerExecError>
             {
-                $(if <<$handler as Handler>::Protocol as Protocol>::PREFIX == req.prefix
-                    { return self.${index(0)}.execute($crate::extract_ctx!(cx), req, bp) })*
+                $(if <<$handler as Handler>::Protocol as Protocol>::PREFIX == req.prefix {
+                    cx.metrics.record_request(stringify!($name), stringify!($handler));
+                    return self.${index(0)}.execute($crate::extract_ctx!(cx), req, bp)
+                })*
                 Err($crate::handlers::HandlerExecError::UnknownPrefix)
             }
 
+            pub fn is_idle(&self) -> bool {
+                $(self.${index(0)}.is_empty() &&)* true
+            }
+
         }
     )*};
 }
 
+mod admission;
 mod chat;
 mod peer_search;
 mod populating;
@@ -225,6 +232,12 @@ impl<H> Default for HandlerNest<H> {
 
 pub type ExitedEarly = bool;
 
+impl<H> HandlerNest<H> {
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+}
+
 impl<H: Handler> HandlerNest<H> {
     pub fn execute(
         &mut self,
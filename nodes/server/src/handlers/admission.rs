@@ -0,0 +1,26 @@
+use chat_spec::{PossibleTopic, PowNonce};
+
+/// Decides whether new topics can be created, everything that creates state from nothing
+/// (profiles, chats) needs to pass this.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AdmissionPolicy {
+    pub profile_difficulty: u8,
+    pub chat_difficulty: u8,
+}
+
+impl AdmissionPolicy {
+    /// Returns the required difficulty on failure so the client can solve the puzzle again.
+    pub fn admit(&self, topic: impl Into<PossibleTopic>, nonce: PowNonce) -> Result<(), u8> {
+        let topic = topic.into();
+        let difficulty = self.difficulty(topic);
+        crate::ensure!(chat_spec::pow_difficulty(topic, nonce) >= difficulty, difficulty);
+        Ok(())
+    }
+
+    pub fn difficulty(&self, topic: PossibleTopic) -> u8 {
+        match topic {
+            PossibleTopic::Profile(_) => self.profile_difficulty,
+            PossibleTopic::Chat(_) => self.chat_difficulty,
+        }
+    }
+}
@@ -1,36 +1,44 @@
 use {
     super::{Codec, Protocol, ProtocolResult, RequestOrigin, Scope, SyncHandler},
+    crate::util::Hex,
     chat_spec::{
         advance_nonce, retain_messages_in_vec, unpack_messages, unpack_messages_ref, BlockNumber,
         ChatAction, ChatActionError, ChatEvent, ChatName, CreateChat, CreateChatError, Cursor,
         FetchLatestBlock, FetchLatestBlockError, FetchMessages, FetchMessagesError, Identity,
-        InvalidBlockReason, Message, Nonce, PerformChatAction, ProposeMsgBlock,
-        ProposeMsgBlockError, SendBlock, SendBlockError, REPLICATION_FACTOR,
+        InvalidBlockReason, Message, NetworkParams, Nonce, PerformChatAction, ProposeMsgBlock,
+        ProposeMsgBlockError, SendBlock, SendBlockError,
     },
     component_utils::{encode_len, Buffer, NoCapOverflow, Reminder},
     std::{
-        collections::{HashMap, VecDeque},
-        iter, usize,
+        collections::{hash_map::Entry, HashMap, VecDeque},
+        fmt, iter, usize,
+        time::{Duration, Instant},
     },
 };
 
-const MAX_MESSAGE_SIZE: usize = 1024;
+pub(crate) const MAX_EPHEMERAL_SIZE: usize = 256;
 const MESSAGE_FETCH_LIMIT: usize = 20;
-const BLOCK_SIZE: usize = if cfg!(test) { 1024 * 4 } else { 1024 * 32 };
-const BLOCK_HISTORY: usize = 32;
+const CHAT_TOMBSTONE_PERIOD: Duration = Duration::from_secs(60 * 60 * 24);
 
 impl SyncHandler for CreateChat {
     fn execute<'a>(
         mut cx: Scope<'a>,
-        (name, identity): Self::Request<'_>,
+        (name, identity, pow): Self::Request<'_>,
     ) -> ProtocolResult<'a, Self> {
+        cx.admission.admit(name, pow).map_err(CreateChatError::InsufficientWork)?;
+
+        if let Entry::Occupied(tombstone) = cx.storage.tombstones.entry(name) {
+            crate::ensure!(
+                tombstone.get().elapsed() >= CHAT_TOMBSTONE_PERIOD,
+                CreateChatError::RecentlyDeleted
+            );
+            tombstone.remove();
+        }
+
         let chat_entry = cx.storage.chats.entry(name);
-        crate::ensure!(
-            let std::collections::hash_map::Entry::Vacant(entry) = chat_entry,
-            CreateChatError::AlreadyExists
-        );
+        crate::ensure!(let Entry::Vacant(entry) = chat_entry, CreateChatError::AlreadyExists);
 
-        entry.insert(Chat::new(identity, false));
+        entry.insert(Chat::new(identity, false, cx.cx.params));
 
         Ok(())
     }
@@ -43,6 +51,7 @@ impl SyncHandler for PerformChatAction {
     ) -> ProtocolResult<'a, Self> {
         crate::ensure!(proof.verify(), ChatActionError::InvalidProof);
 
+        let params = sc.cx.params;
         let chat =
             sc.cx.storage.chats.get_mut(&proof.context).ok_or(ChatActionError::ChatNotFound)?;
 
@@ -63,7 +72,10 @@ impl SyncHandler for PerformChatAction {
                 );
             }
             ChatAction::SendMessage(Reminder(msg)) => {
-                crate::ensure!(msg.len() <= MAX_MESSAGE_SIZE, ChatActionError::MessageTooLarge);
+                crate::ensure!(
+                    msg.len() <= params.max_message_size,
+                    ChatActionError::MessageTooLarge
+                );
 
                 // TODO: move this to context
                 let bn = chat.block_number;
@@ -72,7 +84,7 @@ impl SyncHandler for PerformChatAction {
                     nonce: sender.action - 1,
                     content: Reminder(msg),
                 };
-                match chat.push_message(message, &mut sc.cx.res.hashes) {
+                match chat.push_message(message, params, &mut sc.cx.res.hashes) {
                     Err(Some(hash)) => send_block_proposals(sc.reborrow(), proof.context, bn, hash),
                     Err(None) => return Err(ChatActionError::MessageBlockNotFinalized),
                     Ok(()) => (),
@@ -80,6 +92,27 @@ impl SyncHandler for PerformChatAction {
 
                 sc.push(proof.context, ChatEvent::Message(proof, Reminder(msg)));
             }
+            ChatAction::Ephemeral(Reminder(content)) => {
+                crate::ensure!(
+                    content.len() <= MAX_EPHEMERAL_SIZE,
+                    ChatActionError::MessageTooLarge
+                );
+                sc.push(proof.context, ChatEvent::Ephemeral(proof, Reminder(content)));
+            }
+            ChatAction::TransferOwnership(id) => {
+                crate::ensure!(chat.owner == sender_id, ChatActionError::NotOwner);
+                crate::ensure!(chat.members.contains_key(&id), ChatActionError::UserNotMember);
+                chat.owner = id;
+            }
+            ChatAction::DeleteChat => {
+                crate::ensure!(chat.owner == sender_id, ChatActionError::NotOwner);
+                sc.push(proof.context, ChatEvent::Deleted(proof));
+                sc.cx.unsubscribe_all(proof.context.into());
+                let storage = &mut sc.cx.storage;
+                storage.chats.remove(&proof.context);
+                storage.tombstones.retain(|_, t| t.elapsed() < CHAT_TOMBSTONE_PERIOD);
+                storage.tombstones.insert(proof.context, Instant::now());
+            }
         }
 
         Ok(())
@@ -88,11 +121,13 @@ impl SyncHandler for PerformChatAction {
 
 fn send_block_proposals(sc: Scope, name: ChatName, number: BlockNumber, hash: crypto::Hash) {
     let us = *sc.cx.swarm.local_peer_id();
-    let beh = sc.cx.swarm.behaviour_mut();
     let mut msg = [0; std::mem::size_of::<(u8, ChatName, crypto::Hash)>()];
     ProposeMsgBlock::rpc((name, number, hash)).encode(&mut msg.as_mut_slice()).unwrap();
-    for recip in crate::other_replicators_for(&beh.dht.table, name, us) {
-        _ = beh.rpc.request(recip, msg);
+    // signed proposals can be used as evidence of equivocation
+    let msg = sc.cx.seal(&msg);
+    let beh = sc.cx.swarm.behaviour_mut();
+    for recip in crate::reachable_replicators_for(&beh.dht.table, name, us, sc.cx.params) {
+        _ = beh.rpc.request(recip, msg.as_ref());
     }
 }
 
@@ -128,6 +163,7 @@ impl SyncHandler for ProposeMsgBlock {
 
                 let packet =
                     SendBlock::rpc((chat, number, Reminder(block.data.as_ref()))).to_bytes();
+                let packet = sc.cx.seal(&packet).into_owned();
                 _ = sc.cx.swarm.behaviour_mut().rpc.request(origin, packet);
                 return Ok(());
             }
@@ -146,14 +182,14 @@ impl SyncHandler for ProposeMsgBlock {
 
         others[index] = phash;
 
-        if others.iter().filter(|h| **h == phash).count()
-            > REPLICATION_FACTOR.get() / 2 - usize::from(we_match)
-        {
+        let params = sc.cx.params;
+        let needed = params.majority().saturating_sub(usize::from(we_match));
+        if others.iter().filter(|h| **h == phash).count() > needed {
             chat_data.stage = if let Some(block) = proposed.take()
                 && block.hash == phash
             {
-                chat_data.push_to_finalized(block);
-                BlockStage::default()
+                chat_data.push_to_finalized(block, params);
+                BlockStage::new(params)
             } else {
                 BlockStage::Recovering { final_hash: phash, we_finalized }
             };
@@ -194,11 +230,12 @@ impl SyncHandler for SendBlock {
 
                 others[index] = hash;
 
-                if others.iter().filter(|h| **h == hash).count() < REPLICATION_FACTOR.get() / 2 {
+                let params = sc.cx.params;
+                if others.iter().filter(|h| **h == hash).count() < params.majority() {
                     Err(InvalidBlock(MajorityMismatch))
                 } else {
-                    chat_data.stage = BlockStage::default();
-                    chat_data.push_to_finalized(Block { hash, data: block.into() });
+                    chat_data.stage = BlockStage::new(params);
+                    chat_data.push_to_finalized(Block { hash, data: block.into() }, params);
 
                     Ok(())
                 }
@@ -215,7 +252,7 @@ impl SyncHandler for SendBlock {
                     !hash_temp.contains(&crypto::hash::from_slice(msg))
                 });
 
-                chat_data.push_to_finalized(Block { hash, data: block.into() });
+                chat_data.push_to_finalized(Block { hash, data: block.into() }, sc.cx.params);
 
                 Ok(())
             }
@@ -285,19 +322,39 @@ struct Block {
     data: Box<[u8]>,
 }
 
+/// `others` holds a proposal slot for each other replicator.
 #[derive(Codec)]
 enum BlockStage {
-    Unfinalized { proposed: Option<Block>, others: [crypto::Hash; REPLICATION_FACTOR.get()] },
+    Unfinalized { proposed: Option<Block>, others: Vec<crypto::Hash> },
     Recovering { final_hash: crypto::Hash, we_finalized: bool },
 }
 
-impl Default for BlockStage {
-    fn default() -> Self {
-        Self::Unfinalized { proposed: None, others: Default::default() }
+impl fmt::Display for BlockStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unfinalized { proposed, others } => {
+                match proposed {
+                    Some(p) => writeln!(f, "proposed {} ({}B)", Hex(&p.hash), p.data.len())?,
+                    None => writeln!(f, "collecting messages")?,
+                }
+                for hash in others.iter().filter(|h| **h != crypto::Hash::default()) {
+                    writeln!(f, "other proposal {}", Hex(hash))?;
+                }
+                Ok(())
+            }
+            Self::Recovering { final_hash, we_finalized } => {
+                writeln!(f, "recovering to {}, we finalized: {we_finalized}", Hex(final_hash))
+            }
+        }
     }
 }
 
 impl BlockStage {
+    fn new(params: &NetworkParams) -> Self {
+        let others = vec![Default::default(); params.replication_factor.get()];
+        Self::Unfinalized { proposed: None, others }
+    }
+
     fn unfinalized_block(&mut self) -> Option<&mut [u8]> {
         match self {
             Self::Unfinalized { proposed, .. } => proposed.as_mut().map(|p| p.data.as_mut()),
@@ -308,6 +365,7 @@ impl BlockStage {
 
 #[derive(Codec)]
 pub struct Chat {
+    owner: Identity,
     members: HashMap<Identity, Member>,
     finalized: VecDeque<Block>,
     current_block: Vec<u8>,
@@ -317,13 +375,14 @@ pub struct Chat {
 }
 
 impl Chat {
-    pub fn new(id: Identity, restoring: bool) -> Self {
+    pub fn new(id: Identity, restoring: bool, params: &NetworkParams) -> Self {
         Self {
+            owner: id,
             members: [(id, Member::new())].into(),
             finalized: Default::default(),
-            current_block: Vec::with_capacity(BLOCK_SIZE),
+            current_block: Vec::with_capacity(params.block_size),
             block_number: 0,
-            stage: Default::default(),
+            stage: BlockStage::new(params),
             restoring,
         }
     }
@@ -331,6 +390,7 @@ impl Chat {
     pub fn push_message<'a>(
         &mut self,
         msg: impl Codec<'a>,
+        params: &NetworkParams,
         hash_temp: &mut Vec<crypto::Hash>,
     ) -> Result<(), Option<crypto::Hash>> {
         let prev_len = self.current_block.len();
@@ -353,8 +413,8 @@ impl Chat {
             BlockStage::Unfinalized { proposed, .. } if proposed.is_some() => return Err(None),
             BlockStage::Unfinalized { proposed, others } => {
                 let hash = Self::hash_block(self.current_block.as_slice(), hash_temp);
-                if others.iter().filter(|h| **h == hash).count() >= REPLICATION_FACTOR.get() / 2 {
-                    self.finalize_current_block(hash);
+                if others.iter().filter(|h| **h == hash).count() >= params.majority() {
+                    self.finalize_current_block(hash, params);
                 } else {
                     *proposed = Some(Block { hash, data: self.current_block.as_slice().into() });
                     self.current_block.clear();
@@ -367,7 +427,7 @@ impl Chat {
                 *we_finalized = true;
                 let hash = Self::hash_block(self.current_block.as_slice(), hash_temp);
                 if hash == *final_hash {
-                    self.finalize_current_block(hash);
+                    self.finalize_current_block(hash, params);
                 } else {
                     self.current_block.clear();
                 }
@@ -380,15 +440,32 @@ impl Chat {
         Err(err)
     }
 
-    fn finalize_current_block(&mut self, hash: crypto::Hash) {
-        self.stage = BlockStage::default();
-        self.push_to_finalized(Block { hash, data: self.current_block.as_slice().into() });
+    pub fn owner(&self) -> Identity {
+        self.owner
+    }
+
+    pub fn member_count(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn size(&self) -> usize {
+        self.current_block.len() + self.finalized.iter().map(|b| b.data.len()).sum::<usize>()
+    }
+
+    pub fn stage(&self) -> impl fmt::Display + '_ {
+        &self.stage
+    }
+
+    fn finalize_current_block(&mut self, hash: crypto::Hash, params: &NetworkParams) {
+        self.stage = BlockStage::new(params);
+        let block = Block { hash, data: self.current_block.as_slice().into() };
+        self.push_to_finalized(block, params);
         self.current_block.clear();
         self.block_number += 1;
     }
 
-    fn push_to_finalized(&mut self, block: Block) {
-        if self.finalized.len() == BLOCK_HISTORY {
+    fn push_to_finalized(&mut self, block: Block, params: &NetworkParams) {
+        if self.finalized.len() >= params.block_history {
             self.finalized.pop_back();
         }
         self.finalized.push_front(block);
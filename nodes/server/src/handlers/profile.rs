@@ -1,11 +1,9 @@
 use {
-    super::{
-        CallId, Codec, Handler, HandlerResult, ProtocolResult, RequestOrigin, Scope, SyncHandler,
-    },
+    super::{ProtocolResult, RequestOrigin, Scope, SyncHandler},
     chat_spec::{
         advance_nonce, CreateAccountError, CreateProfile, FetchFullProfile, FetchProfile,
-        FetchProfileError, FetchVault, FetchVaultError, Identity, Profile, Protocol, ReadMail,
-        ReadMailError, SendMailError, SetVault, SetVaultError,
+        FetchProfileError, FetchVault, FetchVaultError, Profile, ReadMail, ReadMailError, SendMail,
+        SendMailError, SetVault, SetVaultError, SubscribeProfile,
     },
     component_utils::Reminder,
     std::collections::hash_map::Entry,
@@ -30,10 +28,16 @@ impl SyncHandler for FetchFullProfile {
 }
 
 impl SyncHandler for CreateProfile {
-    fn execute<'a>(mut cx: Scope<'a>, (proof, enc): Self::Request<'_>) -> ProtocolResult<'a, Self> {
+    fn execute<'a>(
+        mut cx: Scope<'a>,
+        (proof, enc, pow): Self::Request<'_>,
+    ) -> ProtocolResult<'a, Self> {
+        let user_id = crypto::hash::from_raw(&proof.pk);
+        if !cx.storage.profiles.contains_key(&user_id) {
+            cx.admission.admit(user_id, pow).map_err(CreateAccountError::InsufficientWork)?;
+        }
         crate::ensure!(proof.verify(), CreateAccountError::InvalidProof);
 
-        let user_id = crypto::hash::from_raw(&proof.pk);
         let entry = cx.storage.profiles.entry(user_id);
 
         match entry {
@@ -44,6 +48,7 @@ impl SyncHandler for CreateProfile {
                     last_sig: proof.signature,
                     vault_version: proof.nonce,
                     mail_action: proof.nonce,
+                    mail_seq: 0,
                     vault: proof.context.to_vec(),
                     mail: Vec::new(),
                 });
@@ -93,107 +98,49 @@ impl SyncHandler for FetchVault {
 }
 
 impl SyncHandler for ReadMail {
-    fn execute<'a>(sc: Scope<'a>, request: Self::Request<'_>) -> ProtocolResult<'a, Self> {
-        crate::ensure!(request.verify(), ReadMailError::InvalidProof);
+    fn execute<'a>(sc: Scope<'a>, (proof, from): Self::Request<'_>) -> ProtocolResult<'a, Self> {
+        crate::ensure!(proof.verify(), ReadMailError::InvalidProof);
         let store = sc.cx.storage;
-        let identity = crypto::hash::from_raw(&request.pk);
+        let identity = crypto::hash::from_raw(&proof.pk);
         let profile = store.profiles.get_mut(&identity);
         crate::ensure!(let Some(profile) = profile, ReadMailError::NotFound);
         crate::ensure!(
-            advance_nonce(&mut profile.mail_action, request.nonce),
+            advance_nonce(&mut profile.mail_action, proof.nonce),
             ReadMailError::InvalidAction
         );
-        store.online.insert(identity, sc.origin);
-        Ok(Reminder(profile.read_mail()))
+        // mail pushed to subscribers stays until acknowledged here
+        profile.ack_mail(from);
+        Ok((profile.mail_seq, Reminder(profile.mail.as_slice())))
     }
 }
 
-pub struct SendMail {
-    dm: CallId,
-    for_who: Identity,
-}
+impl SyncHandler for SendMail {
+    fn execute<'a>(
+        mut sc: Scope<'a>,
+        (for_who, Reminder(mail)): Self::Request<'_>,
+    ) -> ProtocolResult<'a, Self> {
+        let profile = sc.cx.storage.profiles.get_mut(&for_who);
+        crate::ensure!(let Some(profile) = profile, SendMailError::NotFound);
+        crate::ensure!(profile.mail.len() + mail.len() < MAIL_BOX_CAP, SendMailError::MailboxFull);
 
-#[allow(clippy::unnecessary_wraps)]
-impl SendMail {
-    fn clear_presence(self, mut cx: Scope) -> HandlerResult<Self> {
-        cx.storage.online.remove(&self.for_who);
-        Ok(Ok(()))
-    }
+        // every replica holds the mail until it is acknowledged, subscribers only get a copy
+        let seq = profile.push_mail(mail);
+        sc.cx.push(for_who, (seq, Reminder(mail)));
 
-    fn pop_pushed_mail(self, mut cx: Scope) -> HandlerResult<Self> {
-        if let Some(profile) = cx.storage.profiles.get_mut(&self.for_who) {
-            profile.mail.clear();
-        };
-        Ok(Err(SendMailError::SentDirectly))
+        Ok(())
     }
 }
 
-impl Handler for SendMail {
-    type Event = rpc::Event;
-    type Protocol = chat_spec::SendMail;
-
+/// Missed mail is replayed by [`crate::Server`] once the response is written.
+impl SyncHandler for SubscribeProfile {
     fn execute<'a>(
-        sc: Scope<'a>,
-        req @ (for_who, Reminder(mail)): <Self::Protocol as Protocol>::Request<'_>,
-    ) -> HandlerResult<'a, Self> {
-        crate::ensure!(let Some(profile) = sc.cx.storage.profiles.get_mut(&for_who), Ok(SendMailError::NotFound));
-        crate::ensure!(
-            profile.mail.len() + mail.len() < MAIL_BOX_CAP,
-            Ok(SendMailError::MailboxFull)
-        );
-
-        let Entry::Occupied(online_in) = sc.cx.storage.online.entry(for_who) else {
-            profile.push_mail(mail);
-            return Ok(Ok(()));
-        };
-
-        match *online_in.get() {
-            RequestOrigin::Client(p) => {
-                crate::ensure!(
-                    RequestOrigin::Client(p) != sc.origin,
-                    Ok(SendMailError::SendingToSelf)
-                );
-                crate::ensure!(
-                    !crate::push_notification(sc.cx.clients, for_who, Reminder(mail), p),
-                    Ok(SendMailError::SentDirectly)
-                );
-
-                online_in.remove();
-                profile.push_mail(mail);
-                Ok(Ok(()))
-            }
-            RequestOrigin::Server(peer) => {
-                profile.push_mail(mail);
-                if matches!(sc.origin, RequestOrigin::Server(_)) {
-                    online_in.remove();
-                    return Ok(Ok(()));
-                }
-
-                let packet = (sc.prefix, req).to_bytes();
-                if let Ok(dm) = sc.cx.swarm.behaviour_mut().rpc.request(peer, packet) {
-                    Err(Self { dm, for_who })
-                } else {
-                    Ok(Ok(()))
-                }
-            }
+        mut sc: Scope<'a>,
+        (identity, _): Self::Request<'_>,
+    ) -> ProtocolResult<'a, Self> {
+        if let RequestOrigin::Client(path) = sc.origin {
+            sc.cx.subscribe(identity.into(), sc.call_id, path);
         }
-    }
 
-    fn resume<'a>(self, sc: Scope<'a>, enent: &'a Self::Event) -> HandlerResult<'a, Self> {
-        crate::ensure!(let rpc::Event::Response(_, call, res) = enent, self);
-        crate::ensure!(*call == self.dm, self);
-
-        let mut request = match res {
-            Ok((request, ..)) => request.as_slice(),
-            Err(_) => return self.clear_presence(sc),
-        };
-
-        if ProtocolResult::<'a, Self::Protocol>::decode(&mut request)
-            == Some(Err(SendMailError::SentDirectly))
-        {
-            self.pop_pushed_mail(sc)
-        } else {
-            self.clear_presence(sc)
-        }
+        Ok(())
     }
 }
@@ -1,6 +1,5 @@
 use {
     super::{Handler, TryUnwrap},
-    crate::REPLICATION_FACTOR,
     chat_spec::{PossibleTopic, Protocol, ReplError, ReplVec, ToPossibleTopic},
     component_utils::{Codec, FindAndRemove},
     rpc::CallId,
@@ -19,9 +18,10 @@ impl<H> Repl<H> {
         cx: crate::Context,
     ) -> Self {
         let us = *cx.swarm.local_peer_id();
+        let request = cx.seal(&request);
         let beh = cx.swarm.behaviour_mut();
-        let ongoing = crate::other_replicators_for(&beh.dht.table, topic, us)
-            .filter_map(|peer| beh.rpc.request(peer, request.as_slice()).ok())
+        let ongoing = crate::reachable_replicators_for(&beh.dht.table, topic, us, cx.params)
+            .filter_map(|peer| beh.rpc.request(peer, request.as_ref()).ok())
             .collect();
 
         Self::Replicating { response, ongoing, matched: 0 }
@@ -92,7 +92,12 @@ where
             Ok((remote_resp, _)) => {
                 *matched += usize::from(remote_resp.as_slice() == response.as_slice());
 
-                if *matched > REPLICATION_FACTOR.get() / 2 {
+                if *matched > cx.params.majority() {
+                    // the rest can not change the outcome
+                    for call in ongoing.drain(..) {
+                        cx.swarm.behaviour_mut().rpc.cancel(call);
+                    }
+
                     let Some(resp): Option<Result<_, _>> =
                         Codec::decode(&mut remote_resp.as_slice())
                     else {
@@ -107,7 +112,11 @@ where
             }
         }
 
-        if ongoing.len() + *matched < REPLICATION_FACTOR.get() / 2 {
+        if ongoing.len() + *matched < cx.params.majority() {
+            for call in ongoing.drain(..) {
+                cx.swarm.behaviour_mut().rpc.cancel(call);
+            }
+            cx.metrics.repl_no_majority.inc();
             return Ok(Err(ReplError::NoMajority));
         }
 
@@ -2,7 +2,7 @@ use {
     super::{codec, CallId, Codec, Handler, HandlerResult, ProtocolResult, Scope, TryUnwrap},
     chat_spec::{
         unpack_messages_ref, BlockNumber, FetchFullProfile, FetchLatestBlock, Identity,
-        PossibleTopic, Protocol, ReplVec, ToPossibleTopic, REPLICATION_FACTOR,
+        PossibleTopic, Protocol, ReplVec, ToPossibleTopic,
     },
     component_utils::{FindAndRemove, Reminder},
     std::{collections::hash_map::Entry, marker::PhantomData},
@@ -62,6 +62,9 @@ where
                     .map_err(|h| Self::Handling(h, topic, PhantomData))
                     .map(|r| r.map_err(NotFoundError::Inner))
             }
+            PossibleTopic::Chat(name) if sc.cx.storage.tombstones.contains_key(&name) => {
+                return Ok(Err(NotFoundError::NotFound));
+            }
             PossibleTopic::Chat(name) => {
                 //sc.cx.storage.chats.insert(name);
                 FetchLatestBlock::rpc(name)
@@ -71,10 +74,12 @@ where
         }
 
         let us = *sc.cx.swarm.local_peer_id();
+        let packet = sc.cx.seal(&packet);
         let beh = sc.cx.swarm.behaviour_mut();
-        let pending = crate::other_replicators_for(&beh.dht.table, topic, us)
-            .filter_map(|peer| beh.rpc.request(peer, packet).ok())
+        let pending = crate::reachable_replicators_for(&beh.dht.table, topic, us, sc.cx.params)
+            .filter_map(|peer| beh.rpc.request(peer, packet.as_ref()).ok())
             .collect();
+        sc.cx.metrics.retry_restores.inc();
 
         Err(Self::Restoring(Restoring {
             topic,
@@ -140,15 +145,13 @@ where
                             r.block_data.push((block_number, end));
                         }
                     },
-                    Err(_) => {
-                        todo!()
-                    }
+                    Err(e) => log::warn!("failed to fetch topic for restore: {e}"),
                 }
 
                 crate::ensure!(r.pending.is_empty(), Self::Restoring(r));
 
                 if let PossibleTopic::Chat(_name) = r.topic
-                    && r.block_data.len() > REPLICATION_FACTOR.get() / 2
+                    && r.block_data.len() > sc.cx.params.majority()
                 {
                     sc.cx.res.hashes.clear();
                     let mut message_bounds = r
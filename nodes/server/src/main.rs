@@ -11,19 +11,24 @@
 #[cfg(test)]
 use futures::channel::mpsc;
 use {
-    self::handlers::RequestOrigin,
+    self::{
+        handlers::{AdmissionPolicy, RequestOrigin},
+        metrics::Metrics,
+        rate_limit::RateLimiter,
+    },
     anyhow::Context as _,
-    chain_api::{ContractId, NodeAddress, NodeData},
+    chain_api::{ContractId, NodeData, NodeEndpoint, NodeTransport},
     chat_spec::{
         CallId, ChatName, CreateChat, CreateProfile, FetchFullProfile, FetchLatestBlock,
-        FetchMessages, FetchProfile, FetchVault, Identity, PerformChatAction, PossibleTopic,
-        Profile, ProposeMsgBlock, Protocol, ReadMail, SendBlock, SetVault, Subscribe, Topic,
-        REPLICATION_FACTOR,
+        FetchMessages, FetchProfile, FetchVault, Identity, Mail, NetworkParams, PerformChatAction,
+        PossibleTopic, Profile, Proof, ProposeMsgBlock, Protocol, ReadMail, Rejection, SendBlock,
+        SendMail, SetVault, SignedRpc, Subscribe, SubscribeProfile, Topic, LEAVING_PREFIX,
+        SIGNED_RPC_PREFIX,
     },
-    component_utils::{Codec, LinearMap, Reminder},
+    component_utils::{crypto::ToProofContext, Codec, LinearMap, Reminder},
     crypto::{enc, sign, TransmutationCircle},
     dht::Route,
-    handlers::{Chat, Handler, HandlerNest, Repl, Retry, SendMail, TryUnwrap},
+    handlers::{Chat, Handler, HandlerNest, Repl, Retry, TryUnwrap},
     libp2p::{
         core::{multiaddr, muxing::StreamMuxerBox, upgrade::Version},
         futures::{self, stream::SelectAll, SinkExt, StreamExt},
@@ -34,12 +39,14 @@ use {
     onion::{EncryptedStream, PathId},
     rand_core::OsRng,
     std::{
-        collections::HashMap,
+        borrow::Cow,
+        collections::{HashMap, HashSet},
         convert::Infallible,
         fs,
         future::Future,
         io,
         net::{IpAddr, Ipv4Addr},
+        sync::Arc,
         time::Duration,
     },
 };
@@ -52,13 +59,21 @@ macro_rules! extract_ctx {
             clients: &mut $self.clients,
             storage: &mut $self.storage,
             res: &mut $self.res,
+            admission: &$self.admission,
+            params: &$self.params,
+            metrics: &$self.metrics,
+            rpc_signer: $self.rpc_signer.as_ref(),
         }
     };
 }
 
+mod admin;
 mod handlers;
+mod metrics;
+mod rate_limit;
 #[cfg(test)]
 mod tests;
+mod util;
 
 type ReplRetry<T> = Repl<Retry<T>>;
 
@@ -80,6 +95,7 @@ compose_handlers! {
 
     ExternalServer {
         Subscribe,
+        SubscribeProfile,
 
         Repl<CreateProfile>,
         ReplRetry<SetVault>,
@@ -124,16 +140,70 @@ crypto::impl_transmute! {
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
 
-    let node_config = NodeConfig::from_env();
-    let chain_config = ChainConfig::from_env();
+    let sources = config::Sources::from_process()?;
+    let mut errors = config::Errors::default();
+    let node_config = NodeConfig::from_sources(&sources, "", &mut errors);
+    let chain_config = ChainConfig::from_sources(&sources, "", &mut errors);
+    let (node_config, chain_config) = errors.into_result(node_config.zip(chain_config))?;
     let (keys, is_new) = Server::load_keys(&node_config.key_path)?;
-    let (node_list, stake_events) = deal_with_chain(chain_config, &keys, is_new).await?;
+    let reclaim_on_exit = chain_config.reclaim_on_exit;
+    let full_list = !node_config.dht.lookups;
+    let (node_list, params, stake_events, node_checks, chain) =
+        deal_with_chain(chain_config, &keys, is_new, full_list).await?;
+
+    let shutdown_timeout = Duration::from_millis(node_config.shutdown_timeout);
+    let metrics_port = node_config.metrics_port;
+    let admin_socket = node_config.admin_socket.clone();
+    let (admin_sink, admin_commands) = futures::channel::mpsc::channel(0);
+    let mut server = Server::new(
+        node_config,
+        keys,
+        node_list,
+        params,
+        stake_events,
+        node_checks,
+        admin_commands,
+    )?;
+    if !admin_socket.is_empty() {
+        tokio::spawn(async move {
+            if let Err(e) = admin::listen(admin_socket, admin_sink).await {
+                log::error!("admin socket failed: {e}");
+            }
+        });
+    }
+    if metrics_port != 0 {
+        let metrics = server.metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(metrics, metrics_port).await {
+                log::error!("metrics endpoint failed: {e}");
+            }
+        });
+    }
+
+    tokio::select! {
+        never = &mut server => match never {},
+        res = shutdown_signal() => res.context("listening for shutdown signals")?,
+    }
 
-    Server::new(node_config, keys, node_list, stake_events)?.await;
+    log::info!("shutting down");
+    server.shutdown(shutdown_timeout).await;
+
+    if reclaim_on_exit {
+        chain.reclaim().await?;
+        log::info!("stake reclaimed");
+    }
 
     Ok(())
 }
 
+async fn shutdown_signal() -> io::Result<()> {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
 config::env_config! {
     struct NodeConfig {
         port: u16,
@@ -141,6 +211,46 @@ config::env_config! {
         key_path: String,
         boot_nodes: config::List<Multiaddr>,
         idle_timeout: u64,
+        profile_pow_difficulty: u8,
+        chat_pow_difficulty: u8,
+        path_rate_limit: u32,
+        identity_rate_limit: u32,
+        metrics_port: u16,
+        admin_socket: String,
+        shutdown_timeout: u64,
+        #[section]
+        onion: OnionConfig,
+        #[section]
+        rpc: RpcConfig,
+        #[section]
+        dht: DhtConfig,
+    }
+}
+
+config::env_config! {
+    struct OnionConfig {
+        max_streams: usize = "10",
+        keep_alive_interval: u64 = "100000",
+    }
+}
+
+config::env_config! {
+    struct DhtConfig {
+        /// Milliseconds, 0 disables pinging suspect nodes.
+        ping_interval: u64 = "10000",
+        /// Milliseconds a failing node keeps its replication slot.
+        suspect_grace: u64 = "30000",
+        /// Learn nodes by looking them up from the boot nodes instead of downloading the whole
+        /// node list. Boot nodes need a `/p2p` suffix and should keep the full list. Found nodes
+        /// are only dialed until the chain confirms their stake.
+        lookups: bool = "false",
+    }
+}
+
+config::env_config! {
+    struct RpcConfig {
+        sign: bool = "false",
+        require_signed: bool = "false",
     }
 }
 
@@ -148,14 +258,54 @@ config::env_config! {
     struct ChainConfig {
         exposed_address: IpAddr,
         port: u16,
+        ws_port: u16,
         nonce: u64,
-        chain_node: String,
+        chain_nodes: config::List<String>,
         node_account: String,
         node_contract: ContractId,
+        reclaim_on_exit: bool,
     }
 }
 
-type StakeEvents = futures::channel::mpsc::Receiver<chain_api::Result<chain_api::StakeEvent>>;
+const CHAIN_BACKOFF_START: Duration = Duration::from_millis(500);
+const CHAIN_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+type ChainClient = chain_api::Client<chain_api::Keypair>;
+
+enum ChainEvent {
+    Stake(chain_api::StakeEvent),
+    /// Sent after reconnecting, events could have been lost in the meantime.
+    NodeList(Vec<(NodeData, Vec<NodeEndpoint>, u32)>),
+    /// Registrations of the nodes sent through [`NodeChecks`], unstaked ones are left out.
+    Confirmed(Vec<(NodeData, Vec<NodeEndpoint>, u32)>),
+}
+
+/// Everything needed to talk to the chain after startup.
+#[derive(Clone)]
+struct Chain {
+    endpoints: Vec<String>,
+    account: chain_api::Keypair,
+    contract: ContractId,
+    identity: chain_api::NodeIdentity,
+    nonce: u64,
+    /// Nodes that learn others through lookups do not download the node list.
+    full_list: bool,
+}
+
+impl Chain {
+    async fn reclaim(&self) -> anyhow::Result<()> {
+        let client = connect_chain(&self.endpoints, &self.account).await?;
+        let nonce = client.get_nonce().await.context("fetching nonce")? + self.nonce;
+        client
+            .reclaim(self.contract.clone(), self.identity, nonce)
+            .await
+            .context("reclaiming stake")
+    }
+}
+
+type StakeEvents = futures::channel::mpsc::Receiver<ChainEvent>;
+/// Keys of nodes found by lookups, answered with [`ChainEvent::Confirmed`].
+type NodeChecks = futures::channel::mpsc::UnboundedSender<Vec<sign::Ed>>;
 
 struct Server {
     swarm: libp2p::swarm::Swarm<Behaviour>,
@@ -165,64 +315,270 @@ struct Server {
     internal: InternalServer,
     external: ExternalServer,
     stake_events: StakeEvents,
+    admin_commands: admin::Commands,
+    replications: admin::Replications,
+    draining: bool,
     res: TempRes,
+    admission: AdmissionPolicy,
+    params: NetworkParams,
+    /// Set when requests to other nodes should be signed.
+    rpc_signer: Option<sign::Keypair>,
+    require_signed_rpc: bool,
+    /// Hashes of the signing keys nodes registered on chain.
+    node_signs: HashMap<PeerId, crypto::Hash>,
+    /// Suspects past the grace period whose topics were already taken over.
+    dead_peers: HashSet<PeerId>,
+    node_checks: NodeChecks,
+    path_limits: RateLimiter<PathId>,
+    /// Lookups of peers outside of the white list, clients find their replicators this way.
+    lookup_limits: RateLimiter<PeerId>,
+    identity_limits: RateLimiter<Identity>,
+    metrics: Arc<Metrics>,
 }
 
 fn unpack_node_id(id: sign::Ed) -> anyhow::Result<ed25519::PublicKey> {
     libp2p::identity::ed25519::PublicKey::try_from_bytes(&id).context("deriving ed signature")
 }
 
-fn unpack_node_addr(addr: chain_api::NodeAddress) -> Multiaddr {
-    let (addr, port) = addr.into();
-    Multiaddr::empty()
-        .with(match addr {
-            IpAddr::V4(ip) => multiaddr::Protocol::Ip4(ip),
-            IpAddr::V6(ip) => multiaddr::Protocol::Ip6(ip),
-        })
-        .with(multiaddr::Protocol::Tcp(port))
+fn unpack_endpoint(endpoint: NodeEndpoint) -> Multiaddr {
+    let (addr, port) = endpoint.addr.into();
+    let addr = Multiaddr::empty().with(match addr {
+        IpAddr::V4(ip) => multiaddr::Protocol::Ip4(ip),
+        IpAddr::V6(ip) => multiaddr::Protocol::Ip6(ip),
+    });
+    match endpoint.transport {
+        NodeTransport::Tcp => addr.with(multiaddr::Protocol::Tcp(port)),
+        NodeTransport::Ws => {
+            addr.with(multiaddr::Protocol::Tcp(port)).with(multiaddr::Protocol::Ws("/".into()))
+        }
+        NodeTransport::Quic => {
+            addr.with(multiaddr::Protocol::Udp(port)).with(multiaddr::Protocol::QuicV1)
+        }
+    }
+}
+
+/// Boot node address ending with `/p2p/<peer id>` as a route.
+fn boot_route(addr: &Multiaddr) -> Option<Route> {
+    let mut addr = addr.clone();
+    let Some(multiaddr::Protocol::P2p(peer)) = addr.pop() else {
+        return None;
+    };
+    let pk = unpack_node_id(dht::try_peer_id_to_ed(peer)?).ok()?;
+    Some(Route::new(pk, addr))
+}
+
+/// Tcp is what other nodes dial, websocket is for clients.
+fn advertised_endpoints(exposed_address: IpAddr, port: u16, ws_port: u16) -> Vec<NodeEndpoint> {
+    vec![
+        NodeEndpoint { transport: NodeTransport::Tcp, addr: (exposed_address, port).into() },
+        NodeEndpoint { transport: NodeTransport::Ws, addr: (exposed_address, ws_port).into() },
+    ]
 }
 
 async fn deal_with_chain(
     config: ChainConfig,
     keys: &NodeKeys,
     is_new: bool,
-) -> anyhow::Result<(Vec<(NodeData, NodeAddress)>, StakeEvents)> {
-    let ChainConfig { chain_node, node_account, node_contract, port, exposed_address, nonce } =
-        config;
-    let (mut chain_events_tx, stake_events) = futures::channel::mpsc::channel(0);
+    full_list: bool,
+) -> anyhow::Result<(
+    Vec<(NodeData, Vec<NodeEndpoint>, u32)>,
+    NetworkParams,
+    StakeEvents,
+    NodeChecks,
+    Chain,
+)> {
+    let ChainConfig {
+        chain_nodes,
+        node_account,
+        node_contract,
+        port,
+        ws_port,
+        exposed_address,
+        nonce,
+        ..
+    } = config;
+    let (chain_events_tx, stake_events) = futures::channel::mpsc::channel(0);
     let account = if node_account.starts_with("//") {
         chain_api::dev_keypair(&node_account)
     } else {
         chain_api::mnemonic_keypair(&node_account)
     };
 
-    let client = chain_api::Client::with_signer(&chain_node, account)
-        .await
-        .context("connecting to chain")?;
-
-    let node_list = client.list(node_contract.clone()).await.context("fetching node list")?;
+    let client = connect_chain(&chain_nodes.0, &account).await.context("connecting to chain")?;
 
-    let stream = client.node_contract_event_stream(node_contract.clone()).await?;
-    tokio::spawn(async move {
-        let mut stream = std::pin::pin!(stream);
-        // TODO: properly recover fro errors, add node pool to try
-        while let Some(event) = stream.next().await {
-            _ = chain_events_tx.send(event).await;
-        }
-    });
+    let node_list = if full_list {
+        client.list(node_contract.clone()).await.context("fetching node list")?
+    } else {
+        Vec::new()
+    };
+    let mut params: NetworkParams = client
+        .network_params(node_contract.clone())
+        .await
+        .context("fetching network params")?
+        .try_into()
+        .context("validating network params")?;
+    if params.virtual_nodes != 0 {
+        params.placement_beacon = client
+            .placement_beacon(node_contract.clone())
+            .await
+            .context("fetching placement beacon")?;
+    }
 
     if is_new {
         let nonce = client.get_nonce().await.context("fetching nonce")? + nonce;
+        let endpoints = advertised_endpoints(exposed_address, port, ws_port);
         client
-            .join(node_contract.clone(), keys.to_stored(), (exposed_address, port).into(), nonce)
+            .join(node_contract.clone(), keys.to_stored(), endpoints, nonce)
             .await
             .context("registeing to chain")?;
         log::info!("registered on chain");
     }
 
-    log::info!("entered the network with {} nodes", node_list.len());
+    log::info!("entered the network with {} nodes, {params:?}", node_list.len());
+
+    let NodeData { sign, enc, .. } = keys.to_stored();
+    let identity = chain_api::NodeIdentity { sign, enc };
+    let chain = Chain {
+        endpoints: chain_nodes.0,
+        account,
+        contract: node_contract,
+        identity,
+        nonce,
+        full_list,
+    };
+    let (node_checks, checks) = futures::channel::mpsc::unbounded();
+    if !full_list {
+        tokio::spawn(check_found_nodes(
+            chain.clone(),
+            client.clone(),
+            checks,
+            chain_events_tx.clone(),
+        ));
+    }
+    tokio::spawn(pump_chain_events(chain.clone(), client, chain_events_tx));
+
+    Ok((node_list, params, stake_events, node_checks, chain))
+}
+
+/// Looks the found nodes up one by one since we do not hold the node list to check against.
+async fn check_found_nodes(
+    chain: Chain,
+    mut client: ChainClient,
+    mut checks: futures::channel::mpsc::UnboundedReceiver<Vec<sign::Ed>>,
+    mut events: futures::channel::mpsc::Sender<ChainEvent>,
+) {
+    while let Some(ids) = checks.next().await {
+        let mut confirmed = Vec::with_capacity(ids.len());
+        for id in ids {
+            match client.node(chain.contract.clone(), id).await {
+                Ok(Some(node)) => confirmed.push(node),
+                Ok(None) => log::warn!("found node {id:?} is not staked"),
+                Err(e) => {
+                    log::warn!("failed to check a found node: {e}");
+                    match connect_chain(&chain.endpoints, &chain.account).await {
+                        Ok(new_client) => client = new_client,
+                        Err(e) => log::warn!("failed to reconnect to chain: {e:#}"),
+                    }
+                }
+            }
+        }
+
+        if events.send(ChainEvent::Confirmed(confirmed)).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn connect_chain(
+    endpoints: &[String],
+    account: &chain_api::Keypair,
+) -> anyhow::Result<ChainClient> {
+    let mut last_error = None;
+    for endpoint in endpoints {
+        match chain_api::Client::with_signer(endpoint, account.clone()).await {
+            Ok(client) => return Ok(client),
+            Err(e) => {
+                log::warn!("failed to connect to chain node {endpoint}: {e}");
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.map_or_else(|| anyhow::anyhow!("no chain nodes configured"), Into::into))
+}
+
+/// Keeps the server informed about stake changes, reconnecting with backoff when the
+/// connection drops. Since we can not be sure nothing was missed, fresh node list is sent
+/// after each reconnect.
+async fn pump_chain_events(
+    chain: Chain,
+    mut client: ChainClient,
+    mut events: futures::channel::mpsc::Sender<ChainEvent>,
+) {
+    let mut last_block = None;
+    let mut backoff = CHAIN_BACKOFF_START;
+    loop {
+        if let Err(e) =
+            forward_chain_events(&client, &chain, &mut last_block, &mut backoff, &mut events).await
+        {
+            log::warn!("chain event stream failed: {e:#}");
+        }
+
+        loop {
+            if events.is_closed() {
+                return;
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(CHAIN_BACKOFF_MAX);
+
+            let reconnected = async {
+                let new_client = connect_chain(&chain.endpoints, &chain.account).await?;
+                let node_list = if chain.full_list {
+                    Some(new_client.list(chain.contract.clone()).await?)
+                } else {
+                    None
+                };
+                anyhow::Ok((new_client, node_list))
+            };
+
+            match reconnected.await {
+                Ok((new_client, node_list)) => {
+                    log::info!("reconnected to chain");
+                    client = new_client;
+                    if let Some(node_list) = node_list {
+                        _ = events.send(ChainEvent::NodeList(node_list)).await;
+                    }
+                    break;
+                }
+                Err(e) => log::warn!("failed to reconnect to chain: {e:#}"),
+            }
+        }
+    }
+}
+
+async fn forward_chain_events(
+    client: &ChainClient,
+    chain: &Chain,
+    last_block: &mut Option<chain_api::BlockNumber>,
+    backoff: &mut Duration,
+    events: &mut futures::channel::mpsc::Sender<ChainEvent>,
+) -> anyhow::Result<()> {
+    let stream = client.node_contract_event_stream(chain.contract.clone(), *last_block).await?;
+    let mut stream = std::pin::pin!(stream);
+    while let Some(block) = stream.next().await {
+        let (number, block_events) = block?;
+        *backoff = CHAIN_BACKOFF_START;
+        for event in block_events {
+            match event {
+                Ok(event) => events.send(ChainEvent::Stake(event)).await?,
+                Err(e) => log::warn!("invalid stake event in block {number}: {e}"),
+            }
+        }
+        *last_block = Some(number);
+    }
 
-    Ok((node_list, stake_events))
+    anyhow::bail!("event stream ended")
 }
 
 fn filter_incoming(
@@ -242,14 +598,48 @@ fn filter_incoming(
     Ok(())
 }
 
+/// Identity behind the request proof, requests that do not prove it are only limited per path
+/// so that nobody can drain the bucket of someone else.
+fn request_identity(prefix: u8, body: &[u8]) -> Option<Identity> {
+    fn verified<'a, T: ToProofContext + Codec<'a>>(mut body: &'a [u8]) -> Option<Identity> {
+        let proof = Proof::<T>::decode(&mut body)?;
+        proof.verify().then(|| crypto::hash::from_raw(&proof.pk))
+    }
+
+    // the proof always goes first
+    match prefix {
+        CreateProfile::PREFIX => verified::<&[u8]>(body),
+        SetVault::PREFIX => verified::<Reminder>(body),
+        ReadMail::PREFIX => verified::<Mail>(body),
+        PerformChatAction::PREFIX => verified::<ChatName>(body),
+        _ => None,
+    }
+}
+
 impl Server {
     fn new(
         config: NodeConfig,
         keys: NodeKeys,
-        node_list: Vec<(NodeData, NodeAddress)>,
+        node_list: Vec<(NodeData, Vec<NodeEndpoint>, u32)>,
+        params: NetworkParams,
         stake_events: StakeEvents,
+        node_checks: NodeChecks,
+        admin_commands: admin::Commands,
     ) -> anyhow::Result<Self> {
-        let NodeConfig { port, ws_port, boot_nodes, idle_timeout, .. } = config;
+        let NodeConfig {
+            port,
+            ws_port,
+            boot_nodes,
+            idle_timeout,
+            profile_pow_difficulty,
+            chat_pow_difficulty,
+            path_rate_limit,
+            identity_rate_limit,
+            onion: OnionConfig { max_streams, keep_alive_interval },
+            rpc: RpcConfig { sign: sign_rpc, require_signed: require_signed_rpc },
+            dht: DhtConfig { ping_interval, suspect_grace, lookups },
+            ..
+        } = config;
 
         let local_key = libp2p::identity::Keypair::ed25519_from_bytes(keys.sign.pre_quantum())
             .context("deriving ed signature")?;
@@ -261,12 +651,24 @@ impl Server {
             onion: topology_wrapper::new(
                 onion::Behaviour::new(
                     onion::Config::new(keys.enc.into(), peer_id)
-                        .max_streams(10)
-                        .keep_alive_interval(Duration::from_secs(100)),
+                        .max_streams(max_streams)
+                        .keep_alive_interval(Duration::from_millis(keep_alive_interval)),
                 ),
                 sender.clone(),
             ),
-            dht: dht::Behaviour::new(filter_incoming),
+            dht: {
+                let dht = dht::Behaviour::new(filter_incoming)
+                    .with_transports(vec![dht::Transport::Tcp, dht::Transport::Ws]);
+                let dht = match ping_interval {
+                    0 => dht,
+                    ms => dht.with_pings(Duration::from_millis(ms)),
+                };
+                if lookups {
+                    dht.with_lookups(unpack_node_id(keys.to_stored().id)?)
+                } else {
+                    dht
+                }
+            },
             rpc: topology_wrapper::new(rpc::Behaviour::default(), sender.clone()),
             report: topology_wrapper::report::new(receiver),
         };
@@ -318,15 +720,32 @@ impl Server {
             )
             .context("starting to isten for clients")?;
 
+        let mut node_signs = HashMap::new();
         let node_data = node_list
             .into_iter()
-            .map(|(node, addr)| {
+            .map(|(node, endpoints, weight)| {
                 let pk = unpack_node_id(node.id)?;
-                let addr = unpack_node_addr(addr);
-                Ok(Route::new(pk, addr))
+                node_signs.insert(identity::PublicKey::from(pk.clone()).to_peer_id(), node.sign);
+                let addrs = endpoints.into_iter().map(unpack_endpoint);
+                Ok(Route::with_addrs(pk, addrs).with_weight(weight))
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
-        swarm.behaviour_mut().dht.table.bulk_insert(node_data);
+        let table = &mut swarm.behaviour_mut().dht.table;
+        table.suspect_grace = Duration::from_millis(suspect_grace);
+        table.set_placement(dht::Placement::new(params.virtual_nodes, params.placement_beacon));
+        table.bulk_insert(node_data);
+
+        let beh = swarm.behaviour_mut();
+        if let Some(lookups) = &mut beh.dht.lookups {
+            let boot_routes = boot_nodes.0.iter().filter_map(boot_route).collect::<Vec<_>>();
+            anyhow::ensure!(!boot_routes.is_empty(), "lookups need boot nodes with a peer id");
+            lookups.placement = beh.dht.table.placement();
+            lookups.buckets.suspect_grace = Duration::from_millis(suspect_grace);
+            // boot nodes come from the config, they are whitelisted once the chain agrees
+            let ids = boot_routes.iter().filter_map(|r| dht::try_peer_id_to_ed(r.peer_id()));
+            _ = node_checks.unbounded_send(ids.collect());
+            lookups.bootstrap(boot_routes, &mut beh.rpc);
+        }
 
         for boot_node in boot_nodes.0 {
             swarm.dial(boot_node).context("dialing a boot peer")?;
@@ -337,13 +756,61 @@ impl Server {
             clients: Default::default(),
             buffer: Default::default(),
             stake_events,
+            admin_commands,
+            replications: Default::default(),
+            draining: false,
             storage: Default::default(),
             internal: Default::default(),
             external: Default::default(),
             res: Default::default(),
+            admission: AdmissionPolicy {
+                profile_difficulty: profile_pow_difficulty,
+                chat_difficulty: chat_pow_difficulty,
+            },
+            params,
+            rpc_signer: sign_rpc.then_some(keys.sign),
+            require_signed_rpc,
+            node_signs,
+            dead_peers: Default::default(),
+            node_checks,
+            path_limits: RateLimiter::new(path_rate_limit),
+            lookup_limits: RateLimiter::new(path_rate_limit),
+            identity_limits: RateLimiter::new(identity_rate_limit),
+            metrics: Default::default(),
         })
     }
 
+    fn is_idle(&self) -> bool {
+        self.internal.is_idle() && self.external.is_idle() && self.swarm.behaviour().rpc.is_idle()
+    }
+
+    /// Stops accepting requests and keeps serving until in-flight requests finish or `deadline`
+    /// passes. Storage lives in memory so once we return the other replicators are the only
+    /// copy left, we tell them to hand our topics over whether the stake is reclaimed or not.
+    async fn shutdown(&mut self, deadline: Duration) {
+        self.draining = true;
+        let leaving = seal_rpc(self.rpc_signer.as_ref(), &[LEAVING_PREFIX]).into_owned();
+        let beh = self.swarm.behaviour_mut();
+        for peer in beh.dht.table.iter().map(Route::peer_id).collect::<Vec<_>>() {
+            if let Err(e) = beh.rpc.request(peer, leaving.as_slice()) {
+                log::warn!("failed to tell {peer} we are leaving: {e}");
+            }
+        }
+
+        let drain = futures::future::poll_fn(|cx| {
+            _ = std::pin::Pin::new(&mut *self).poll(cx);
+            if self.is_idle() {
+                std::task::Poll::Ready(())
+            } else {
+                std::task::Poll::Pending
+            }
+        });
+
+        if tokio::time::timeout(deadline, drain).await.is_err() {
+            log::warn!("shutdown deadline reached with requests still in flight");
+        }
+    }
+
     fn load_keys(path: &str) -> io::Result<(NodeKeys, bool)> {
         let file = match fs::read(path) {
             Ok(file) => file,
@@ -364,12 +831,58 @@ impl Server {
 
     fn handle_event(&mut self, event: SE) {
         match event {
-            SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::Request(peer, id, body))) => {
-                if self.swarm.behaviour_mut().dht.table.get(peer).is_none() {
+            SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::Request(peer, id, raw))) => {
+                let whitelisted = self.swarm.behaviour().dht.table.get(peer).is_some();
+
+                // the table only holds confirmed nodes, so clients are answered too, within
+                // a limit since they cost us nothing to make up
+                if let Some(target) = dht::lookup::parse_find_node(&raw) {
+                    if !whitelisted && !self.lookup_limits.try_acquire(peer) {
+                        log::warn!("peer {} is looking up nodes too often", peer);
+                        return;
+                    }
+                    let beh = self.swarm.behaviour_mut();
+                    if !beh.rpc.is_congested(peer) {
+                        let answer = dht::lookup::answer_find_node(&beh.dht.table, target);
+                        beh.rpc.respond(peer, id, answer);
+                    }
+                    return;
+                }
+
+                if !whitelisted {
                     log::warn!("peer {} made rpc request but is not on the white list", peer);
                     return;
                 }
 
+                let body = if raw.first() == Some(&SIGNED_RPC_PREFIX) {
+                    let Some(payload) = self.open_signed_rpc(peer, &raw[1..]) else {
+                        return;
+                    };
+                    payload
+                } else if self.require_signed_rpc {
+                    log::warn!("peer {} made unsigned rpc request", peer);
+                    return;
+                } else {
+                    raw.as_slice()
+                };
+
+                if body == [LEAVING_PREFIX] {
+                    self.swarm.behaviour_mut().rpc.respond(peer, id, Vec::new());
+                    self.handle_peer_leaving(peer);
+                    return;
+                }
+
+                if self.draining {
+                    log::info!("refusing rpc request from {}, shutting down", peer);
+                    return;
+                }
+
+                if self.swarm.behaviour().rpc.is_congested(peer) {
+                    log::warn!("shedding rpc request from {}, responses are backing up", peer);
+                    self.metrics.rpc_shed.inc();
+                    return;
+                }
+
                 let Some((&prefix, body)) = body.split_first() else {
                     log::info!("invalid rpc request");
                     return;
@@ -389,13 +902,42 @@ impl Server {
                     }
                 }
             }
+            SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::WriteDropped(peer, id))) => {
+                log::warn!("response {:?} to {} was dropped", id, peer);
+                self.metrics.rpc_dropped_writes.inc();
+            }
             SwarmEvent::Behaviour(BehaviourEvent::Onion(onion::Event::InboundStream(
                 inner,
                 id,
             ))) => {
+                if self.draining {
+                    log::info!("refusing client stream, shutting down");
+                    return;
+                }
+                self.metrics.onion_circuits_opened.inc();
                 self.clients.push(Stream::new(id, inner));
             }
             SwarmEvent::Behaviour(ev) => {
+                if let BehaviourEvent::Rpc(rpc::Event::Response(peer, call, res)) = &ev {
+                    let table = &mut self.swarm.behaviour_mut().dht.table;
+                    match res {
+                        Ok(_) => table.report_alive(*peer),
+                        Err(_) => {
+                            self.metrics.rpc_failures.inc();
+                            table.report_failure(*peer);
+                            self.check_dead_peer(*peer);
+                        }
+                    }
+
+                    let beh = self.swarm.behaviour_mut();
+                    if let Some(lookups) = &mut beh.dht.lookups {
+                        let response = res.as_ref().ok().map(|(r, _)| r.as_slice());
+                        if lookups.on_response(*peer, *call, response, &mut beh.rpc) {
+                            return self.learn_found_nodes();
+                        }
+                    }
+                }
+
                 self.buffer.clear();
                 let Ok((origin, id)) = Err(ev)
                     .or_else(|ev| {
@@ -423,10 +965,147 @@ impl Server {
                     }
                 }
             }
+            SwarmEvent::OutgoingConnectionError { peer_id: Some(peer), .. } => {
+                self.check_dead_peer(peer);
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                self.dead_peers.remove(&peer_id);
+            }
             e => log::debug!("{e:?}"),
         }
     }
 
+    /// Nodes found by finished lookups stay in the lookups where they can be dialed. Anyone
+    /// can answer a lookup, so they are whitelisted and replicate only once the chain confirms
+    /// their stake.
+    fn learn_found_nodes(&mut self) {
+        let dht = &mut self.swarm.behaviour_mut().dht;
+        let Some(lookups) = &mut dht.lookups else {
+            return;
+        };
+        let mut unknown = Vec::new();
+        while let Some((_, routes)) = lookups.take_finished() {
+            log::info!("lookup found {} nodes", routes.len());
+            let routes = routes.iter().map(Route::peer_id).filter(|&p| dht.table.get(p).is_none());
+            unknown.extend(routes.filter_map(dht::try_peer_id_to_ed));
+        }
+
+        if !unknown.is_empty() && self.node_checks.unbounded_send(unknown).is_err() {
+            log::warn!("chain stopped checking found nodes");
+        }
+    }
+
+    /// Returns the payload if it was signed by the key `peer` registered on chain.
+    fn open_signed_rpc<'a>(&self, peer: PeerId, mut bytes: &'a [u8]) -> Option<&'a [u8]> {
+        let Some(signed) = SignedRpc::decode(&mut bytes) else {
+            log::warn!("peer {} sent malformed signed rpc", peer);
+            return None;
+        };
+
+        let Some(signer) = signed.verify() else {
+            log::warn!("peer {} sent rpc with invalid signature", peer);
+            return None;
+        };
+
+        if self.node_signs.get(&peer) != Some(&signer) {
+            log::warn!("peer {} signed rpc with a key it did not register", peer);
+            return None;
+        }
+
+        Some(signed.payload.0)
+    }
+
+    fn reconcile_routes(&mut self, node_list: Vec<(NodeData, Vec<NodeEndpoint>, u32)>) {
+        self.node_signs.clear();
+        let routes = self.register_nodes(node_list);
+
+        let table = &mut self.swarm.behaviour_mut().dht.table;
+        table.retain(|r| routes.iter().any(|n| n.peer_id() == r.peer_id()));
+        table.insert_all(routes);
+        log::info!("routing table reconciled with the chain");
+    }
+
+    /// Remembers the signing keys of the nodes and returns their routes.
+    fn register_nodes(&mut self, nodes: Vec<(NodeData, Vec<NodeEndpoint>, u32)>) -> Vec<Route> {
+        nodes
+            .into_iter()
+            .filter_map(|(node, endpoints, weight)| {
+                let pk = unpack_node_id(node.id).ok()?;
+                let peer = identity::PublicKey::from(pk.clone()).to_peer_id();
+                self.node_signs.insert(peer, node.sign);
+                let addrs = endpoints.into_iter().map(unpack_endpoint);
+                Some(Route::with_addrs(pk, addrs).with_weight(weight))
+            })
+            .collect()
+    }
+
+    /// The node stays out of the routes until the chain lists it again. For every topic it
+    /// replicated, the first remaining replicator pushes our copy to the nodes that joined
+    /// the group in its place.
+    fn handle_peer_leaving(&mut self, peer: PeerId) {
+        log::info!("peer {} is leaving, taking over its topics", peer);
+        let groups = self.groups_of(peer);
+        self.dead_peers.remove(&peer);
+        self.node_signs.remove(&peer);
+        self.swarm.behaviour_mut().dht.table.remove(peer);
+        self.hand_over(groups);
+    }
+
+    /// Once a suspect stays unreachable past the grace period it drops out of its groups and
+    /// the next closest routes take its place, they get our copy the same way as when the
+    /// node leaves. Without pings, only failed requests notice this.
+    fn check_dead_peer(&mut self, peer: PeerId) {
+        let table = &self.swarm.behaviour().dht.table;
+        if table.get(peer).is_none() || table.is_alive(peer) || !self.dead_peers.insert(peer) {
+            return;
+        }
+
+        log::info!("peer {} is unreachable past the grace period, taking over its topics", peer);
+        let groups = self.groups_of(peer);
+        self.hand_over(groups);
+    }
+
+    /// Groups of the topics we store that `peer` belongs to, as if it was alive.
+    fn groups_of(&self, peer: PeerId) -> Vec<(PossibleTopic, Vec<PeerId>)> {
+        let table = &self.swarm.behaviour().dht.table;
+        (self.storage.profiles.keys().copied().map(PossibleTopic::Profile))
+            .chain(self.storage.chats.keys().copied().map(PossibleTopic::Chat))
+            .map(|topic| {
+                let group = table
+                    .closest(topic.as_bytes())
+                    .map(Route::peer_id)
+                    .filter(|&p| p == peer || table.is_alive(p))
+                    .take(self.params.replicator_count());
+                (topic, group.collect::<Vec<_>>())
+            })
+            .filter(|(_, group)| group.contains(&peer))
+            .collect()
+    }
+
+    /// For every group, the first member of its current form pushes our copy to the members
+    /// that were not in the `old_group`.
+    fn hand_over(&mut self, groups: Vec<(PossibleTopic, Vec<PeerId>)>) {
+        let us = *self.swarm.local_peer_id();
+        for (topic, old_group) in groups {
+            let table = &self.swarm.behaviour().dht.table;
+            let new_group = replicators_for(table, topic, &self.params).collect::<Vec<_>>();
+            if new_group.first() != Some(&us) {
+                continue;
+            }
+
+            let targets =
+                new_group.into_iter().filter(|p| !old_group.contains(p)).collect::<Vec<_>>();
+            if targets.is_empty() {
+                continue;
+            }
+
+            match self.replication_request(topic) {
+                Ok(request) => self.replicate(topic, request, targets, None),
+                Err(e) => log::warn!("failed to hand over {:?}: {e}", topic),
+            }
+        }
+    }
+
     fn handle_client_message(&mut self, id: PathId, req: io::Result<Vec<u8>>) {
         let req = match req {
             Ok(req) => req,
@@ -443,6 +1122,28 @@ impl Server {
 
         log::info!("received message from client: {:?} {:?}", req.id, req.prefix,);
 
+        let rejection = if self.draining {
+            Some(Rejection::ShuttingDown)
+        } else if !self.path_limits.try_acquire(id) {
+            Some(Rejection::PathRateLimited)
+        } else if let Some(identity) = request_identity(req.prefix, req.body.0)
+            && !self.identity_limits.try_acquire(identity)
+        {
+            Some(Rejection::IdentityRateLimited)
+        } else {
+            None
+        };
+
+        if let Some(rejection) = rejection {
+            log::info!("rejecting client request: {}", rejection);
+            let stream =
+                self.clients.iter_mut().find(|s| s.id == id).expect("we just received message");
+            if stream.inner.write((req.id, rejection)).is_none() {
+                log::info!("client cannot process the rejection");
+            }
+            return;
+        }
+
         let req = handlers::Request {
             prefix: req.prefix,
             id: req.id,
@@ -460,6 +1161,10 @@ impl Server {
                 if stream.inner.write((req.id, Reminder(&self.buffer))).is_none() {
                     log::info!("client cannot process the response");
                 }
+
+                if req.prefix == SubscribeProfile::PREFIX {
+                    self.replay_mail(id, req);
+                }
             }
             Err(e) => {
                 log::info!("failed to dispatch client request: {}", e);
@@ -467,12 +1172,37 @@ impl Server {
         }
     }
 
-    fn handle_stake_event(&mut self, event: Result<chain_api::StakeEvent, chain_api::Error>) {
+    /// Sends the mail the subscriber missed, after the subscription response so that the
+    /// client does not mistake it for one.
+    fn replay_mail(&mut self, path: PathId, req: handlers::Request<'_>) {
+        let Some((identity, from)) =
+            <SubscribeProfile as Protocol>::Request::decode(&mut &*req.body)
+        else {
+            return;
+        };
+        let Some(profile) = self.storage.profiles.get(&identity) else {
+            return;
+        };
+        let Some(stream) = self.clients.iter_mut().find(|s| s.id == path) else {
+            return;
+        };
+
+        for (seq, mail) in profile.mail_since(from) {
+            if stream.inner.write((req.id, (seq, Reminder(mail)))).is_none() {
+                log::info!("client cannot process the missed mail");
+                break;
+            }
+        }
+    }
+
+    fn handle_stake_event(&mut self, event: ChainEvent) {
         let event = match event {
-            Ok(event) => event,
-            Err(e) => {
-                log::info!("failed to read from chain: {}", e);
-                return;
+            ChainEvent::Stake(event) => event,
+            ChainEvent::NodeList(node_list) => return self.reconcile_routes(node_list),
+            ChainEvent::Confirmed(nodes) => {
+                log::info!("chain confirmed {} found nodes", nodes.len());
+                let routes = self.register_nodes(nodes);
+                return self.swarm.behaviour_mut().dht.table.insert_all(routes);
             }
         };
 
@@ -483,7 +1213,10 @@ impl Server {
                     return;
                 };
                 log::info!("node joined the network: {pk:?}");
-                let route = Route::new(pk, unpack_node_addr(j.addr));
+                let peer = identity::PublicKey::from(pk.clone()).to_peer_id();
+                self.node_signs.insert(peer, j.sign);
+                let route = Route::with_addrs(pk, j.endpoints.into_iter().map(unpack_endpoint))
+                    .with_weight(j.weight);
                 self.swarm.behaviour_mut().dht.table.insert(route);
             }
             chain_api::StakeEvent::Reclaimed(r) => {
@@ -492,11 +1225,9 @@ impl Server {
                     return;
                 };
                 log::info!("node left the network: {pk:?}");
-                self.swarm
-                    .behaviour_mut()
-                    .dht
-                    .table
-                    .remove(identity::PublicKey::from(pk).to_peer_id());
+                let peer = identity::PublicKey::from(pk).to_peer_id();
+                self.node_signs.remove(&peer);
+                self.swarm.behaviour_mut().dht.table.remove(peer);
             }
             chain_api::StakeEvent::AddrChanged(c) => {
                 let Ok(pk) = unpack_node_id(c.identity) else {
@@ -504,8 +1235,11 @@ impl Server {
                     return;
                 };
                 log::info!("node changed address: {pk:?}");
-                let route = Route::new(pk, unpack_node_addr(c.addr));
-                self.swarm.behaviour_mut().dht.table.insert(route);
+                let table = &mut self.swarm.behaviour_mut().dht.table;
+                let peer = identity::PublicKey::from(pk.clone()).to_peer_id();
+                let weight = table.route(peer).map_or(1, Route::weight);
+                let route = Route::with_addrs(pk, c.endpoints.into_iter().map(unpack_endpoint));
+                table.insert(route.with_weight(weight));
             }
         }
     }
@@ -530,6 +1264,22 @@ impl Future for Server {
             self.handle_stake_event(e);
         }
 
+        while let std::task::Poll::Ready(Some(c)) = self.admin_commands.poll_next_unpin(cx) {
+            self.handle_admin_command(c);
+        }
+
+        while let std::task::Poll::Ready(Some(r)) = self.replications.poll_next_unpin(cx) {
+            match r {
+                Ok(r) => self.push_replication(r),
+                Err(e) => log::error!("solving replication pow failed: {e}"),
+            }
+        }
+
+        self.metrics.onion_circuits.set(self.clients.len());
+        self.metrics.subscriptions.set(self.clients.iter().map(|c| c.subscriptions.len()).sum());
+        let beh = self.swarm.behaviour();
+        self.metrics.record_layers(beh.onion.stats(), beh.rpc.stats());
+
         std::task::Poll::Pending
     }
 }
@@ -544,9 +1294,18 @@ pub struct Context<'a> {
     clients: &'a mut SelectAll<Stream>,
     storage: &'a mut Storage,
     res: &'a mut TempRes,
+    admission: &'a AdmissionPolicy,
+    params: &'a NetworkParams,
+    metrics: &'a Metrics,
+    rpc_signer: Option<&'a sign::Keypair>,
 }
 
 impl Context<'_> {
+    /// Wraps the rpc request into [`SignedRpc`] when signing is enabled.
+    fn seal<'b>(&self, packet: &'b [u8]) -> Cow<'b, [u8]> {
+        seal_rpc(self.rpc_signer, packet)
+    }
+
     fn subscribe(&mut self, topic: PossibleTopic, id: CallId, origin: PathId) {
         let Some(stream) = self.clients.iter_mut().find(|s| s.id == origin) else {
             log::error!("whaaaat???");
@@ -555,12 +1314,19 @@ impl Context<'_> {
         stream.subscriptions.insert(topic, id);
     }
 
-    fn push(&mut self, topic: ChatName, event: <ChatName as Topic>::Event<'_>) {
-        handle_event(self.clients, PossibleTopic::Chat(topic), event);
+    fn push<T: Topic>(&mut self, topic: T, event: T::Event<'_>) {
+        handle_event(self.clients, topic.into(), event);
+    }
+
+    /// Drops the subscriptions of all clients, used once the topic no longer exists.
+    fn unsubscribe_all(&mut self, topic: PossibleTopic) {
+        for stream in self.clients.iter_mut() {
+            stream.subscriptions.remove(&topic);
+        }
     }
 
     fn is_valid_topic(&self, topic: PossibleTopic) -> bool {
-        replicators_for(&self.swarm.behaviour().dht.table, topic)
+        replicators_for(&self.swarm.behaviour().dht.table, topic, self.params)
             .any(|peer| peer == *self.swarm.local_peer_id())
     }
 
@@ -568,7 +1334,7 @@ impl Context<'_> {
         &self,
         topic: impl Into<PossibleTopic>,
     ) -> impl Iterator<Item = PeerId> + '_ {
-        replicators_for(&self.swarm.behaviour().dht.table, topic.into())
+        replicators_for(&self.swarm.behaviour().dht.table, topic.into(), self.params)
     }
 
     fn other_replicators_for(
@@ -579,45 +1345,36 @@ impl Context<'_> {
             &self.swarm.behaviour().dht.table,
             topic.into(),
             *self.swarm.local_peer_id(),
+            self.params,
         )
     }
 }
 
+fn seal_rpc<'a>(signer: Option<&sign::Keypair>, packet: &'a [u8]) -> Cow<'a, [u8]> {
+    match signer {
+        Some(keys) => (SIGNED_RPC_PREFIX, SignedRpc::new(keys, packet, OsRng)).to_bytes().into(),
+        None => packet.into(),
+    }
+}
+
+/// Group of the topic. Routes suspect past the grace period are skipped so the next closest
+/// routes take their place until they answer again.
 fn replicators_for(
     table: &dht::RoutingTable,
     topic: impl Into<PossibleTopic>,
+    params: &NetworkParams,
 ) -> impl Iterator<Item = PeerId> + '_ {
-    table.closest(topic.into().as_bytes()).take(REPLICATION_FACTOR.get() + 1).map(Route::peer_id)
+    let group = table.closest_alive(topic.into().as_bytes());
+    group.take(params.replicator_count()).map(Route::peer_id)
 }
 
 fn other_replicators_for(
     table: &dht::RoutingTable,
     topic: impl Into<PossibleTopic>,
     us: PeerId,
+    params: &NetworkParams,
 ) -> impl Iterator<Item = PeerId> + '_ {
-    replicators_for(table, topic).filter(move |&p| p != us)
-}
-
-fn push_notification(
-    streams: &mut SelectAll<Stream>,
-    topic: Identity,
-    event: <Identity as Topic>::Event<'_>,
-    recip: PathId,
-) -> bool {
-    let Some(stream) = streams.iter_mut().find(|s| s.id == recip) else {
-        return false;
-    };
-
-    let Some(&call_id) = stream.subscriptions.get(&PossibleTopic::Profile(topic)) else {
-        return false;
-    };
-
-    if stream.inner.write((call_id, event)).is_none() {
-        return false;
-    }
-
-    log::info!("pushed notification to client");
-    true
+    replicators_for(table, topic, params).filter(move |&p| p != us)
 }
 
 fn handle_event<'a>(streams: &mut SelectAll<Stream>, topic: PossibleTopic, event: impl Codec<'a>) {
@@ -741,6 +1498,8 @@ impl libp2p::futures::Stream for Stream {
 #[derive(Default)]
 pub struct Storage {
     profiles: HashMap<Identity, Profile>,
-    online: HashMap<Identity, RequestOrigin>,
     chats: HashMap<ChatName, Chat>,
+    /// deleted chats keep the name reserved for a while so that stale replicas and clients
+    /// can not resurrect them
+    tombstones: HashMap<ChatName, std::time::Instant>,
 }
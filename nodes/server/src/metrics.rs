@@ -0,0 +1,278 @@
+use {
+    std::{
+        collections::HashMap,
+        fmt::Write as _,
+        io,
+        net::Ipv4Addr,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+    tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    },
+};
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: usize) {
+        self.0.store(value as u64, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Shared between the server and the http task, everything is updated in place.
+#[derive(Default)]
+pub struct Metrics {
+    pub onion_circuits: Gauge,
+    pub onion_circuits_opened: Counter,
+    pub subscriptions: Gauge,
+    pub rpc_failures: Counter,
+    pub rpc_shed: Counter,
+    pub rpc_dropped_writes: Counter,
+    pub repl_no_majority: Counter,
+    pub retry_restores: Counter,
+    requests: Mutex<HashMap<(&'static str, &'static str), u64>>,
+    layers: Mutex<(onion::Stats, rpc::Stats)>,
+}
+
+impl Metrics {
+    pub fn record_request(&self, server: &'static str, handler: &'static str) {
+        *self.requests.lock().unwrap().entry((server, handler)).or_default() += 1;
+    }
+
+    pub fn record_layers(&self, onion: onion::Stats, rpc: rpc::Stats) {
+        *self.layers.lock().unwrap() = (onion, rpc);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let mut scalar = |name: &str, kind: &str, help: &str, value: u64| {
+            _ = writeln!(out, "# HELP orion_{name} {help}");
+            _ = writeln!(out, "# TYPE orion_{name} {kind}");
+            _ = writeln!(out, "orion_{name} {value}");
+        };
+
+        scalar(
+            "onion_circuits",
+            "gauge",
+            "client circuits currently open",
+            self.onion_circuits.get(),
+        );
+        scalar(
+            "onion_circuits_opened_total",
+            "counter",
+            "client circuits opened since start",
+            self.onion_circuits_opened.get(),
+        );
+        scalar(
+            "subscriptions",
+            "gauge",
+            "topic subscriptions across all clients",
+            self.subscriptions.get(),
+        );
+        scalar(
+            "rpc_failures_total",
+            "counter",
+            "rpc calls to other nodes that failed",
+            self.rpc_failures.get(),
+        );
+        scalar(
+            "rpc_shed_total",
+            "counter",
+            "rpc requests refused because responses to the peer were backing up",
+            self.rpc_shed.get(),
+        );
+        scalar(
+            "rpc_dropped_writes_total",
+            "counter",
+            "rpc responses dropped because the peer was not reading",
+            self.rpc_dropped_writes.get(),
+        );
+        scalar(
+            "repl_no_majority_total",
+            "counter",
+            "replicated requests that did not reach majority",
+            self.repl_no_majority.get(),
+        );
+        scalar(
+            "retry_restores_total",
+            "counter",
+            "topics restored from other replicators",
+            self.retry_restores.get(),
+        );
+
+        let (onion, rpc) = *self.layers.lock().unwrap();
+        scalar(
+            "onion_relayed_channels",
+            "gauge",
+            "channels relayed between other nodes",
+            onion.relayed_channels as u64,
+        );
+        scalar(
+            "onion_pending_streams",
+            "gauge",
+            "streams waiting for a connection to the next hop",
+            (onion.pending_incoming + onion.pending_outgoing) as u64,
+        );
+        scalar(
+            "onion_refused_streams_total",
+            "counter",
+            "streams refused because the node routes too many",
+            onion.refused_streams,
+        );
+        scalar("rpc_streams", "gauge", "rpc streams to other nodes", rpc.streams as u64);
+        scalar(
+            "rpc_in_flight",
+            "gauge",
+            "rpc requests waiting for a response",
+            rpc.in_flight as u64,
+        );
+        scalar(
+            "rpc_queued_requests",
+            "gauge",
+            "rpc requests waiting for a stream or an in-flight slot",
+            rpc.queued_requests as u64,
+        );
+        scalar(
+            "rpc_queued_responses",
+            "gauge",
+            "rpc responses and chunks waiting for the peer",
+            rpc.queued_responses as u64,
+        );
+        scalar("rpc_timeouts_total", "counter", "rpc attempts that timed out", rpc.timeouts);
+        scalar("rpc_retries_total", "counter", "rpc attempts made after a failure", rpc.retries);
+        scalar("rpc_hedges_total", "counter", "rpc attempts made to beat a slow peer", rpc.hedges);
+
+        _ = writeln!(out, "# HELP orion_requests_total requests dispatched to handlers");
+        _ = writeln!(out, "# TYPE orion_requests_total counter");
+        let requests = self.requests.lock().unwrap();
+        let mut requests = requests.iter().collect::<Vec<_>>();
+        requests.sort_unstable();
+        for ((server, handler), count) in requests {
+            let (server, handler) = (label(server), label(handler));
+            _ = writeln!(
+                out,
+                "orion_requests_total{{server=\"{server}\",handler=\"{handler}\"}} {count}"
+            );
+        }
+
+        out
+    }
+}
+
+/// Handler names come from `stringify!`, so they can contain spaces, paths and generics.
+/// Everything outside `[a-zA-Z0-9_]` collapses into a single `_`.
+fn label(raw: &str) -> String {
+    raw.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+pub async fn serve(metrics: Arc<Metrics>, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+    log::info!("serving metrics on port {port}");
+    serve_on(metrics, listener).await
+}
+
+async fn serve_on(metrics: Arc<Metrics>, listener: TcpListener) -> io::Result<()> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            // any request gets the metrics, we read only so that the client sees a clean close
+            let mut buf = [0; 1024];
+            _ = stream.read(&mut buf).await;
+
+            let body = metrics.render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n{body}",
+                body.len()
+            );
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                log::info!("failed to write metrics: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, tokio::net::TcpStream};
+
+    #[test]
+    fn labels_are_sanitized() {
+        assert_eq!(label("Repl < CreateProfile >"), "Repl_CreateProfile");
+        assert_eq!(label("handlers :: Retry<ReadMail>"), "handlers_Retry_ReadMail");
+        assert_eq!(label("plain_name"), "plain_name");
+    }
+
+    #[test]
+    fn render_contains_every_metric() {
+        let metrics = Metrics::default();
+        metrics.rpc_failures.inc();
+        metrics.subscriptions.set(3);
+        metrics.record_request("external", "Retry < ReadMail >");
+        metrics.record_request("external", "Retry < ReadMail >");
+        metrics.record_layers(
+            onion::Stats { relayed_channels: 2, ..Default::default() },
+            rpc::Stats { hedges: 4, ..Default::default() },
+        );
+
+        let out = metrics.render();
+        assert!(
+            out.contains("# TYPE orion_rpc_failures_total counter\norion_rpc_failures_total 1\n")
+        );
+        assert!(out.contains("orion_subscriptions 3\n"));
+        assert!(out.contains("orion_onion_relayed_channels 2\n"));
+        assert!(out.contains("orion_rpc_hedges_total 4\n"));
+        assert!(out
+            .contains("orion_requests_total{server=\"external\",handler=\"Retry_ReadMail\"} 2\n"));
+        assert!(out.lines().all(|l| l.starts_with('#') || l.split(' ').count() == 2));
+    }
+
+    #[tokio::test]
+    async fn serve_answers_with_rendered_metrics() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.retry_restores.inc();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_on(metrics.clone(), listener));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert_eq!(body, metrics.render());
+        assert!(body.contains("orion_retry_restores_total 1\n"));
+    }
+}
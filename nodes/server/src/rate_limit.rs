@@ -0,0 +1,87 @@
+use std::{collections::HashMap, hash::Hash, time::Instant};
+
+const INITIAL_PRUNE_THRESHOLD: usize = 1024;
+
+/// Token bucket per key, each key can burst up to `rate` requests and then gets `rate`
+/// requests per second.
+pub struct RateLimiter<K> {
+    rate: u32,
+    buckets: HashMap<K, Bucket>,
+    prune_threshold: usize,
+}
+
+struct Bucket {
+    tokens: f32,
+    last_refill: Instant,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    /// Rate of 0 disables the limit.
+    pub fn new(rate: u32) -> Self {
+        Self { rate, buckets: HashMap::new(), prune_threshold: INITIAL_PRUNE_THRESHOLD }
+    }
+
+    #[must_use = "the request should be rejected on false"]
+    pub fn try_acquire(&mut self, key: K) -> bool {
+        self.try_acquire_at(key, Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, key: K, now: Instant) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+
+        if self.buckets.len() > self.prune_threshold {
+            self.prune(now);
+        }
+
+        let rate = self.rate as f32;
+        let bucket = self.buckets.entry(key).or_insert(Bucket { tokens: rate, last_refill: now });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f32();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.last_refill = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Full buckets are indistinguishable from missing ones so we drop them.
+    fn prune(&mut self, now: Instant) {
+        let rate = self.rate as f32;
+        self.buckets.retain(|_, b| {
+            b.tokens + now.duration_since(b.last_refill).as_secs_f32() * rate < rate
+        });
+        self.prune_threshold = (self.buckets.len() * 2).max(INITIAL_PRUNE_THRESHOLD);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::time::Duration};
+
+    #[test]
+    fn buckets_refill_per_key() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2);
+
+        assert!(limiter.try_acquire_at(1, start));
+        assert!(limiter.try_acquire_at(1, start));
+        assert!(!limiter.try_acquire_at(1, start));
+        assert!(limiter.try_acquire_at(2, start));
+
+        assert!(!limiter.try_acquire_at(1, start + Duration::from_millis(400)));
+        assert!(limiter.try_acquire_at(1, start + Duration::from_millis(600)));
+        assert!(!limiter.try_acquire_at(1, start + Duration::from_millis(600)));
+    }
+
+    #[test]
+    fn zero_rate_disables_the_limit() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(0);
+        assert!((0..1000).all(|_| limiter.try_acquire_at((), start)));
+    }
+}
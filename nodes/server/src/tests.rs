@@ -9,7 +9,7 @@ use {
 
 #[tokio::test]
 async fn repopulate_account() {
-    let mut nodes = create_nodes(REPLICATION_FACTOR.get() + 1);
+    let mut nodes = create_nodes(test_params().replicator_count());
     let mut user = Account::new();
     let [mut stream, used] = Stream::new_test();
     nodes.iter_mut().next().unwrap().clients.push(used);
@@ -39,7 +39,7 @@ async fn repopulate_account() {
 
 #[tokio::test]
 async fn direct_messaging() {
-    let mut nodes = create_nodes(REPLICATION_FACTOR.get() + 1);
+    let mut nodes = create_nodes(test_params().replicator_count());
 
     let mut user = Account::new();
     let mut user2 = Account::new();
@@ -58,12 +58,12 @@ async fn direct_messaging() {
     stream2
         .test_req::<chat_spec::ReadMail>(
             &mut nodes,
-            user2.proof(chat_spec::Mail),
-            Ok(Reminder(&[0, 1, 1])),
+            (user2.proof(chat_spec::Mail), 0),
+            Ok((1, Reminder(&[0, 1, 1]))),
         )
         .await;
 
-    stream2.test_req::<chat_spec::Subscribe>(&mut nodes, user2.identity().into(), Ok(())).await;
+    stream2.test_req::<SubscribeProfile>(&mut nodes, (user2.identity(), 1), Ok(())).await;
 
     futures::future::select(
         nodes.next(),
@@ -72,27 +72,43 @@ async fn direct_messaging() {
     .await;
 
     stream1
-        .test_req::<chat_spec::SendMail>(
-            &mut nodes,
-            (user2.identity(), Reminder(&[2])),
-            Err(SendMailError::SentDirectly),
-        )
+        .test_req::<chat_spec::SendMail>(&mut nodes, (user2.identity(), Reminder(&[2])), Ok(()))
         .await;
 
-    stream2.expect_event(&mut nodes, Reminder(&[2])).await;
+    stream2.expect_event(&mut nodes, (1u64, Reminder(&[2]))).await;
 
     drop(stream2);
 
     stream1
         .test_req::<chat_spec::SendMail>(&mut nodes, (user2.identity(), Reminder(&[3])), Ok(()))
         .await;
+
+    let [mut stream3, used3] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used3);
+    stream3.test_req::<SubscribeProfile>(&mut nodes, (user2.identity(), 2), Ok(())).await;
+    stream3.expect_event(&mut nodes, (2u64, Reminder(&[3]))).await;
+
+    stream3
+        .test_req::<chat_spec::ReadMail>(
+            &mut nodes,
+            (user2.proof(chat_spec::Mail), 2),
+            Ok((3, Reminder(&[0, 1, 3]))),
+        )
+        .await;
+    stream3
+        .test_req::<chat_spec::ReadMail>(
+            &mut nodes,
+            (user2.proof(chat_spec::Mail), 3),
+            Ok((3, Reminder(&[]))),
+        )
+        .await;
 }
 
 #[tokio::test]
 async fn message_block_finalization() {
     _ = env_logger::builder().is_test(true).try_init();
 
-    let mut nodes = create_nodes(REPLICATION_FACTOR.get() + 1);
+    let mut nodes = create_nodes(test_params().replicator_count());
 
     let mut user = Account::new();
     let mut user2 = Account::new();
@@ -106,7 +122,7 @@ async fn message_block_finalization() {
 
     let chat = ChatName::from("foo").unwrap();
 
-    stream1.test_req::<CreateChat>(&mut nodes, (chat, user.identity()), Ok(())).await;
+    stream1.test_req::<CreateChat>(&mut nodes, (chat, user.identity(), 0), Ok(())).await;
     stream1
         .test_req::<PerformChatAction>(
             &mut nodes,
@@ -165,6 +181,428 @@ async fn message_block_finalization() {
         .await;
 }
 
+#[tokio::test]
+async fn ephemeral_chat_action() {
+    let mut nodes = create_nodes(test_params().replicator_count());
+
+    let mut user = Account::new();
+    let [mut stream, used] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used);
+    stream.create_user(&mut nodes, &mut user).await;
+
+    let chat = ChatName::from("foo").unwrap();
+    stream.test_req::<CreateChat>(&mut nodes, (chat, user.identity(), 0), Ok(())).await;
+
+    let [mut subscriber, used] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used);
+    subscriber.test_req::<Subscribe>(&mut nodes, PossibleTopic::Chat(chat), Ok(())).await;
+
+    let too_big = [0; handlers::MAX_EPHEMERAL_SIZE + 1];
+    stream
+        .test_req::<PerformChatAction>(
+            &mut nodes,
+            (user.proof(chat), ChatAction::Ephemeral(Reminder(&too_big))),
+            Err(ChatActionError::MessageTooLarge),
+        )
+        .await;
+
+    stream
+        .test_req::<PerformChatAction>(
+            &mut nodes,
+            (user.proof(chat), ChatAction::Ephemeral(Reminder(&[1, 2, 3]))),
+            Ok(()),
+        )
+        .await;
+
+    let event = subscriber.next_event(&mut nodes).await;
+    let Some(ChatEvent::Ephemeral(proof, Reminder(content))) =
+        ChatEvent::decode(&mut event.as_slice())
+    else {
+        panic!("expected ephemeral event");
+    };
+    assert!(proof.verify());
+    assert_eq!(content, [1, 2, 3]);
+
+    assert_nodes(&nodes, |s| s.storage.chats.get(&chat).is_some_and(|c| c.size() == 0));
+}
+
+#[tokio::test]
+async fn chat_admission() {
+    let difficulty = 8;
+    let mut nodes = create_nodes_with(test_params().replicator_count(), |c| {
+        c.chat_pow_difficulty = difficulty;
+    });
+
+    let mut user = Account::new();
+    let [mut stream, used] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used);
+    stream.create_user(&mut nodes, &mut user).await;
+
+    let chat = ChatName::from("foo").unwrap();
+    let topic = PossibleTopic::Chat(chat);
+    let lazy = (0..).find(|&n| pow_difficulty(topic, n) < difficulty).unwrap();
+
+    stream
+        .test_req::<CreateChat>(
+            &mut nodes,
+            (chat, user.identity(), lazy),
+            Err(CreateChatError::InsufficientWork(difficulty)),
+        )
+        .await;
+
+    let pow = solve_pow(topic, difficulty);
+    stream.test_req::<CreateChat>(&mut nodes, (chat, user.identity(), pow), Ok(())).await;
+
+    assert_nodes(&nodes, |s| s.storage.chats.contains_key(&chat));
+}
+
+#[tokio::test]
+async fn rate_limit() {
+    let mut nodes = create_nodes_with(test_params().replicator_count(), |c| {
+        c.path_rate_limit = 1;
+    });
+
+    let mut user = Account::new();
+    let [mut stream, used] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used);
+    stream.create_user(&mut nodes, &mut user).await;
+
+    let chat = ChatName::from("foo").unwrap();
+    stream
+        .inner
+        .write((CreateChat::PREFIX, CallId::whatever(), (chat, user.identity(), 0)))
+        .unwrap();
+    stream.expect_event(&mut nodes, Rejection::PathRateLimited).await;
+
+    assert_nodes(&nodes, |s| !s.storage.chats.contains_key(&chat));
+}
+
+#[tokio::test]
+async fn identity_rate_limit() {
+    let mut nodes = create_nodes_with(test_params().replicator_count(), |c| {
+        c.identity_rate_limit = 1;
+    });
+
+    // created through a different node so that the bucket we test is still full
+    let mut user = Account::new();
+    let [mut creator, used] = Stream::new_test();
+    nodes.iter_mut().last().unwrap().clients.push(used);
+    creator.create_user(&mut nodes, &mut user).await;
+
+    let [mut stream, used] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used);
+    let [mut attacker, used] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used);
+
+    for _ in 0..3 {
+        let mut forged = user.proof(Mail);
+        forged.signature[0] ^= 1;
+        attacker
+            .test_req::<ReadMail>(&mut nodes, (forged, 0), Err(ReadMailError::InvalidProof))
+            .await;
+    }
+
+    stream.test_req::<ReadMail>(&mut nodes, (user.proof(Mail), 0), Ok((0, Reminder(&[])))).await;
+
+    stream.inner.write((ReadMail::PREFIX, CallId::whatever(), (user.proof(Mail), 0))).unwrap();
+    stream.expect_event(&mut nodes, Rejection::IdentityRateLimited).await;
+}
+
+#[tokio::test]
+async fn signed_rpc() {
+    let mut nodes = create_nodes_with(test_params().replicator_count(), |c| {
+        c.rpc = RpcConfig { sign: true, require_signed: true };
+    });
+
+    let mut user = Account::new();
+    let [mut stream, used] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used);
+    stream.create_user(&mut nodes, &mut user).await;
+
+    assert_nodes(&nodes, |node| node.storage.profiles.contains_key(&user.identity()));
+}
+
+#[tokio::test]
+async fn chat_ownership() {
+    let mut nodes = create_nodes(test_params().replicator_count());
+
+    let mut user = Account::new();
+    let mut user2 = Account::new();
+    let [mut stream, used] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used);
+    stream.create_user(&mut nodes, &mut user).await;
+    stream.create_user(&mut nodes, &mut user2).await;
+
+    let chat = ChatName::from("foo").unwrap();
+    stream.test_req::<CreateChat>(&mut nodes, (chat, user.identity(), 0), Ok(())).await;
+
+    stream
+        .test_req::<PerformChatAction>(
+            &mut nodes,
+            (user.proof(chat), ChatAction::TransferOwnership(user2.identity())),
+            Err(ChatActionError::UserNotMember),
+        )
+        .await;
+
+    stream
+        .test_req::<PerformChatAction>(
+            &mut nodes,
+            (user.proof(chat), ChatAction::AddUser(user2.identity())),
+            Ok(()),
+        )
+        .await;
+
+    stream
+        .test_req::<PerformChatAction>(
+            &mut nodes,
+            (user2.proof(chat), ChatAction::DeleteChat),
+            Err(ChatActionError::NotOwner),
+        )
+        .await;
+
+    stream
+        .test_req::<PerformChatAction>(
+            &mut nodes,
+            (user.proof(chat), ChatAction::TransferOwnership(user2.identity())),
+            Ok(()),
+        )
+        .await;
+
+    let [mut subscriber, used] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used);
+    subscriber.test_req::<Subscribe>(&mut nodes, PossibleTopic::Chat(chat), Ok(())).await;
+
+    stream
+        .test_req::<PerformChatAction>(
+            &mut nodes,
+            (user2.proof(chat), ChatAction::DeleteChat),
+            Ok(()),
+        )
+        .await;
+
+    let event = subscriber.next_event(&mut nodes).await;
+    let Some(ChatEvent::Deleted(proof)) = ChatEvent::decode(&mut event.as_slice()) else {
+        panic!("expected chat deletion");
+    };
+    assert!(proof.verify());
+    assert_eq!(proof.context, chat);
+    assert_nodes(&nodes, |s| !s.storage.chats.contains_key(&chat));
+    assert!(nodes.iter().all(|s| s.clients.iter().all(|c| c.subscriptions.is_empty())));
+
+    stream
+        .test_req::<CreateChat>(
+            &mut nodes,
+            (chat, user.identity(), 0),
+            Err(CreateChatError::RecentlyDeleted),
+        )
+        .await;
+}
+
+#[tokio::test]
+async fn admin_commands() {
+    let mut nodes = create_nodes(test_params().replicator_count());
+
+    let mut user = Account::new();
+    let [mut stream, used] = Stream::new_test();
+    nodes.iter_mut().next().unwrap().clients.push(used);
+    stream.create_user(&mut nodes, &mut user).await;
+    let chat = ChatName::from("foo").unwrap();
+    stream.test_req::<CreateChat>(&mut nodes, (chat, user.identity(), 0), Ok(())).await;
+
+    let profile = format!("profile:{}", util::Hex(&user.identity()));
+    let target = nodes.iter_mut().next().unwrap();
+    let listing = admin_command(target, "profiles").await.unwrap();
+    assert!(listing.starts_with(&util::Hex(&user.identity()).to_string()));
+    assert!(admin_command(target, "chats").await.unwrap().starts_with("foo owner="));
+    assert!(admin_command(target, "help").await.unwrap().contains("replicate <topic>"));
+    let unknown = admin_command(target, "frobnicate").await.unwrap();
+    assert!(unknown.starts_with("error: unknown command"));
+    assert!(admin_command(target, "evict").await.unwrap().starts_with("error: missing topic"));
+    assert_eq!(admin_command(target, &format!("evict {profile}")).await.unwrap(), "evicted\n");
+    assert_eq!(admin_command(target, "evict chat:foo").await.unwrap(), "evicted\n");
+    let missing = admin_command(target, "evict chat:foo").await.unwrap();
+    assert!(missing.starts_with("error: topic not found"));
+
+    for topic in [profile.as_str(), "chat:foo"] {
+        let source = nodes.iter_mut().last().unwrap();
+        let mut reply = admin_command(source, &format!("replicate {topic}"));
+        let reply = futures::select! {
+            _ = nodes.select_next_some() => unreachable!(),
+            reply = reply => reply.unwrap(),
+        };
+        assert_eq!(reply.lines().count(), test_params().replicator_count() - 1, "{reply}");
+        assert!(reply.lines().all(|l| l.starts_with("sent to")), "{reply}");
+    }
+
+    futures::future::select(
+        nodes.next(),
+        std::pin::pin!(tokio::time::sleep(Duration::from_millis(300))),
+    )
+    .await;
+
+    let target = nodes.iter_mut().next().unwrap();
+    assert!(target.storage.profiles.contains_key(&user.identity()));
+    assert!(target.storage.chats.get(&chat).is_some_and(|c| c.owner() == user.identity()));
+}
+
+#[tokio::test]
+async fn draining_hands_topics_over() {
+    let mut nodes = create_nodes(test_params().replicator_count() + 1);
+
+    let mut user = Account::new();
+    let node = nodes.iter().next().unwrap();
+    let group = replicators_for(&node.swarm.behaviour().dht.table, user.identity(), &node.params)
+        .collect::<Vec<_>>();
+    let [mut stream, used] = Stream::new_test();
+    let entry = nodes.iter_mut().find(|n| *n.swarm.local_peer_id() == group[0]).unwrap();
+    entry.clients.push(used);
+    stream.create_user(&mut nodes, &mut user).await;
+
+    let mut nodes = nodes.into_iter().collect::<Vec<_>>();
+    let last = *group.last().unwrap();
+    let leaving = nodes.iter().position(|n| *n.swarm.local_peer_id() == last).unwrap();
+    let mut leaving = nodes.swap_remove(leaving);
+    let mut nodes = nodes.into_iter().collect::<FuturesUnordered<_>>();
+    let spare = nodes.iter().find(|n| !group.contains(n.swarm.local_peer_id())).unwrap();
+    assert!(!spare.storage.profiles.contains_key(&user.identity()));
+
+    futures::future::select(std::pin::pin!(leaving.shutdown(Duration::from_secs(1))), nodes.next())
+        .await;
+    futures::future::select(
+        nodes.next(),
+        std::pin::pin!(tokio::time::sleep(Duration::from_millis(300))),
+    )
+    .await;
+
+    assert!(nodes.iter().all(|n| n.storage.profiles.contains_key(&user.identity())));
+    assert!(nodes.iter().all(|n| n.swarm.behaviour().dht.table.get(last).is_none()));
+
+    let [mut late, used] = Stream::new_test();
+    leaving.clients.push(used);
+    let mut left = FuturesUnordered::from_iter([leaving]);
+    late.inner.write((FetchProfile::PREFIX, CallId::whatever(), user.identity())).unwrap();
+    late.expect_event(&mut left, Rejection::ShuttingDown).await;
+}
+
+#[tokio::test]
+async fn unreachable_replicator_is_taken_over() {
+    let mut nodes = create_nodes_with(test_params().replicator_count() + 1, |config| {
+        config.dht.ping_interval = 50;
+        config.dht.suspect_grace = 200;
+    });
+
+    let mut user = Account::new();
+    let node = nodes.iter().next().unwrap();
+    let group = replicators_for(&node.swarm.behaviour().dht.table, user.identity(), &node.params)
+        .collect::<Vec<_>>();
+    let [mut stream, used] = Stream::new_test();
+    let entry = nodes.iter_mut().find(|n| *n.swarm.local_peer_id() == group[0]).unwrap();
+    entry.clients.push(used);
+    stream.create_user(&mut nodes, &mut user).await;
+
+    let mut nodes = nodes.into_iter().collect::<Vec<_>>();
+    let last = *group.last().unwrap();
+    let dead = nodes.iter().position(|n| *n.swarm.local_peer_id() == last).unwrap();
+    drop(nodes.swap_remove(dead));
+    let mut nodes = nodes.into_iter().collect::<FuturesUnordered<_>>();
+    // as if a request to it failed, pings keep failing from here on
+    for node in nodes.iter_mut() {
+        node.swarm.behaviour_mut().dht.table.report_failure(last);
+    }
+    let spare = nodes.iter().find(|n| !group.contains(n.swarm.local_peer_id())).unwrap();
+    assert!(!spare.storage.profiles.contains_key(&user.identity()));
+
+    futures::future::select(
+        nodes.next(),
+        std::pin::pin!(tokio::time::sleep(Duration::from_millis(600))),
+    )
+    .await;
+
+    assert!(nodes.iter().all(|n| n.storage.profiles.contains_key(&user.identity())));
+    assert!(nodes.iter().all(|n| {
+        let table = &n.swarm.behaviour().dht.table;
+        !replicators_for(table, user.identity(), &n.params).any(|p| p == last)
+    }));
+}
+
+#[tokio::test]
+async fn found_nodes_join_the_table_once_confirmed() {
+    let node_data = (0..4).map(|_| (next_node_config(), NodeKeys::default())).collect::<Vec<_>>();
+    let localhost = IpAddr::from(Ipv4Addr::LOCALHOST);
+    let registered = node_data
+        .iter()
+        .map(|(config, keys)| {
+            (keys.to_stored(), advertised_endpoints(localhost, config.port, config.ws_port), 1)
+        })
+        .collect::<Vec<(NodeData, Vec<NodeEndpoint>, u32)>>();
+
+    let (boot_config, boot_keys) = &node_data[0];
+    let boot_peer =
+        identity::PublicKey::from(unpack_node_id(boot_keys.to_stored().id).unwrap()).to_peer_id();
+    let boot_node = unpack_endpoint(advertised_endpoints(localhost, boot_config.port, 0)[0])
+        .with(multiaddr::Protocol::P2p(boot_peer));
+
+    let (mut chain, events) = mpsc::channel(1);
+    let (node_checks, mut checks) = mpsc::unbounded();
+    let mut events = Some(events);
+    let mut node_checks = Some(node_checks);
+    let mut nodes = node_data
+        .into_iter()
+        .enumerate()
+        .map(|(i, (mut config, keys))| {
+            let (_, admin) = mpsc::channel(1);
+            let (list, rx, checks) = if i == 3 {
+                config.dht.lookups = true;
+                config.boot_nodes = config::List(vec![boot_node.clone()]);
+                (Vec::new(), events.take().unwrap(), node_checks.take().unwrap())
+            } else {
+                (registered.clone(), mpsc::channel(1).1, mpsc::unbounded().0)
+            };
+            Server::new(config, keys, list, test_params(), rx, checks, admin).unwrap()
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    futures::future::select(
+        nodes.next(),
+        std::pin::pin!(tokio::time::sleep(Duration::from_millis(500))),
+    )
+    .await;
+
+    let seeker = nodes.iter().find(|n| n.swarm.behaviour().dht.lookups.is_some()).unwrap();
+    let others = nodes
+        .iter()
+        .map(|n| *n.swarm.local_peer_id())
+        .filter(|p| p != seeker.swarm.local_peer_id());
+    let others = others.collect::<Vec<_>>();
+    let dht = &seeker.swarm.behaviour().dht;
+    assert!(others.iter().all(|&p| dht.table.get(p).is_none()));
+    assert!(others.iter().all(|&p| dht.lookups.as_ref().unwrap().route(p).is_some()));
+
+    let mut checked = Vec::new();
+    while let Ok(Some(ids)) = checks.try_next() {
+        checked.extend(ids);
+    }
+    let confirmed = registered[..3].to_vec();
+    assert!(confirmed.iter().all(|(data, ..)| checked.contains(&data.id)));
+
+    chain.send(ChainEvent::Confirmed(confirmed)).await.unwrap();
+    futures::future::select(
+        nodes.next(),
+        std::pin::pin!(tokio::time::sleep(Duration::from_millis(100))),
+    )
+    .await;
+
+    let seeker = nodes.iter().find(|n| n.swarm.behaviour().dht.lookups.is_some()).unwrap();
+    let table = &seeker.swarm.behaviour().dht.table;
+    assert!(others.iter().all(|&p| table.get(p).is_some()));
+}
+
+fn admin_command(node: &mut Server, line: &str) -> futures::channel::oneshot::Receiver<String> {
+    let (reply, rx) = futures::channel::oneshot::channel();
+    node.handle_admin_command(admin::Command { line: line.into(), reply });
+    rx
+}
+
 impl Stream {
     async fn test_req<P: Protocol>(
         &mut self,
@@ -182,12 +620,28 @@ impl Stream {
     async fn create_user(&mut self, nodes: &mut FuturesUnordered<Server>, user: &mut Account) {
         self.test_req::<CreateProfile>(
             nodes,
-            (user.proof(&[]), user.enc.public_key().into_bytes()),
+            (user.proof(&[]), user.enc.public_key().into_bytes(), 0),
             Ok(()),
         )
         .await;
     }
 
+    /// Body of the next subscription event, without the call id.
+    async fn next_event(&mut self, nodes: &mut FuturesUnordered<Server>) -> Vec<u8> {
+        futures::select! {
+            _ = nodes.select_next_some() => unreachable!(),
+            res = self.next().fuse() => {
+                let res = res.unwrap().1.unwrap();
+                let mut body = res.as_slice();
+                CallId::decode(&mut body).unwrap();
+                body.to_vec()
+            }
+            _ = tokio::time::sleep(Duration::from_millis(1000)).fuse() => {
+                panic!("timeout")
+            }
+        }
+    }
+
     async fn expect_event<'a, T: Codec<'a> + PartialEq + Debug>(
         &mut self,
         nodes: &mut FuturesUnordered<Server>,
@@ -234,7 +688,7 @@ async fn response<P: Protocol>(
 
 #[track_caller]
 fn assert_nodes(nodes: &FuturesUnordered<Server>, mut predicate: impl FnMut(&Server) -> bool) {
-    assert!(nodes.iter().filter(|e| predicate(e)).count() > REPLICATION_FACTOR.get() / 2);
+    assert!(nodes.iter().filter(|e| predicate(e)).count() > test_params().majority());
 }
 
 struct Account {
@@ -261,6 +715,10 @@ impl Account {
     }
 }
 
+fn test_params() -> NetworkParams {
+    NetworkParams { block_size: 1024 * 4, ..Default::default() }
+}
+
 fn next_node_config() -> NodeConfig {
     static PORT: std::sync::atomic::AtomicU16 = std::sync::atomic::AtomicU16::new(0);
     let port = PORT.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
@@ -271,25 +729,51 @@ fn next_node_config() -> NodeConfig {
         key_path: Default::default(),
         boot_nodes: config::List::default(),
         idle_timeout: 1000,
+        profile_pow_difficulty: 0,
+        chat_pow_difficulty: 0,
+        path_rate_limit: 0,
+        identity_rate_limit: 0,
+        metrics_port: 0,
+        admin_socket: String::new(),
+        shutdown_timeout: 0,
+        onion: OnionConfig { max_streams: 10, keep_alive_interval: 100_000 },
+        rpc: RpcConfig { sign: false, require_signed: false },
+        dht: DhtConfig { ping_interval: 0, suspect_grace: 30_000, lookups: false },
     }
 }
 
 fn create_nodes(count: usize) -> FuturesUnordered<Server> {
-    let node_data =
-        (0..count).map(|_| (next_node_config(), NodeKeys::default())).collect::<Vec<_>>();
+    create_nodes_with(count, |_| {})
+}
+
+fn create_nodes_with(
+    count: usize,
+    mut modify_config: impl FnMut(&mut NodeConfig),
+) -> FuturesUnordered<Server> {
+    let node_data = (0..count)
+        .map(|_| {
+            let mut config = next_node_config();
+            modify_config(&mut config);
+            (config, NodeKeys::default())
+        })
+        .collect::<Vec<_>>();
 
     let nodes = node_data
         .iter()
         .map(|(config, keys)| {
-            (keys.to_stored(), (IpAddr::from(Ipv4Addr::LOCALHOST), config.port).into())
+            let localhost = IpAddr::from(Ipv4Addr::LOCALHOST);
+            let endpoints = advertised_endpoints(localhost, config.port, config.ws_port);
+            (keys.to_stored(), endpoints, 1)
         })
-        .collect::<Vec<(NodeData, NodeAddress)>>();
+        .collect::<Vec<(NodeData, Vec<NodeEndpoint>, u32)>>();
 
     node_data
         .into_iter()
         .map(|(config, keys)| {
             let (_, rx) = mpsc::channel(1);
-            Server::new(config, keys, nodes.clone(), rx).unwrap()
+            let (checks, _) = mpsc::unbounded();
+            let (_, admin) = mpsc::channel(1);
+            Server::new(config, keys, nodes.clone(), test_params(), rx, checks, admin).unwrap()
         })
         .collect()
 }
@@ -1,3 +1,420 @@
-fn main() {
-    println!("Hello, world!");
+use {
+    self::store::Store,
+    anyhow::Context,
+    component_utils::{futures::StreamExt, Codec, Protocol, Reminder},
+    libp2p::{
+        identity::ed25519,
+        swarm::{NetworkBehaviour, SwarmEvent},
+        Multiaddr, PeerId, StreamProtocol,
+    },
+    std::{
+        io,
+        ops::Range,
+        sync::Arc,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    storage_spec::{
+        metabase::BlockId,
+        protocol::{
+            self, FetchPieces, FetchPiecesError, FreePieces, FreePiecesError, PieceIndex,
+            Rejection, ReportCapacity, ReportCapacityError, StorePieces, StorePiecesError,
+            MAX_TRANSFER_PIECES,
+        },
+        ticket::{AllocationTicket, TicketError, TicketKind},
+    },
+};
+
+mod store;
+
+config::env_config! {
+    struct Config {
+        port: u16 = "8090",
+        identity_ed: config::Hex = "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+        /// Directory with the block fragments and their metadata, kept between restarts.
+        storage_dir: String = "storage",
+        /// Number of block fragments the node is willing to hold.
+        capacity: u32 = "128",
+        /// Ed25519 public key of the satellite allocating our space.
+        satelite_identity: config::Hex,
+        /// Hash of the key the satellite signs tickets with, it logs it on start.
+        satelite_sign: config::Hex,
+        satelite_addr: Multiaddr = "/ip4/127.0.0.1/udp/8080/quic-v1",
+        /// Seconds between capacity reports to the satellite.
+        report_interval: u64 = "60",
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let config = Config::load()?;
+    let store = Store::open(config.storage_dir.clone().into(), config.capacity)
+        .context("opening the storage")?;
+    let mut node = Node::new(config, store)?;
+    node.run().await
+}
+
+pub struct Node {
+    swarm: libp2p::Swarm<Behaviour>,
+    store: Store,
+    satelite: PeerId,
+    satelite_sign: crypto::Hash,
+    report_interval: tokio::time::Interval,
+    pending_report: Option<rpc::CallId>,
+    /// Streamed fetches with pieces left to send.
+    fetches: Vec<(PeerId, rpc::CallId, BlockId, Range<PieceIndex>)>,
+    buffer: Vec<u8>,
+}
+
+impl Node {
+    fn new(config: Config, store: Store) -> anyhow::Result<Self> {
+        let identity: libp2p::identity::Keypair =
+            ed25519::Keypair::try_from_bytes(&mut config.identity_ed.to_bytes())
+                .context("invalid identity")?
+                .into();
+        let satelite = ed25519::PublicKey::try_from_bytes(&config.satelite_identity.to_bytes())
+            .context("invalid satellite identity")?;
+
+        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(identity)
+            .with_tokio()
+            .with_quic()
+            .with_behaviour(|_| Behaviour {
+                dht: dht::Behaviour::default().with_transports(vec![dht::Transport::Quic]),
+                rpc: rpc::Behaviour::new(
+                    rpc::Config::new().protocol(StreamProtocol::new(protocol::PROTOCOL_NAME)),
+                ),
+                streaming: streaming::Behaviour::default(),
+            })?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+
+        swarm.listen_on(
+            Multiaddr::empty()
+                .with(libp2p::multiaddr::Protocol::Ip4([0; 4].into()))
+                .with(libp2p::multiaddr::Protocol::Udp(config.port))
+                .with(libp2p::multiaddr::Protocol::QuicV1),
+        )?;
+
+        let route = dht::Route::new(satelite, config.satelite_addr);
+        let satelite = route.peer_id();
+        swarm.behaviour_mut().dht.table.insert(route);
+
+        log::info!(
+            "storing for {satelite}, holding {} blocks, {} free",
+            store.block_count(),
+            store.free_blocks()
+        );
+
+        Ok(Self {
+            swarm,
+            store,
+            satelite,
+            satelite_sign: config.satelite_sign.to_bytes(),
+            report_interval: tokio::time::interval(Duration::from_secs(config.report_interval)),
+            pending_report: None,
+            fetches: vec![],
+            buffer: vec![],
+        })
+    }
+
+    async fn run(&mut self) -> ! {
+        loop {
+            tokio::select! {
+                e = self.swarm.select_next_some() => self.swarm_event(e),
+                _ = self.report_interval.tick() => self.report_capacity(),
+            }
+        }
+    }
+
+    fn swarm_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::Request(peer, call, body))) => {
+                let Some((&prefix, body)) = body.split_first() else {
+                    log::info!("empty rpc request from {peer}");
+                    return;
+                };
+                if let Err(e) = self.handle_request(peer, call, prefix, body) {
+                    log::warn!("failed to handle request {prefix} from {peer}: {e:#}");
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::Response(_, call, res)))
+                if self.pending_report == Some(call) =>
+            {
+                self.pending_report = None;
+                if let Err(e) = self.handle_report(res.map(|(body, _)| body)) {
+                    log::warn!("capacity report failed: {e:#}");
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::ChunksWanted(peer, call))) => {
+                let rpc = &self.swarm.behaviour().rpc;
+                self.fetches.retain(|&(p, c, ..)| rpc.queued_chunks(p, c).is_some());
+                self.continue_fetch(peer, call);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Rpc(rpc::Event::WriteDropped(peer, call))) => {
+                log::warn!("response {call:?} to {peer} was dropped");
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        peer: PeerId,
+        call: rpc::CallId,
+        prefix: u8,
+        mut body: &[u8],
+    ) -> anyhow::Result<()> {
+        let response = match prefix {
+            StorePieces::PREFIX => {
+                let (ticket, block, start, Reminder(data)) =
+                    <StorePieces as Protocol>::Request::decode(&mut body)
+                        .context("invalid request")?;
+                let res = match self.check_ticket(&ticket, TicketKind::Upload, peer) {
+                    Ok(()) => match ticket.pieces(block) {
+                        Some(covers) => self
+                            .store
+                            .write(block, covers, start, data)
+                            .unwrap_or_else(|e| internal_error(e, StorePiecesError::Internal)),
+                        None => Err(StorePiecesError::InvalidTicket(TicketError::NotCovered)),
+                    },
+                    Err(e) => Err(StorePiecesError::InvalidTicket(e)),
+                };
+                res.to_bytes()
+            }
+            FetchPieces::PREFIX => {
+                let (_, block, pieces) = <FetchPieces as Protocol>::Request::decode(&mut body)
+                    .context("invalid request")?;
+                self.fetch_pieces(peer, call, block, pieces);
+                return Ok(());
+            }
+            FreePieces::PREFIX => {
+                let ticket = <FreePieces as Protocol>::Request::decode(&mut body)
+                    .context("invalid request")?;
+                free_pieces(&mut self.store, &ticket, peer, &self.satelite_sign, unix_now())
+                    .unwrap_or_else(|e| internal_error(e, FreePiecesError::Internal))
+                    .to_bytes()
+            }
+            _ => {
+                log::info!("rejecting unsupported request {prefix} from {peer}");
+                Rejection::Unsupported.to_bytes()
+            }
+        };
+
+        self.swarm.behaviour_mut().rpc.respond(peer, call, response);
+        Ok(())
+    }
+
+    fn check_ticket(
+        &self,
+        ticket: &AllocationTicket,
+        kind: TicketKind,
+        peer: PeerId,
+    ) -> Result<(), TicketError> {
+        check_ticket(ticket, kind, peer, &self.satelite_sign, unix_now())
+    }
+
+    fn fetch_pieces(
+        &mut self,
+        peer: PeerId,
+        call: rpc::CallId,
+        block: BlockId,
+        mut pieces: Range<PieceIndex>,
+    ) {
+        if let Err(e) = self.store.readable(block, &pieces) {
+            let rpc = &mut self.swarm.behaviour_mut().rpc;
+            rpc.respond(peer, call, Err::<Reminder, _>(e).to_bytes());
+            return;
+        }
+
+        let chunk = self.next_chunk(block, &mut pieces);
+        self.swarm.behaviour_mut().rpc.respond_chunk(peer, call, chunk);
+        self.fetches.push((peer, call, block, pieces));
+        self.continue_fetch(peer, call);
+    }
+
+    /// Reads further chunks only while none wait in the rpc queue, so that the pieces held in
+    /// memory are bounded by what the caller has credit for.
+    fn continue_fetch(&mut self, peer: PeerId, call: rpc::CallId) {
+        let Some(index) = self.fetches.iter().position(|&(p, c, ..)| p == peer && c == call) else {
+            return;
+        };
+        let (peer, call, block, mut pieces) = self.fetches.swap_remove(index);
+
+        while !pieces.is_empty() && self.swarm.behaviour().rpc.queued_chunks(peer, call) == Some(0)
+        {
+            let chunk = self.next_chunk(block, &mut pieces);
+            self.swarm.behaviour_mut().rpc.respond_chunk(peer, call, chunk);
+        }
+
+        let rpc = &mut self.swarm.behaviour_mut().rpc;
+        match rpc.queued_chunks(peer, call) {
+            None => log::debug!("{peer} stopped fetching {call:?}"),
+            Some(_) if pieces.is_empty() => rpc.finish_response(peer, call),
+            Some(_) => self.fetches.push((peer, call, block, pieces)),
+        }
+    }
+
+    /// Encoded response with the next pieces, a read failure ends the fetch with
+    /// [`FetchPiecesError::Internal`] as its last chunk.
+    fn next_chunk(&mut self, block: BlockId, pieces: &mut Range<PieceIndex>) -> Vec<u8> {
+        let end = pieces.end.min(pieces.start + MAX_TRANSFER_PIECES);
+        self.buffer.clear();
+        let res = match self.store.read(block, pieces.start..end, &mut self.buffer) {
+            Ok(()) => {
+                pieces.start = end;
+                Ok(Reminder(&self.buffer))
+            }
+            Err(e) => {
+                pieces.start = pieces.end;
+                internal_error(e, FetchPiecesError::Internal)
+            }
+        };
+        res.to_bytes()
+    }
+
+    fn report_capacity(&mut self) {
+        if self.pending_report.is_some() {
+            return;
+        }
+
+        let request = (ReportCapacity::PREFIX, self.store.free_blocks()).to_bytes();
+        match self.swarm.behaviour_mut().rpc.request(self.satelite, request) {
+            Ok(call) => self.pending_report = Some(call),
+            Err(e) => log::warn!("failed to report capacity: {e}"),
+        }
+    }
+
+    fn handle_report(&mut self, res: Result<Vec<u8>, Arc<streaming::Error>>) -> anyhow::Result<()> {
+        let body = res?;
+        let store_id = Result::<_, ReportCapacityError>::decode(&mut body.as_slice())
+            .context("invalid response")??;
+        if self.store.store_id() != Some(store_id) {
+            log::info!("registered with the satellite as store {store_id}");
+        }
+        self.store.set_store_id(store_id)?;
+        Ok(())
+    }
+}
+
+/// Checks that our `satelite` issued the ticket for `kind` to `peer` and it is valid at `now`.
+fn check_ticket(
+    ticket: &AllocationTicket,
+    kind: TicketKind,
+    peer: PeerId,
+    satelite: &crypto::Hash,
+    now: u64,
+) -> Result<(), TicketError> {
+    let user = dht::try_peer_id_to_ed(peer).ok_or(TicketError::WrongUser)?;
+    if ticket.verify(kind, user, now)? != *satelite {
+        return Err(TicketError::UnknownSatelite);
+    }
+    Ok(())
+}
+
+/// Frees the ticket ranges of blocks we hold, other blocks belong to other stores. Nothing is
+/// touched unless the ticket is a deletion ticket `peer` got from our `satelite`.
+fn free_pieces(
+    store: &mut Store,
+    ticket: &AllocationTicket,
+    peer: PeerId,
+    satelite: &crypto::Hash,
+    now: u64,
+) -> io::Result<Result<(), FreePiecesError>> {
+    if let Err(e) = check_ticket(ticket, TicketKind::Delete, peer, satelite, now) {
+        return Ok(Err(FreePiecesError::InvalidTicket(e)));
+    }
+
+    let mut found = false;
+    for b in &ticket.ticket.blocks {
+        match store.free(b.block, b.pieces.clone())? {
+            Ok(()) => found = true,
+            Err(FreePiecesError::NotFound) => {}
+            Err(e) => return Ok(Err(e)),
+        }
+    }
+
+    Ok(if found { Ok(()) } else { Err(FreePiecesError::NotFound) })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Local failures are logged here, the requester only learns that the store is at fault.
+fn internal_error<T, E>(err: io::Error, res: E) -> Result<T, E> {
+    log::error!("storage failure: {err}");
+    Err(res)
+}
+
+#[derive(NetworkBehaviour)]
+pub struct Behaviour {
+    dht: dht::Behaviour,
+    rpc: rpc::Behaviour,
+    streaming: streaming::Behaviour,
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crypto::sign,
+        rand_core::OsRng,
+        std::fs,
+        storage_spec::{
+            ticket::{Ticket, TicketBlock},
+            PIECE_SIZE,
+        },
+    };
+
+    const EXPIRY: u64 = 1000;
+
+    fn user(seed: u8) -> (PeerId, sign::Ed) {
+        let key = ed25519::Keypair::from(ed25519::SecretKey::try_from_bytes([seed; 32]).unwrap());
+        let public = key.public();
+        (libp2p::identity::PublicKey::from(public.clone()).to_peer_id(), public.to_bytes())
+    }
+
+    fn ticket(satelite: &sign::Keypair, kind: TicketKind, user: sign::Ed) -> AllocationTicket {
+        let ticket = Ticket {
+            kind,
+            file: [1; 32],
+            blocks: vec![TicketBlock { block: 2, pieces: 0..1 }],
+            expiry: EXPIRY,
+            user,
+        };
+        AllocationTicket::new(satelite, ticket, OsRng)
+    }
+
+    #[test]
+    fn deletion_needs_a_valid_ticket() {
+        let root = std::env::temp_dir().join(format!("orion-storage-node-{}", std::process::id()));
+        _ = fs::remove_dir_all(&root);
+        let mut store = Store::open(root.clone(), 1).unwrap();
+        store.set_store_id(0).unwrap();
+        store.write(2, 0..1, 0, &[0; PIECE_SIZE]).unwrap().unwrap();
+
+        let satelite = sign::Keypair::new(OsRng);
+        let ours = crypto::hash::new(&satelite.public_key());
+        let (peer, key) = user(7);
+        let (stranger, _) = user(8);
+        let mut free = |ticket: &AllocationTicket, peer, now| {
+            free_pieces(&mut store, ticket, peer, &ours, now).unwrap()
+        };
+
+        let upload = ticket(&satelite, TicketKind::Upload, key);
+        let foreign = ticket(&sign::Keypair::new(OsRng), TicketKind::Delete, key);
+        let delete = ticket(&satelite, TicketKind::Delete, key);
+        let rejected = |e| Err(FreePiecesError::InvalidTicket(e));
+        assert_eq!(free(&upload, peer, 0), rejected(TicketError::WrongKind));
+        assert_eq!(free(&foreign, peer, 0), rejected(TicketError::UnknownSatelite));
+        assert_eq!(free(&delete, stranger, 0), rejected(TicketError::WrongUser));
+        assert_eq!(free(&delete, peer, EXPIRY + 1), rejected(TicketError::Expired));
+        assert_eq!(store.readable(2, &(0..1)), Ok(()));
+
+        free_pieces(&mut store, &delete, peer, &ours, 0).unwrap().unwrap();
+        assert_eq!(store.readable(2, &(0..1)), Err(FetchPiecesError::NotFound));
+
+        fs::remove_dir_all(root).unwrap();
+    }
 }
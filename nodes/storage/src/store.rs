@@ -0,0 +1,298 @@
+use {
+    component_utils::Codec,
+    std::{fs, io, ops::Range, os::unix::fs::FileExt, path::PathBuf},
+    storage_spec::{
+        metabase::{BlockId, StoreId},
+        protocol::{
+            FetchPiecesError, FreePiecesError, PieceIndex, StorePiecesError, MAX_TRANSFER_PIECES,
+        },
+        BLOCK_PIECES, PIECE_SIZE,
+    },
+};
+
+const STATE_FILE_NAME: &str = "state";
+const STATE_TMP_FILE_NAME: &str = "state.tmp";
+
+/// Pieces moved at once while swap removing.
+const MOVE_BATCH: PieceIndex = 64;
+
+/// Everything besides the pieces, rewritten after each change so the store survives restarts.
+#[derive(Codec, Default)]
+struct State {
+    store_id: Option<StoreId>,
+    /// Sorted by the block id, the second element is the number of pieces in use.
+    blocks: Vec<(BlockId, PieceIndex)>,
+}
+
+/// Block fragments on disk, one file per `(store id, block id)` with pieces laid out at
+/// `index * PIECE_SIZE`. Outer error of the methods is a local failure, the inner one is
+/// meant for the requester.
+pub struct Store {
+    root: PathBuf,
+    capacity: u32,
+    state: State,
+}
+
+impl Store {
+    pub fn open(root: PathBuf, capacity: u32) -> io::Result<Self> {
+        fs::create_dir_all(&root)?;
+        let state = match fs::read(root.join(STATE_FILE_NAME)) {
+            Ok(bytes) => State::decode(&mut bytes.as_slice()).ok_or(io::ErrorKind::InvalidData)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { root, capacity, state })
+    }
+
+    pub fn store_id(&self) -> Option<StoreId> {
+        self.state.store_id
+    }
+
+    /// Sets the id the satellite knows us under. Blocks kept under a different id are no
+    /// longer tracked by the satellite so they are dropped.
+    pub fn set_store_id(&mut self, id: StoreId) -> io::Result<()> {
+        match self.state.store_id {
+            Some(prev) if prev == id => return Ok(()),
+            Some(prev) => {
+                log::warn!("satellite changed our store id from {prev} to {id}, dropping blocks");
+                match fs::remove_dir_all(self.root.join(prev.to_string())) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+                self.state.blocks.clear();
+            }
+            None => {}
+        }
+
+        self.state.store_id = Some(id);
+        fs::create_dir_all(self.root.join(id.to_string()))?;
+        self.save()
+    }
+
+    /// Block fragments the store can still take.
+    pub fn free_blocks(&self) -> u32 {
+        self.capacity.saturating_sub(self.state.blocks.len() as u32)
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.state.blocks.len()
+    }
+
+    /// Writes the pieces if they fit the range the ticket `covers`. Blocks grow on first
+    /// upload to the range, since the satellite always allocates at their end.
+    pub fn write(
+        &mut self,
+        block: BlockId,
+        covers: Range<PieceIndex>,
+        start: PieceIndex,
+        data: &[u8],
+    ) -> io::Result<Result<(), StorePiecesError>> {
+        if data.is_empty() || data.len() % PIECE_SIZE != 0 {
+            return Ok(Err(StorePiecesError::PartialPiece));
+        }
+
+        let Some(store_id) = self.state.store_id else {
+            return Ok(Err(StorePiecesError::NotAllocated));
+        };
+
+        let count = (data.len() / PIECE_SIZE) as PieceIndex;
+        if count > MAX_TRANSFER_PIECES
+            || covers.end > BLOCK_PIECES as PieceIndex
+            || start < covers.start
+            || start.checked_add(count).map_or(true, |end| end > covers.end)
+        {
+            return Ok(Err(StorePiecesError::OutOfBounds));
+        }
+
+        let search = self.state.blocks.binary_search_by_key(&block, |&(b, _)| b);
+        let len = search.map_or(0, |i| self.state.blocks[i].1);
+        // gap would mean the ticket is older than a deletion that shrank the block
+        if covers.start > len {
+            return Ok(Err(StorePiecesError::NotAllocated));
+        }
+
+        let path = self.block_path(store_id, block);
+        if covers.end > len {
+            let index = match search {
+                Ok(i) => i,
+                Err(_) if self.free_blocks() == 0 => return Ok(Err(StorePiecesError::Full)),
+                Err(i) => {
+                    self.state.blocks.insert(i, (block, 0));
+                    i
+                }
+            };
+
+            // sparse file, so that reads of pieces not yet uploaded do not fail
+            let file =
+                fs::OpenOptions::new().write(true).create(true).truncate(false).open(&path)?;
+            file.set_len(piece_offset(covers.end))?;
+            self.state.blocks[index].1 = covers.end;
+            self.save()?;
+        }
+
+        let file = fs::OpenOptions::new().write(true).open(path)?;
+        file.write_all_at(data, piece_offset(start))?;
+        Ok(Ok(()))
+    }
+
+    pub fn readable(
+        &self,
+        block: BlockId,
+        pieces: &Range<PieceIndex>,
+    ) -> Result<(), FetchPiecesError> {
+        let (Some(_), Some(len)) = (self.state.store_id, self.len(block)) else {
+            return Err(FetchPiecesError::NotFound);
+        };
+
+        if pieces.start >= pieces.end || pieces.end > len {
+            return Err(FetchPiecesError::OutOfBounds);
+        }
+
+        Ok(())
+    }
+
+    /// Appends the pieces to `buffer`, the range has to be [`Self::readable`].
+    pub fn read(
+        &self,
+        block: BlockId,
+        pieces: Range<PieceIndex>,
+        buffer: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let store_id = self.state.store_id.ok_or(io::ErrorKind::NotFound)?;
+        let file = fs::File::open(self.block_path(store_id, block))?;
+        let prev_len = buffer.len();
+        buffer.resize(prev_len + piece_offset(pieces.len() as PieceIndex) as usize, 0);
+        file.read_exact_at(&mut buffer[prev_len..], piece_offset(pieces.start))
+    }
+
+    /// Swap removes the pieces, the tail of the block is moved into the gap the same way the
+    /// satellite expects. Block is dropped once empty.
+    pub fn free(
+        &mut self,
+        block: BlockId,
+        pieces: Range<PieceIndex>,
+    ) -> io::Result<Result<(), FreePiecesError>> {
+        let (Some(store_id), Ok(index)) =
+            (self.state.store_id, self.state.blocks.binary_search_by_key(&block, |&(b, _)| b))
+        else {
+            return Ok(Err(FreePiecesError::NotFound));
+        };
+
+        let len = self.state.blocks[index].1;
+        if pieces.start >= pieces.end || pieces.end > len {
+            return Ok(Err(FreePiecesError::OutOfBounds));
+        }
+
+        let path = self.block_path(store_id, block);
+        let new_len = len - pieces.len() as PieceIndex;
+        if new_len == 0 {
+            fs::remove_file(path)?;
+            self.state.blocks.remove(index);
+            self.save()?;
+            return Ok(Ok(()));
+        }
+
+        let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let mut buffer = vec![];
+        let mut src = pieces.end.max(new_len);
+        let mut dest = pieces.start;
+        while src < len {
+            let batch = MOVE_BATCH.min(len - src);
+            buffer.resize(piece_offset(batch) as usize, 0);
+            file.read_exact_at(&mut buffer, piece_offset(src))?;
+            file.write_all_at(&buffer, piece_offset(dest))?;
+            src += batch;
+            dest += batch;
+        }
+        file.set_len(piece_offset(new_len))?;
+
+        self.state.blocks[index].1 = new_len;
+        self.save()?;
+        Ok(Ok(()))
+    }
+
+    fn len(&self, block: BlockId) -> Option<PieceIndex> {
+        let index = self.state.blocks.binary_search_by_key(&block, |&(b, _)| b).ok()?;
+        Some(self.state.blocks[index].1)
+    }
+
+    fn block_path(&self, store_id: StoreId, block: BlockId) -> PathBuf {
+        self.root.join(store_id.to_string()).join(block.to_string())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        // rename is atomic, a crash never leaves half written state behind
+        let tmp = self.root.join(STATE_TMP_FILE_NAME);
+        fs::write(&tmp, self.state.to_bytes())?;
+        fs::rename(tmp, self.root.join(STATE_FILE_NAME))
+    }
+}
+
+fn piece_offset(index: PieceIndex) -> u64 {
+    u64::from(index) * PIECE_SIZE as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piece(value: u8) -> [u8; PIECE_SIZE] {
+        [value; PIECE_SIZE]
+    }
+
+    #[test]
+    fn writes_survive_restart() {
+        let root = std::env::temp_dir().join(format!("orion-storage-{}", std::process::id()));
+        _ = fs::remove_dir_all(&root);
+
+        let mut store = Store::open(root.clone(), 1).unwrap();
+        let data = (1..=5).flat_map(piece).collect::<Vec<_>>();
+        assert_eq!(store.write(0, 0..5, 0, &data).unwrap(), Err(StorePiecesError::NotAllocated));
+        store.set_store_id(3).unwrap();
+        store.write(0, 0..5, 0, &data).unwrap().unwrap();
+        assert_eq!(
+            store.write(1, 0..1, 0, &data[..PIECE_SIZE]).unwrap(),
+            Err(StorePiecesError::Full)
+        );
+        assert_eq!(
+            store.write(0, 6..7, 6, &data[..PIECE_SIZE]).unwrap(),
+            Err(StorePiecesError::NotAllocated)
+        );
+        drop(store);
+
+        let store = Store::open(root.clone(), 1).unwrap();
+        let mut buffer = vec![];
+        store.read(0, 1..4, &mut buffer).unwrap();
+        assert_eq!(buffer, [2, 3, 4].into_iter().flat_map(piece).collect::<Vec<_>>());
+        assert_eq!(store.readable(0, &(4..6)), Err(FetchPiecesError::OutOfBounds));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn swap_remove_survives_restart() {
+        let root = std::env::temp_dir().join(format!("orion-storage-free-{}", std::process::id()));
+        _ = fs::remove_dir_all(&root);
+
+        let mut store = Store::open(root.clone(), 2).unwrap();
+        store.set_store_id(3).unwrap();
+        let data = (1..=5).flat_map(piece).collect::<Vec<_>>();
+        store.write(0, 0..5, 0, &data).unwrap().unwrap();
+        store.write(1, 0..1, 0, &data[..PIECE_SIZE]).unwrap().unwrap();
+        assert_eq!(store.free(0, 4..6).unwrap(), Err(FreePiecesError::OutOfBounds));
+        assert_eq!(store.free(2, 0..1).unwrap(), Err(FreePiecesError::NotFound));
+        store.free(0, 0..2).unwrap().unwrap();
+        store.free(1, 0..1).unwrap().unwrap();
+        drop(store);
+
+        let store = Store::open(root.clone(), 2).unwrap();
+        let mut buffer = vec![];
+        store.read(0, 0..3, &mut buffer).unwrap();
+        assert_eq!(buffer, [4, 5, 3].into_iter().flat_map(piece).collect::<Vec<_>>());
+        assert_eq!(store.readable(0, &(2..4)), Err(FetchPiecesError::OutOfBounds));
+        assert_eq!(store.readable(1, &(0..1)), Err(FetchPiecesError::NotFound));
+        assert_eq!(store.free_blocks(), 1);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -402,15 +402,20 @@ async fn main() {
         libp2p::swarm::Config::with_wasm_executor(),
     );
 
-    fn unpack_node_addr(addr: chain_api::NodeAddress) -> Multiaddr {
-        let (addr, port) = addr.into();
-        Multiaddr::empty()
+    /// Only websocket endpoints are usable from the browser.
+    fn unpack_endpoint(endpoint: chain_api::NodeEndpoint) -> Option<Multiaddr> {
+        let chain_api::NodeTransport::Ws = endpoint.transport else {
+            return None;
+        };
+        let (addr, port) = endpoint.addr.into();
+        let addr = Multiaddr::empty()
             .with(match addr {
                 IpAddr::V4(ip) => multiaddr::Protocol::Ip4(ip),
                 IpAddr::V6(ip) => multiaddr::Protocol::Ip6(ip),
             })
-            .with(multiaddr::Protocol::Tcp(port + 100))
-            .with(multiaddr::Protocol::Ws("/".into()))
+            .with(multiaddr::Protocol::Tcp(port))
+            .with(multiaddr::Protocol::Ws("/".into()));
+        Some(addr)
     }
 
     spawn_local(async move {
@@ -420,9 +425,9 @@ async fn main() {
             .list(node_contract())
             .await
             .unwrap();
-        for (node, ip) in nodes {
+        for (node, endpoints, _) in nodes {
             let id = libp2p::identity::ed25519::PublicKey::try_from_bytes(&node.id).unwrap();
-            let route = Route::new(id, unpack_node_addr(ip));
+            let route = Route::with_addrs(id, endpoints.into_iter().filter_map(unpack_endpoint));
             let peer_id = route.peer_id();
             swarm.behaviour_mut().dht.table.insert(route);
             swarm.behaviour_mut().collector.world_mut().0.borrow_mut().servers.insert(peer_id);
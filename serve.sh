@@ -10,10 +10,24 @@ creq subxt
 sod() { export "$1"="${!1:-$2}"; }
 
 sod CHAIN_NODE "ws://localhost:9944"
+sod CHAIN_NODES "$CHAIN_NODE"
 sod NODE_CONTRACT "todo"
 sod USER_CONTRACT "todo"
 sod NODE_COUNT 15
 sod IDLE_TIMEOUT 2000
+sod SHUTDOWN_TIMEOUT 5000
+sod ONION_MAX_STREAMS 10
+sod ONION_KEEP_ALIVE_INTERVAL 100000
+sod RPC_SIGN false
+sod RPC_REQUIRE_SIGNED false
+sod DHT_PING_INTERVAL 10000
+sod DHT_SUSPECT_GRACE 30000
+sod DHT_LOOKUPS false
+sod RECLAIM_ON_EXIT false
+sod PROFILE_POW_DIFFICULTY 0
+sod CHAT_POW_DIFFICULTY 0
+sod PATH_RATE_LIMIT 0
+sod IDENTITY_RATE_LIMIT 0
 sod FRONTEND_PORT 7777
 sod TOPOLOGY_PORT 8888
 sod RUST_LOG "info"
@@ -21,6 +35,12 @@ sod RUST_BACKTRACE 1
 sod NODE_START 8800
 sod NETWORK_BOOT_NODE "/ip4/127.0.0.1/tcp/$((NODE_START + 100))/ws"
 sod MIN_NODES 5
+sod CLIENT_BOOT_NODES ""
+sod REPLICATION_FACTOR 4
+sod BLOCK_SIZE 32768
+sod BLOCK_HISTORY 32
+sod MAX_MESSAGE_SIZE 1024
+sod VIRTUAL_NODES 0
 sod BALANCE 10000000000000
 sod TEST_WALLETS 5CwfgYUrq24dTpfh2sQ2st1FNCR2fM2JFSn3EtdWyrGdEaER,5E7YrzVdg1ovRYfWLQG1bJV7FvZWJpnVnQ3nVCKEwpFzkX8s
 sod EXPOSED_ADDRESS 127.0.0.1
@@ -60,8 +80,12 @@ subxt metadata > core/chain-types/metadata.scale
 rebuild_workspace
 
 # setup chain
+NETWORK_PARAMS="{ replication_factor: $REPLICATION_FACTOR, block_size: $BLOCK_SIZE,\
+  block_history: $BLOCK_HISTORY, max_message_size: $MAX_MESSAGE_SIZE,\
+  virtual_nodes: $VIRTUAL_NODES }"
 export NODE_CONTRACT=$(cd contracts/node_staker &&\
-  cargo contract instantiate --suri //Charlie -x --skip-confirm --output-json | jq -r '.contract')
+  cargo contract instantiate --suri //Charlie -x --skip-confirm --output-json\
+  --constructor with_params --args "$NETWORK_PARAMS" | jq -r '.contract')
 export USER_CONTRACT=$(cd contracts/user_manager &&\
   cargo contract instantiate --suri //Charlie -x --skip-confirm --output-json | jq -r '.contract')
 echo "node contract: $NODE_CONTRACT"
@@ -32,6 +32,8 @@ fn main() {
             let child = command
                 .env("PORT", (cmd.first_port + i as u16).to_string())
                 .env("WS_PORT", (cmd.first_port + i as u16 + 100).to_string())
+                .env("METRICS_PORT", (cmd.first_port + i as u16 + 200).to_string())
+                .env("ADMIN_SOCKET", format!("node_keys/node{i}.sock"))
                 .env("BOOT_NODES", boot_nodes)
                 .env("NODE_ACCOUNT", "//Alice")
                 .env("KEY_PATH", format!("node_keys/node{i}.keys"))
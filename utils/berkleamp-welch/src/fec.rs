@@ -497,6 +497,18 @@ pub struct Share<'a> {
     data: &'a mut [u8],
 }
 
+impl<'a> Share<'a> {
+    /// `number` is the position of the share in the encoded output, data shares come first.
+    pub fn new(number: usize, data: &'a mut [u8]) -> Self {
+        Self { number, data }
+    }
+
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1,48 +1,359 @@
 #![feature(array_chunks)]
-#![feature(let_chains)]
-use std::str::FromStr;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 
 #[macro_export]
 macro_rules! env_config {
     (
-       struct $name:ident {$(
-            $field:ident: $ty:ty $(= $default:expr)?,
-       )*}
+        $(#[doc = $doc:literal])*
+        struct $name:ident { $($fields:tt)* }
     ) => {
+        $crate::env_config!(@fields [$(#[doc = $doc])* $name] [] $($fields)*);
+    };
+
+    // fields are normalized one by one, matching `#[section]` and doc comments in a single
+    // pattern is ambiguous
+    (
+        @fields $head:tt [$($parsed:tt)*]
+        $(#[doc = $doc:literal])* #[section] $field:ident: $ty:ty, $($rest:tt)*
+    ) => {
+        $crate::env_config!(
+            @fields $head [$($parsed)* [[$(#[doc = $doc])*] $field $ty [section] []]] $($rest)*
+        );
+    };
+    (
+        @fields $head:tt [$($parsed:tt)*]
+        $(#[doc = $doc:literal])* $field:ident: $ty:ty $(= $default:expr)?, $($rest:tt)*
+    ) => {
+        $crate::env_config!(
+            @fields $head [$($parsed)* [[$(#[doc = $doc])*] $field $ty [] [$($default)?]]]
+            $($rest)*
+        );
+    };
+    (
+        @fields [$(#[doc = $doc:literal])* $name:ident] [$([
+            [$(#[doc = $field_doc:literal])*] $field:ident $ty:ty
+            [$($section:ident)?] [$($default:expr)?]
+        ])*]
+    ) => {
+        $(#[doc = $doc])*
         pub struct $name {$(
+            $(#[doc = $field_doc])*
             pub $field: $ty,
         )*}
 
         impl $name {
+            /// Loads the config from command line, environment and config file, in that order
+            /// of precedence.
+            pub fn load() -> Result<Self, $crate::Errors> {
+                let sources = $crate::Sources::from_process()?;
+                let mut errors = $crate::Errors::default();
+                let config = Self::from_sources(&sources, "", &mut errors);
+                errors.into_result(config)
+            }
+
             pub fn from_env() -> Self {
-                $name {$(
-                    $field: $crate::get_env(stringify!($field), $crate::env_config!(@default $($default)?)),
-                )*}
+                Self::load().unwrap_or_else(|e| panic!("{e}"))
+            }
+
+            pub fn from_sources(
+                sources: &$crate::Sources,
+                prefix: &str,
+                errors: &mut $crate::Errors,
+            ) -> Option<Self> {
+                $(
+                    let $field = $crate::env_config!(
+                        @field sources, errors, prefix, $field, $ty, [$($section)?], [$($default)?]
+                    );
+                )*
+                Some($name {$(
+                    $field: $field?,
+                )*})
             }
         }
     };
 
+    (@field $sources:ident, $errors:ident, $prefix:ident, $field:ident, $ty:ty, [section], []) => {
+        <$ty>::from_sources($sources, &$crate::join_path($prefix, stringify!($field)), $errors)
+    };
+    (
+        @field $sources:ident, $errors:ident, $prefix:ident, $field:ident, $ty:ty,
+        [], [$($default:expr)?]
+    ) => {
+        $sources.get::<$ty>(
+            &$crate::join_path($prefix, stringify!($field)),
+            $crate::env_config!(@default $($default)?),
+            $errors,
+        )
+    };
+
     (@default $value:expr) => { Some($value) };
     (@default ) => { None };
 }
 
 #[must_use]
-pub fn get_env<T: std::str::FromStr>(key: &str, default: Option<&str>) -> T
-where
-    T::Err: std::fmt::Display,
-{
-    let key = key.to_uppercase();
-    if cfg!(debug_assertions)
-        && let Some(default) = default
-    {
-        std::env::var(&key).unwrap_or_else(|_| default.to_string())
+pub fn join_path(prefix: &str, field: &str) -> String {
+    if prefix.is_empty() {
+        field.to_string()
     } else {
-        std::env::var(&key).unwrap_or_else(|_| panic!("{key} is not set"))
+        format!("{prefix}.{field}")
+    }
+}
+
+fn env_name(path: &str) -> String {
+    path.replace('.', "_").to_uppercase()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Args,
+    Env,
+    File,
+    Default,
+}
+
+impl std::fmt::Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Args => "command line",
+            Self::Env => "environment",
+            Self::File => "config file",
+            Self::Default => "default",
+        })
+    }
+}
+
+/// Raw values keyed by dotted field path (`onion.max_streams`). Env variables are looked up
+/// by the path with dots replaced by underscores and uppercased (`ONION_MAX_STREAMS`).
+#[derive(Default)]
+pub struct Sources {
+    args: HashMap<String, String>,
+    /// Arguments given as `--key value`, the value may as well be a positional argument
+    /// following a switch.
+    switches: HashSet<String>,
+    env: HashMap<String, String>,
+    file: HashMap<String, String>,
+}
+
+impl Sources {
+    /// Config file is taken from `--config` argument or `CONFIG` env variable, if any.
+    pub fn from_process() -> Result<Self, Errors> {
+        let env = std::env::vars().collect::<HashMap<_, _>>();
+        let mut errors = Errors::default();
+        let (args, switches) = parse_args(std::env::args().skip(1), &mut errors);
+
+        let file = match args.get("config").or_else(|| env.get("CONFIG")) {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(content) => parse_file(&content, &mut errors),
+                Err(e) => {
+                    errors.push("config", ErrorKind::File(format!("reading {path}: {e}")));
+                    HashMap::new()
+                }
+            },
+            None => HashMap::new(),
+        };
+
+        errors.into_result(Some(Self { args, switches, env, file }))
+    }
+
+    pub fn from_parts(
+        args: impl IntoIterator<Item = String>,
+        env: HashMap<String, String>,
+        file: &str,
+    ) -> Result<Self, Errors> {
+        let mut errors = Errors::default();
+        let (args, switches) = parse_args(args, &mut errors);
+        let file = parse_file(file, &mut errors);
+        errors.into_result(Some(Self { args, switches, env, file }))
+    }
+
+    fn lookup<'a>(&'a self, path: &str, default: Option<&'a str>) -> Option<(Layer, &'a str)> {
+        let env = self.env.get(&env_name(path));
+        self.args
+            .get(path)
+            .map(|v| (Layer::Args, v.as_str()))
+            .or_else(|| env.map(|v| (Layer::Env, v.as_str())))
+            .or_else(|| self.file.get(path).map(|v| (Layer::File, v.as_str())))
+            .or_else(|| default.map(|v| (Layer::Default, v)))
+    }
+
+    pub fn get<T: FromStr>(
+        &self,
+        path: &str,
+        default: Option<&str>,
+        errors: &mut Errors,
+    ) -> Option<T>
+    where
+        T::Err: std::fmt::Display,
+    {
+        let Some((layer, value)) = self.lookup(path, default) else {
+            errors.push(path, ErrorKind::Missing);
+            return None;
+        };
+
+        value
+            .parse()
+            .or_else(|e| {
+                // `--switch positional` took the positional as its value
+                if layer == Layer::Args && self.switches.contains(path) {
+                    "true".parse().map_err(|_| e)
+                } else {
+                    Err(e)
+                }
+            })
+            .map_err(|e| {
+                let message = format!("not valid {}: {e:#}", std::any::type_name::<T>());
+                errors.push(path, ErrorKind::Invalid { layer, message });
+            })
+            .ok()
+    }
+}
+
+/// Accepts `--key value` and `--key=value`, dashes in keys are treated as underscores. A
+/// `--key` not followed by a value is a switch set to `true`, when the value does not parse
+/// it is taken for a positional following the switch. Positional arguments and everything
+/// after `--` are not ours, they are skipped.
+fn parse_args(
+    args: impl IntoIterator<Item = String>,
+    errors: &mut Errors,
+) -> (HashMap<String, String>, HashSet<String>) {
+    let mut parsed = HashMap::new();
+    let mut switches = HashSet::new();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+
+        let Some(key) = arg.strip_prefix("--") else {
+            continue;
+        };
+
+        let (key, value, switch) = match key.split_once('=') {
+            Some((key, value)) => (key, value.to_string(), false),
+            None => match args.next_if(|value| !value.starts_with("--")) {
+                Some(value) => (key, value, true),
+                None => (key, "true".to_string(), false),
+            },
+        };
+
+        if key.is_empty() {
+            errors.push(&arg, ErrorKind::Argument("expected `--key value`".into()));
+            continue;
+        }
+
+        let key = key.replace('-', "_");
+        if switch {
+            switches.insert(key.clone());
+        } else {
+            switches.remove(&key);
+        }
+        parsed.insert(key, value);
+    }
+    (parsed, switches)
+}
+
+fn parse_file(content: &str, errors: &mut Errors) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    match content.parse::<toml::Table>() {
+        Ok(table) => flatten_table("", table, &mut parsed),
+        Err(e) => errors.push("config", ErrorKind::File(e.to_string())),
+    }
+    parsed
+}
+
+fn flatten_table(prefix: &str, table: toml::Table, into: &mut HashMap<String, String>) {
+    for (key, value) in table {
+        let path = join_path(prefix, &key);
+        match value {
+            toml::Value::Table(table) => flatten_table(&path, table, into),
+            value => _ = into.insert(path, value_to_string(value)),
+        }
+    }
+}
+
+/// Arrays turn into comma separated values so that they parse into [`List`].
+fn value_to_string(value: toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s,
+        toml::Value::Array(values) => {
+            values.into_iter().map(value_to_string).collect::<Vec<_>>().join(",")
+        }
+        other => other.to_string(),
+    }
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Missing,
+    Invalid { layer: Layer, message: String },
+    Argument(String),
+    File(String),
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub path: String,
+    pub kind: ErrorKind,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = &self.path;
+        match &self.kind {
+            ErrorKind::Missing => write!(
+                f,
+                "{path} is not set (use --{path}, {} or `{path}` in the config file)",
+                env_name(path)
+            ),
+            ErrorKind::Invalid { layer, message } => {
+                write!(f, "{path} (from {layer}) is {message}")
+            }
+            ErrorKind::Argument(message) => write!(f, "argument {path}: {message}"),
+            ErrorKind::File(message) => write!(f, "config file: {message}"),
+        }
     }
-    .parse()
-    .unwrap_or_else(|e| panic!("{key} is not valid {}: {e:#}", std::any::type_name::<T>()))
 }
 
+/// Everything that went wrong while loading, so that it can be fixed in one go.
+#[derive(Debug, Default)]
+pub struct Errors(pub Vec<Error>);
+
+impl Errors {
+    pub fn push(&mut self, path: &str, kind: ErrorKind) {
+        self.0.push(Error { path: path.to_string(), kind });
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// # Panics
+    ///
+    /// When `value` is missing but no error was recorded.
+    pub fn into_result<T>(self, value: Option<T>) -> Result<T, Self> {
+        match value {
+            Some(value) if self.is_empty() => Ok(value),
+            _ => {
+                assert!(!self.is_empty(), "value is missing but no error was recorded");
+                Err(self)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Errors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid configuration:")?;
+        self.0.iter().try_for_each(|e| write!(f, "\n  {e}"))
+    }
+}
+
+impl std::error::Error for Errors {}
+
 pub struct List<T>(pub Vec<T>);
 
 impl<T: FromStr> FromStr for List<T> {
@@ -50,7 +361,7 @@ impl<T: FromStr> FromStr for List<T> {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut peers = Vec::new();
-        for peer in s.split(',') {
+        for peer in s.split(',').filter(|p| !p.is_empty()) {
             peers.push(peer.parse::<T>()?);
         }
         Ok(Self(peers))
@@ -129,3 +440,76 @@ impl std::fmt::Display for SecretKeyError {
 }
 
 impl std::error::Error for SecretKeyError {}
+
+#[cfg(test)]
+// configs here are loaded from parts, not from the process
+#[allow(dead_code)]
+mod test {
+    crate::env_config! {
+        /// Nested section.
+        struct Inner {
+            /// Documented field.
+            limit: u32,
+        }
+    }
+
+    crate::env_config! {
+        struct Outer {
+            port: u16,
+            peers: crate::List<u8> = "1",
+            verbose: bool = "false",
+            /// Documented section.
+            #[section]
+            inner: Inner,
+        }
+    }
+
+    fn load(args: &[&str], env: &[(&str, &str)], file: &str) -> Result<Outer, crate::Errors> {
+        let args = args.iter().map(|s| s.to_string());
+        let env = env.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        let sources = crate::Sources::from_parts(args, env, file)?;
+        let mut errors = crate::Errors::default();
+        let config = Outer::from_sources(&sources, "", &mut errors);
+        errors.into_result(config)
+    }
+
+    #[test]
+    fn precedence() {
+        let file = "port = 1\npeers = [2, 3]\n[inner]\nlimit = 4";
+        let config = load(&[], &[], file).unwrap();
+        assert_eq!((config.port, config.peers.0, config.inner.limit), (1, vec![2, 3], 4));
+
+        let config = load(&[], &[("PORT", "5"), ("INNER_LIMIT", "6")], file).unwrap();
+        assert_eq!((config.port, config.inner.limit), (5, 6));
+
+        let args = ["--port", "7", "--inner.limit=8"];
+        let config = load(&args, &[("PORT", "5"), ("INNER_LIMIT", "6")], file).unwrap();
+        assert_eq!((config.port, config.inner.limit), (7, 8));
+    }
+
+    #[test]
+    fn foreign_args_are_skipped() {
+        let args =
+            ["positional", "--port", "7", "-x", "--verbose", "--inner.limit=8", "--", "--port"];
+        let config = load(&args, &[], "").unwrap();
+        assert_eq!((config.port, config.verbose, config.inner.limit), (7, true, 8));
+
+        let config = load(&["--inner.limit", "1", "--port", "2", "--verbose"], &[], "").unwrap();
+        assert!(config.verbose);
+        let args = ["--verbose", "positional", "--port", "2", "--inner.limit", "1"];
+        let config = load(&args, &[], "").unwrap();
+        assert_eq!((config.port, config.verbose, config.peers.0), (2, true, vec![1]));
+        let config = load(&["--verbose", "false", "--port", "2", "--inner.limit", "1"], &[], "");
+        assert!(!config.unwrap().verbose);
+        assert!(load(&["--port", "positional", "--inner.limit", "1"], &[], "").is_err());
+        assert!(load(&["--=1"], &[("PORT", "1"), ("INNER_LIMIT", "1")], "").is_err());
+    }
+
+    #[test]
+    fn all_errors_reported() {
+        let errors = load(&["--port", "nan"], &[], "").err().unwrap();
+        let paths = errors.0.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["port", "inner.limit"]);
+        assert!(load(&[], &[], "port = ").is_err());
+    }
+}